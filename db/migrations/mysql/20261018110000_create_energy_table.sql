@@ -0,0 +1,22 @@
+CREATE TABLE energy (
+  id INT AUTO_INCREMENT PRIMARY KEY,
+  e_pv_total DOUBLE NOT NULL,
+  e_pv_interval DOUBLE NOT NULL,
+  e_inv_total DOUBLE NOT NULL,
+  e_inv_interval DOUBLE NOT NULL,
+  e_rec_total DOUBLE NOT NULL,
+  e_rec_interval DOUBLE NOT NULL,
+  e_chg_total DOUBLE NOT NULL,
+  e_chg_interval DOUBLE NOT NULL,
+  e_dischg_total DOUBLE NOT NULL,
+  e_dischg_interval DOUBLE NOT NULL,
+  e_eps_total DOUBLE NOT NULL,
+  e_eps_interval DOUBLE NOT NULL,
+  e_to_grid_total DOUBLE NOT NULL,
+  e_to_grid_interval DOUBLE NOT NULL,
+  e_to_user_total DOUBLE NOT NULL,
+  e_to_user_interval DOUBLE NOT NULL,
+
+  datalog TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL
+)
@@ -0,0 +1,60 @@
+CREATE TABLE inputs2 (
+  id SERIAL PRIMARY KEY,
+  v_eps_l1 NUMERIC NOT NULL,
+  v_eps_l2 NUMERIC NOT NULL,
+  p_eps_l1 INTEGER NOT NULL,
+  p_eps_l2 INTEGER NOT NULL,
+  s_eps_l1 INTEGER NOT NULL,
+  s_eps_l2 INTEGER NOT NULL,
+  e_eps_l1_day NUMERIC NOT NULL,
+  e_eps_l2_day NUMERIC NOT NULL,
+  e_eps_l1_all NUMERIC NOT NULL,
+  e_eps_l2_all NUMERIC NOT NULL,
+  afci_ch1_current INTEGER NOT NULL,
+  afci_ch2_current INTEGER NOT NULL,
+  afci_ch3_current INTEGER NOT NULL,
+  afci_ch4_current INTEGER NOT NULL,
+  register_144 INTEGER NOT NULL,
+  afci_arc_ch1 INTEGER NOT NULL,
+  afci_arc_ch2 INTEGER NOT NULL,
+  afci_arc_ch3 INTEGER NOT NULL,
+  afci_arc_ch4 INTEGER NOT NULL,
+  afci_max_arc_ch1 INTEGER NOT NULL,
+  afci_max_arc_ch2 INTEGER NOT NULL,
+  afci_max_arc_ch3 INTEGER NOT NULL,
+  afci_max_arc_ch4 INTEGER NOT NULL,
+  p_ac_couple INTEGER NOT NULL,
+  p_load INTEGER NOT NULL,
+  e_load_day NUMERIC NOT NULL,
+  e_load_all NUMERIC NOT NULL,
+  eps_overload_ctrl_time INTEGER NOT NULL,
+  p_inv_s INTEGER NOT NULL,
+  p_inv_t INTEGER NOT NULL,
+  p_rec_s INTEGER NOT NULL,
+  p_rec_t INTEGER NOT NULL,
+  p_to_grid_s INTEGER NOT NULL,
+  p_to_grid_t INTEGER NOT NULL,
+  p_to_user_s INTEGER NOT NULL,
+  p_to_user_t INTEGER NOT NULL,
+  p_gen_s INTEGER NOT NULL,
+  p_gen_t INTEGER NOT NULL,
+  inv_rms_curr_s NUMERIC NOT NULL,
+  inv_rms_curr_t NUMERIC NOT NULL,
+  pf_s NUMERIC NOT NULL,
+  v_grid_l1 NUMERIC NOT NULL,
+  v_grid_l2 NUMERIC NOT NULL,
+  v_gen_l1 NUMERIC NOT NULL,
+  v_gen_l2 NUMERIC NOT NULL,
+  p_inv_l1 INTEGER NOT NULL,
+  p_inv_l2 INTEGER NOT NULL,
+  p_rec_l1 INTEGER NOT NULL,
+  p_rec_l2 INTEGER NOT NULL,
+  p_to_grid_l1 INTEGER NOT NULL,
+  p_to_grid_l2 INTEGER NOT NULL,
+  p_to_user_l1 INTEGER NOT NULL,
+  p_to_user_l2 INTEGER NOT NULL,
+  pf_t NUMERIC NOT NULL,
+
+  datalog TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL
+)
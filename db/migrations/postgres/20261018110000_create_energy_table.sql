@@ -0,0 +1,22 @@
+CREATE TABLE energy (
+  id SERIAL PRIMARY KEY,
+  e_pv_total NUMERIC NOT NULL,
+  e_pv_interval NUMERIC NOT NULL,
+  e_inv_total NUMERIC NOT NULL,
+  e_inv_interval NUMERIC NOT NULL,
+  e_rec_total NUMERIC NOT NULL,
+  e_rec_interval NUMERIC NOT NULL,
+  e_chg_total NUMERIC NOT NULL,
+  e_chg_interval NUMERIC NOT NULL,
+  e_dischg_total NUMERIC NOT NULL,
+  e_dischg_interval NUMERIC NOT NULL,
+  e_eps_total NUMERIC NOT NULL,
+  e_eps_interval NUMERIC NOT NULL,
+  e_to_grid_total NUMERIC NOT NULL,
+  e_to_grid_interval NUMERIC NOT NULL,
+  e_to_user_total NUMERIC NOT NULL,
+  e_to_user_interval NUMERIC NOT NULL,
+
+  datalog TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL
+)
@@ -0,0 +1,11 @@
+CREATE TABLE alarm_events (
+  id INTEGER PRIMARY KEY,
+  kind TEXT NOT NULL,
+  state TEXT NOT NULL,
+  bit INTEGER NOT NULL,
+  code TEXT NOT NULL,
+  description TEXT NOT NULL,
+
+  datalog TEXT NOT NULL,
+  created_at DATETIME NOT NULL
+)
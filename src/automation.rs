@@ -0,0 +1,466 @@
+use crate::prelude::*;
+
+use chrono::NaiveTime;
+use coordinator::metrics::HouseholdMetrics;
+use lxp::packet::{DeviceFunction, ReadInput, TranslatedData};
+use serde::Serialize;
+use std::collections::HashMap;
+
+// the latest value of each input we've seen for an inverter, by name as in
+// inputs/all
+pub type Inputs = serde_json::Map<String, serde_json::Value>;
+
+// inputs not read again within this many intervals are treated as missing,
+// so rules don't act on values from an inverter which has gone quiet
+const STALE_INTERVALS: i64 = 3;
+
+// Condition {{{
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl FromStr for Op {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "<" => Ok(Self::Lt),
+            "<=" => Ok(Self::Le),
+            ">" => Ok(Self::Gt),
+            ">=" => Ok(Self::Ge),
+            "==" | "=" => Ok(Self::Eq),
+            "!=" => Ok(Self::Ne),
+            _ => bail!("unknown comparison '{}'", s),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Condition {
+    Compare { key: String, op: Op, value: f64 },
+    // a window with end before start runs past midnight
+    TimeBetween { start: NaiveTime, end: NaiveTime },
+}
+
+impl Condition {
+    // margin moves the threshold in the condition's favour, so a rule which
+    // has fired needs the value to come back further before it re-arms. None
+    // if we haven't seen the input yet.
+    fn holds(&self, inputs: &Inputs, time: NaiveTime, margin: f64) -> Option<bool> {
+        match self {
+            Self::Compare { key, op, value } => {
+                let actual = inputs.get(key)?.as_f64()?;
+                Some(match op {
+                    Op::Lt => actual < value + margin,
+                    Op::Le => actual <= value + margin,
+                    Op::Gt => actual > value - margin,
+                    Op::Ge => actual >= value - margin,
+                    Op::Eq => actual == *value,
+                    Op::Ne => actual != *value,
+                })
+            }
+            Self::TimeBetween { start, end } => Some(if start <= end {
+                time >= *start && time < *end
+            } else {
+                time >= *start || time < *end
+            }),
+        }
+    }
+}
+
+// Parses conditions joined by "and", each either "{input} {op} {number}" or
+// "time between HH:MM and HH:MM".
+pub fn parse_conditions(s: &str) -> Result<Vec<Condition>> {
+    let tokens = tokenize(s);
+    let mut tokens = tokens.iter().map(String::as_str);
+
+    let mut conditions = Vec::new();
+    loop {
+        let condition = match tokens.next() {
+            Some("time") => {
+                expect(tokens.next(), "between")?;
+                let start = parse_time(tokens.next())?;
+                expect(tokens.next(), "and")?;
+                let end = parse_time(tokens.next())?;
+                Condition::TimeBetween { start, end }
+            }
+            Some(key) if key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {
+                let op = match tokens.next() {
+                    Some(op) => op.parse()?,
+                    None => bail!("expected a comparison after '{}'", key),
+                };
+                let value = match tokens.next() {
+                    Some(value) => value
+                        .parse()
+                        .map_err(|_| anyhow!("'{}' is not a number", value))?,
+                    None => bail!("expected a number to compare '{}' with", key),
+                };
+                Condition::Compare {
+                    key: key.to_owned(),
+                    op,
+                    value,
+                }
+            }
+            Some(token) => bail!("expected a condition, got '{}'", token),
+            None => bail!("expected a condition"),
+        };
+        conditions.push(condition);
+
+        match tokens.next() {
+            None => break,
+            Some("and") => continue,
+            Some(token) => bail!("expected 'and', got '{}'", token),
+        }
+    }
+
+    Ok(conditions)
+}
+
+// splits on whitespace, and around comparisons so "soc<20" works too
+fn tokenize(s: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_op = false;
+
+    for c in s.chars() {
+        let is_op = "<>=!".contains(c);
+        if (c.is_whitespace() || is_op != in_op) && !current.is_empty() {
+            tokens.push(std::mem::take(&mut current));
+        }
+        if !c.is_whitespace() {
+            current.push(c);
+            in_op = is_op;
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    tokens
+}
+
+fn expect(token: Option<&str>, wanted: &str) -> Result<()> {
+    match token {
+        Some(token) if token == wanted => Ok(()),
+        Some(token) => bail!("expected '{}', got '{}'", wanted, token),
+        None => bail!("expected '{}'", wanted),
+    }
+}
+
+fn parse_time(token: Option<&str>) -> Result<NaiveTime> {
+    match token {
+        Some(token) => NaiveTime::parse_from_str(token, "%H:%M")
+            .map_err(|_| anyhow!("'{}' is not a time, use HH:MM", token)),
+        None => bail!("expected a time"),
+    }
+} // }}}
+
+// Rule {{{
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RuleState {
+    fired: bool,
+    last_fired: Option<i64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub name: String,
+    conditions: Vec<Condition>,
+    hysteresis: f64,
+    cooldown: i64,
+    command: String,
+    payload: String,
+    datalogs: Vec<Serial>,
+}
+
+impl Rule {
+    pub fn new(config: &config::Rule) -> Result<Self> {
+        let conditions = parse_conditions(&config.when)
+            .map_err(|err| anyhow!("rule {}: {}", config.name, err))?;
+
+        Ok(Self {
+            name: config.name.clone(),
+            conditions,
+            hysteresis: config.hysteresis(),
+            cooldown: config.cooldown() as i64,
+            command: config.command.clone(),
+            payload: config.payload.clone(),
+            datalogs: config.datalogs.clone(),
+        })
+    }
+
+    fn applies_to(&self, datalog: Serial) -> bool {
+        self.datalogs.is_empty() || self.datalogs.contains(&datalog)
+    }
+
+    // None if any of the inputs is missing
+    fn all_hold(&self, inputs: &Inputs, time: NaiveTime, margin: f64) -> Option<bool> {
+        let mut all = true;
+        for condition in &self.conditions {
+            all &= condition.holds(inputs, time, margin)?;
+        }
+        Some(all)
+    }
+
+    // A rule fires once when its conditions become true, then not again until
+    // they have stopped holding (by at least hysteresis) and cooldown seconds
+    // have passed since it last fired. now is in seconds.
+    pub fn check(&self, state: &mut RuleState, inputs: &Inputs, time: NaiveTime, now: i64) -> bool {
+        if state.fired {
+            if self.all_hold(inputs, time, self.hysteresis) == Some(false) {
+                state.fired = false;
+            }
+            return false;
+        }
+
+        if self.all_hold(inputs, time, 0.0) != Some(true) {
+            return false;
+        }
+
+        if let Some(last_fired) = state.last_fired {
+            if now - last_fired < self.cooldown {
+                return false;
+            }
+        }
+
+        state.fired = true;
+        state.last_fired = Some(now);
+        true
+    }
+
+    // the inputs the conditions look at, for the log
+    fn values(&self, inputs: &Inputs) -> Inputs {
+        self.conditions
+            .iter()
+            .filter_map(|condition| match condition {
+                Condition::Compare { key, .. } => {
+                    inputs.get(key).map(|value| (key.to_owned(), value.clone()))
+                }
+                Condition::TimeBetween { .. } => None,
+            })
+            .collect()
+    }
+} // }}}
+
+// Engine {{{
+// What a rule did, as published to automation/{rule}.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Action {
+    pub rule: String,
+    pub datalog: Serial,
+    pub command: String,
+    pub payload: String,
+    pub values: Inputs,
+}
+
+impl Action {
+    // the command, as if it had arrived over MQTT
+    pub fn command_message(&self) -> mqtt::Message {
+        mqtt::Message {
+            topic: format!("cmd/{}/{}", self.datalog, self.command),
+            retain: false,
+            payload: self.payload.clone(),
+        }
+    }
+
+    pub fn log_message(&self) -> Result<mqtt::Message> {
+        Ok(mqtt::Message {
+            topic: format!("automation/{}", self.rule),
+            retain: false,
+            payload: serde_json::to_string(self)?,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct Engine {
+    rules: Vec<Rule>,
+    // seconds an input is used for after it was read
+    max_age: i64,
+    // each input with when it was read
+    inputs: HashMap<Serial, HashMap<String, (serde_json::Value, i64)>>,
+    states: HashMap<(usize, Serial), RuleState>,
+}
+
+impl Engine {
+    pub fn new(rules: Vec<Rule>, interval: u64) -> Self {
+        Self {
+            rules,
+            max_age: interval as i64 * STALE_INTERVALS,
+            ..Default::default()
+        }
+    }
+
+    // Merges in newly read inputs; the 40 register reads only have some of
+    // them each. now is in seconds. Returns the datalog they were for.
+    pub fn update_inputs(&mut self, td: &TranslatedData, now: i64) -> Option<Serial> {
+        let value = match td.read_input().ok()? {
+            ReadInput::ReadInputAll(r) => {
+                let mut value = serde_json::to_value(&r).ok()?;
+                let metrics = HouseholdMetrics::new(&r).fields().ok()?;
+                value.as_object_mut()?.extend(metrics);
+                value
+            }
+            ReadInput::ReadInputAll2(r) => serde_json::to_value(&r).ok()?,
+            ReadInput::ReadInput1(r) => serde_json::to_value(&r).ok()?,
+            ReadInput::ReadInput2(r) => serde_json::to_value(&r).ok()?,
+            ReadInput::ReadInput3(r) => serde_json::to_value(&r).ok()?,
+        };
+
+        match value {
+            serde_json::Value::Object(fields) => {
+                let inputs = self.inputs.entry(td.datalog).or_default();
+                inputs.extend(fields.into_iter().map(|(key, value)| (key, (value, now))));
+                Some(td.datalog)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn datalogs(&self) -> Vec<Serial> {
+        self.inputs.keys().copied().collect()
+    }
+
+    // Checks every rule for datalog against its latest inputs, returning
+    // those which fire. time is the inverter's time of day.
+    pub fn evaluate(&mut self, datalog: Serial, time: NaiveTime, now: i64) -> Vec<Action> {
+        let inputs: Inputs = match self.inputs.get(&datalog) {
+            Some(inputs) => inputs
+                .iter()
+                .filter(|(_, (_, read))| now - read <= self.max_age)
+                .map(|(key, (value, _))| (key.clone(), value.clone()))
+                .collect(),
+            None => return Vec::new(),
+        };
+
+        let mut actions = Vec::new();
+        for (n, rule) in self.rules.iter().enumerate() {
+            if !rule.applies_to(datalog) {
+                continue;
+            }
+
+            let state = self.states.entry((n, datalog)).or_default();
+            if rule.check(state, &inputs, time, now) {
+                actions.push(Action {
+                    rule: rule.name.clone(),
+                    datalog,
+                    command: rule.command.clone(),
+                    payload: rule.payload.clone(),
+                    values: rule.values(&inputs),
+                });
+            }
+        }
+
+        actions
+    }
+} // }}}
+
+// Automation {{{
+pub struct Automation {
+    config: ConfigWrapper,
+    channels: Channels,
+}
+
+impl Automation {
+    pub fn new(config: ConfigWrapper, channels: Channels) -> Self {
+        Self { config, channels }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        use lxp::inverter::ChannelData;
+
+        let automation = self.config.automation().clone();
+        if automation.is_none() {
+            info!("automation config not found, skipping");
+            return Ok(());
+        }
+
+        let automation = automation.unwrap();
+        if !automation.enabled() {
+            info!("automation disabled, skipping");
+            return Ok(());
+        }
+
+        let rules = automation
+            .rules()
+            .iter()
+            .map(Rule::new)
+            .collect::<Result<Vec<_>>>()?;
+        info!("automation starting with {} rules", rules.len());
+
+        let mut engine = Engine::new(rules, automation.interval());
+        let mut receiver = self.channels.from_inverter.subscribe();
+        let mut interval =
+            tokio::time::interval(std::time::Duration::from_secs(automation.interval()));
+
+        loop {
+            let datalogs = tokio::select! {
+                data = receiver.recv() => match data {
+                    Ok(ChannelData::Packet(Packet::TranslatedData(td)))
+                        if td.device_function == DeviceFunction::ReadInput =>
+                    {
+                        engine.update_inputs(&td, Utils::utc().timestamp()).into_iter().collect()
+                    }
+                    Ok(ChannelData::Shutdown) | Err(broadcast::error::RecvError::Closed) => break,
+                    _ => continue,
+                },
+                _ = interval.tick() => engine.datalogs(),
+            };
+
+            let now = Utils::utc().timestamp();
+            for datalog in datalogs {
+                let time = match self.config.enabled_inverter_with_datalog(datalog) {
+                    Some(inverter) => inverter.local_time().time(),
+                    None => Utils::localtime().time(),
+                };
+                for action in engine.evaluate(datalog, time, now) {
+                    self.act(&action)?;
+                }
+            }
+        }
+
+        info!("automation exiting");
+
+        Ok(())
+    }
+
+    // Commands go straight to the coordinator, so rules work whether or not
+    // MQTT is up; the log is best effort.
+    fn act(&self, action: &Action) -> Result<()> {
+        info!(
+            "rule {} firing for {}: {} {:?} ({})",
+            action.rule,
+            action.datalog,
+            action.command,
+            action.payload,
+            serde_json::Value::Object(action.values.clone())
+        );
+
+        if self
+            .channels
+            .from_mqtt
+            .send(mqtt::ChannelData::Message(action.command_message()))
+            .is_err()
+        {
+            bail!("send(from_mqtt) failed - channel closed?");
+        }
+
+        if self.config.mqtt().enabled() {
+            let message = mqtt::ChannelData::Message(action.log_message()?);
+            if self.channels.to_mqtt.send(message).is_err() {
+                debug!(
+                    "could not publish rule {} action, MQTT not connected?",
+                    action.rule
+                );
+            }
+        }
+
+        Ok(())
+    }
+} // }}}
@@ -0,0 +1,356 @@
+use crate::prelude::*;
+
+use coordinator::commands::{read_hold::ReadHold, read_param::ReadParam};
+use coordinator::commands::{set_hold::SetHold, write_param::WriteParam};
+use serde::{Deserialize, Serialize};
+
+// Holding registers which describe the inverter rather than configure it;
+// model, serial number, firmware versions, the reboot command and the clock.
+// They are backed up for reference but never restored.
+const NOT_RESTORABLE: std::ops::RangeInclusive<u16> = 0..=14;
+
+// The dongle parameters we know of. Any which don't answer are left out of
+// the backup.
+const PARAM_REGISTERS: std::ops::Range<u16> = 0..16;
+
+// Setting {{{
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Setting {
+    pub register: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    // what is actually stored in the register; this is what gets restored
+    pub raw: u16,
+    // human-readable value, for reference only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+}
+
+impl Setting {
+    pub fn hold(register: u16, raw: u16) -> Self {
+        let config = lxp::packet::find_register_config(register);
+
+        Self {
+            register,
+            name: lxp::packet::Register::try_from(register)
+                .ok()
+                .map(|r| format!("{:?}", r)),
+            raw,
+            value: config
+                .as_ref()
+                .map(|config| Utils::round(raw as f64 * config.scale, 3)),
+            unit: config
+                .map(|config| config.unit_of_measurement.to_owned())
+                .filter(|unit| !unit.is_empty()),
+        }
+    }
+
+    pub fn param(register: u16, raw: u16) -> Self {
+        Self {
+            register,
+            name: None,
+            raw,
+            value: None,
+            unit: None,
+        }
+    }
+}
+
+// the scaled value and unit when we know them, as in the backup file, else
+// the raw value
+impl std::fmt::Display for Setting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.value, &self.unit) {
+            (Some(value), Some(unit)) => write!(f, "{} {}", value, unit),
+            (Some(value), None) => write!(f, "{}", value),
+            (None, _) => write!(f, "{}", self.raw),
+        }
+    }
+} // }}}
+
+// Difference {{{
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Hold,
+    Param,
+}
+
+// A setting in a backup which doesn't match the live inverter. live is None
+// if the inverter didn't give us that register at all.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Difference {
+    pub kind: Kind,
+    pub setting: Setting,
+    pub live: Option<u16>,
+}
+
+impl std::fmt::Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let register = self.setting.register;
+        let (kind, live) = match self.kind {
+            Kind::Hold => ("hold", self.live.map(|raw| Setting::hold(register, raw))),
+            Kind::Param => ("param", self.live.map(|raw| Setting::param(register, raw))),
+        };
+        let live = match live {
+            Some(live) => live.to_string(),
+            None => "?".to_owned(),
+        };
+
+        write!(f, "{} {}", kind, register)?;
+        if let Some(name) = &self.setting.name {
+            write!(f, " ({})", name)?;
+        }
+        write!(f, ": {} -> {}", live, self.setting)
+    }
+} // }}}
+
+// Settings {{{
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    #[serde(deserialize_with = "config::de_serial")]
+    pub datalog: Serial,
+    pub holdings: Vec<Setting>,
+    pub params: Vec<Setting>,
+}
+
+impl Settings {
+    pub fn load(file: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(file)
+            .map_err(|err| anyhow!("error reading {}: {}", file, err))?;
+
+        Ok(serde_yaml::from_str(&content)?)
+    }
+
+    pub fn save(&self, file: &str) -> Result<()> {
+        std::fs::write(file, serde_yaml::to_string(self)?)
+            .map_err(|err| anyhow!("error writing {}: {}", file, err))
+    }
+
+    // Everything in this backup which doesn't match live, apart from the
+    // registers which describe the inverter rather than configure it.
+    pub fn differences(&self, live: &Settings) -> Vec<Difference> {
+        let holdings = self
+            .holdings
+            .iter()
+            .filter(|setting| !NOT_RESTORABLE.contains(&setting.register))
+            .filter_map(|setting| Self::difference(Kind::Hold, setting, &live.holdings));
+        let params = self
+            .params
+            .iter()
+            .filter_map(|setting| Self::difference(Kind::Param, setting, &live.params));
+
+        holdings.chain(params).collect()
+    }
+
+    fn difference(kind: Kind, setting: &Setting, live: &[Setting]) -> Option<Difference> {
+        let live = live
+            .iter()
+            .find(|live| live.register == setting.register)
+            .map(|live| live.raw);
+
+        if live == Some(setting.raw) {
+            None
+        } else {
+            Some(Difference {
+                kind,
+                setting: setting.clone(),
+                live,
+            })
+        }
+    }
+} // }}}
+
+// `lxp-bridge backup`; reads every holding register and the dongle
+// parameters from one inverter and saves them to output.
+pub async fn backup(config: ConfigWrapper, datalog: Serial, output: &str) -> Result<()> {
+    let inverter = match config.enabled_inverter_with_datalog(datalog) {
+        Some(inverter) => inverter,
+        None => bail!("no enabled inverter with datalog {} in config", datalog),
+    };
+    let channels = Channels::new();
+
+    let settings = with_connection(
+        &config,
+        &channels,
+        &inverter,
+        read_settings(&channels, &inverter),
+    )
+    .await?;
+    settings.save(output)?;
+
+    println!(
+        "saved {} holding registers and {} parameters from {} to {}",
+        settings.holdings.len(),
+        settings.params.len(),
+        datalog,
+        output
+    );
+
+    Ok(())
+}
+
+// `lxp-bridge restore`; compares a backup with the live inverter and writes
+// back whatever differs, unless dry_run. The backup is restored to the
+// inverter it came from unless datalog is given (eg after a dongle swap).
+pub async fn restore(
+    config: ConfigWrapper,
+    file: &str,
+    datalog: Option<Serial>,
+    dry_run: bool,
+) -> Result<()> {
+    let wanted = Settings::load(file)?;
+    let datalog = datalog.unwrap_or(wanted.datalog);
+    let inverter = match config.enabled_inverter_with_datalog(datalog) {
+        Some(inverter) => inverter,
+        None => bail!("no enabled inverter with datalog {} in config", datalog),
+    };
+    let channels = Channels::new();
+
+    let restore = async {
+        let live = read_settings(&channels, &inverter).await?;
+        let differences = wanted.differences(&live);
+
+        if differences.is_empty() {
+            println!("{} already matches {}", datalog, file);
+            return Ok(());
+        }
+
+        for difference in &differences {
+            println!("{}", difference);
+        }
+
+        if dry_run {
+            println!("dry run, nothing written");
+            return Ok(());
+        }
+
+        let mut failed = 0;
+        for difference in &differences {
+            if let Err(err) = write(&config, &channels, &inverter, difference).await {
+                println!("{}: {}", difference, err);
+                failed += 1;
+            }
+        }
+
+        println!(
+            "wrote {} of {} settings to {}",
+            differences.len() - failed,
+            differences.len(),
+            datalog
+        );
+
+        if failed > 0 {
+            bail!("{} settings could not be written to {}", failed, datalog);
+        }
+
+        Ok(())
+    };
+
+    with_connection(&config, &channels, &inverter, restore).await
+}
+
+async fn read_settings(channels: &Channels, inverter: &config::Inverter) -> Result<Settings> {
+    let mut holdings = Vec::new();
+    for register in coordinator::HOLD_BLOCKS {
+        let packet = ReadHold::new(channels.clone(), inverter.clone(), register, 40)
+            .run()
+            .await?;
+
+        if let Packet::TranslatedData(td) = packet {
+            for (register, raw) in td.pairs() {
+                holdings.push(Setting::hold(register, raw));
+            }
+        }
+    }
+
+    let mut params = Vec::new();
+    for register in PARAM_REGISTERS {
+        match ReadParam::new(channels.clone(), inverter.clone(), register)
+            .run()
+            .await
+        {
+            // only the first value is kept, as that is all WriteParam can restore
+            Ok(Packet::ReadParam(rp)) => {
+                if let Some((_, raw)) = rp.pairs().first() {
+                    params.push(Setting::param(register, *raw));
+                }
+            }
+            Ok(_) => {}
+            Err(err) => warn!("skipping param {}: {}", register, err),
+        }
+    }
+
+    Ok(Settings {
+        datalog: inverter.datalog(),
+        holdings,
+        params,
+    })
+}
+
+// Writes go through the same safeguards as MQTT commands, so read_only and
+// the writable/protected register lists are respected.
+async fn write(
+    config: &ConfigWrapper,
+    channels: &Channels,
+    inverter: &config::Inverter,
+    difference: &Difference,
+) -> Result<()> {
+    use coordinator::safeguards::{check_hold_write, check_param_write};
+
+    let register = difference.setting.register;
+    let raw = difference.setting.raw;
+
+    match difference.kind {
+        Kind::Hold => {
+            check_hold_write(config.read_only(), inverter, register, Some(raw))?;
+            SetHold::new(channels.clone(), inverter.clone(), register, raw)
+                .run()
+                .await?;
+        }
+        Kind::Param => {
+            check_param_write(config.read_only(), inverter)?;
+            WriteParam::new(channels.clone(), inverter.clone(), register, raw)
+                .run()
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+// Connects to the inverter and runs f once it's connected, keeping the
+// connection open until f is done.
+async fn with_connection<F, T>(
+    config: &ConfigWrapper,
+    channels: &Channels,
+    inverter: &config::Inverter,
+    f: F,
+) -> Result<T>
+where
+    F: std::future::Future<Output = Result<T>>,
+{
+    let mut receiver = channels.from_inverter.subscribe();
+    let connection = Inverter::new(config.clone(), inverter, channels.clone());
+
+    let connected = async {
+        loop {
+            if let lxp::inverter::ChannelData::Connected(datalog) = receiver.recv().await? {
+                if datalog == inverter.datalog() {
+                    break;
+                }
+            }
+        }
+
+        f.await
+    };
+
+    tokio::select! {
+        result = connection.start() => {
+            result?;
+            bail!("lost connection to {}", inverter.datalog())
+        }
+        result = connected => result,
+    }
+}
@@ -10,6 +10,8 @@ pub enum Command {
     ReadAcFirstTime(config::Inverter, u16),
     ReadChargePriorityTime(config::Inverter, u16),
     ReadForcedDischargeTime(config::Inverter, u16),
+    ReadTime(config::Inverter),
+    ReadBatch(config::Inverter, Vec<coordinator::batch::Range>),
     SetHold(config::Inverter, u16, f64),
     WriteParam(config::Inverter, u16, u16),
     SetAcChargeTime(config::Inverter, u16, [u8; 4]),
@@ -21,9 +23,15 @@ pub enum Command {
     AcCharge(config::Inverter, bool),
     ChargePriority(config::Inverter, bool),
     ForcedDischarge(config::Inverter, bool),
+    SetBit(config::Inverter, u16, String, bool),
+    SetBits(config::Inverter, u16, Vec<(String, bool)>),
     AcChargeRate(config::Inverter, u16),
     AcChargeSocLimit(config::Inverter, u16),
     DischargeCutoffSocLimit(config::Inverter, u16),
+    SetPreset(config::Inverter, String),
+    SetSchedule(config::Inverter, coordinator::schedule::Schedule),
+    // None sets the clock to now
+    SetTime(config::Inverter, Option<chrono::NaiveDateTime>),
 }
 
 impl Command {
@@ -53,6 +61,8 @@ impl Command {
             ReadForcedDischargeTime(inverter, num) => {
                 format!("{}/read/forced_discharge/{}", inverter.datalog(), num)
             }
+            ReadTime(inverter) => format!("{}/read/time", inverter.datalog()),
+            ReadBatch(inverter, _) => format!("{}/read/batch", inverter.datalog()),
             SetHold(inverter, register, _) => {
                 format!("{}/set/hold/{}", inverter.datalog(), register)
             }
@@ -74,6 +84,12 @@ impl Command {
             AcCharge(inverter, _) => format!("{}/set/ac_charge", inverter.datalog()),
             ChargePriority(inverter, _) => format!("{}/set/charge_priority", inverter.datalog()),
             ForcedDischarge(inverter, _) => format!("{}/set/forced_discharge", inverter.datalog()),
+            SetBit(inverter, register, name, _) => {
+                format!("{}/set/bit/{}/{}", inverter.datalog(), register, name)
+            }
+            SetBits(inverter, register, _) => {
+                format!("{}/set/bits/{}", inverter.datalog(), register)
+            }
             ChargeRate(inverter, _) => format!("{}/set/charge_rate_pct", inverter.datalog()),
             DischargeRate(inverter, _) => format!("{}/set/discharge_rate_pct", inverter.datalog()),
             AcChargeRate(inverter, _) => format!("{}/set/ac_charge_rate_pct", inverter.datalog()),
@@ -83,6 +99,9 @@ impl Command {
             DischargeCutoffSocLimit(inverter, _) => {
                 format!("{}/set/discharge_cutoff_soc_limit_pct", inverter.datalog())
             }
+            SetPreset(inverter, name) => format!("{}/set/preset/{}", inverter.datalog(), name),
+            SetSchedule(inverter, _) => format!("{}/set/schedule", inverter.datalog()),
+            SetTime(inverter, _) => format!("{}/set/time", inverter.datalog()),
         };
 
         format!("result/{}", rest)
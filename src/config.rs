@@ -16,8 +16,28 @@ pub struct Config {
 
     pub scheduler: Option<Scheduler>,
 
+    pub tariff: Option<Tariff>,
+
+    pub forecast: Option<Forecast>,
+
+    pub automation: Option<Automation>,
+
+    pub export_limit: Option<ExportLimit>,
+
     #[serde(default = "Config::default_loglevel")]
     pub loglevel: String,
+
+    // refuse all writes to every inverter
+    #[serde(default)]
+    pub read_only: bool,
+
+    // named sets of register changes, applied with set/preset/{name}
+    #[serde(default)]
+    pub presets: std::collections::BTreeMap<String, serde_yaml::Mapping>,
+
+    // where to keep the energy counters between restarts, so the e_*_total
+    // values carry on from where they were
+    pub energy_state_file: Option<String>,
 }
 
 // Inverter {{{
@@ -36,6 +56,25 @@ pub struct Inverter {
     pub heartbeats: Option<bool>,
     pub publish_holdings_on_connect: Option<bool>,
     pub read_timeout: Option<u64>,
+    pub max_power: Option<u32>,
+    pub hold_cache_max_age: Option<u64>,
+    pub refresh_holdings_interval: Option<u64>,
+
+    // IANA name of the timezone the inverter's clock should keep; the host's
+    // if not given
+    #[serde(default, deserialize_with = "de_timezone")]
+    pub timezone: Option<chrono_tz::Tz>,
+    // seconds the clock can drift before timesync corrects it
+    pub time_tolerance: Option<u64>,
+    // minimum seconds between writes to the same holding register
+    pub write_interval: Option<u64>,
+
+    pub read_only: Option<bool>,
+    // if set, only these holding registers can be written to
+    pub writable_registers: Option<Vec<u16>>,
+    // holding registers which can never be written to; reboot and the LCD
+    // password if not given
+    pub protected_registers: Option<Vec<u16>>,
 }
 impl Inverter {
     pub fn enabled(&self) -> bool {
@@ -69,6 +108,66 @@ impl Inverter {
     pub fn read_timeout(&self) -> u64 {
         self.read_timeout.unwrap_or(900) // 15 minutes
     }
+
+    // how long a cached holding register can be used for before it must be
+    // read from the inverter again
+    pub fn hold_cache_max_age(&self) -> u64 {
+        self.hold_cache_max_age.unwrap_or(300) // 5 minutes
+    }
+
+    // seconds between re-reads of the holding registers, if they should be
+    // re-read at all
+    pub fn refresh_holdings_interval(&self) -> Option<u64> {
+        self.refresh_holdings_interval
+            .filter(|&interval| interval > 0)
+    }
+
+    pub fn timezone(&self) -> Option<chrono_tz::Tz> {
+        self.timezone
+    }
+
+    // now as the wall clock time in timezone
+    pub fn local_time(&self) -> chrono::NaiveDateTime {
+        let now = Utils::utc();
+        match self.timezone {
+            Some(tz) => now.with_timezone(&tz).naive_local(),
+            None => now.with_timezone(&chrono::Local).naive_local(),
+        }
+    }
+
+    pub fn time_tolerance(&self) -> u64 {
+        self.time_tolerance.unwrap_or(120) // 2 minutes
+    }
+
+    // writes to a holding register sooner than this after the last one are
+    // held back, and only the latest of them is written; 0 for no limit
+    pub fn write_interval(&self) -> u64 {
+        self.write_interval.unwrap_or(0)
+    }
+
+    // upper bound on any power flow through the inverter, in W. used to
+    // reject implausible jumps in the energy counters
+    pub fn max_power(&self) -> u32 {
+        self.max_power.unwrap_or(30000)
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only == Some(true)
+    }
+
+    pub fn register_writable(&self, register: u16) -> bool {
+        let allowed = self
+            .writable_registers
+            .as_ref()
+            .map_or(true, |registers| registers.contains(&register));
+        let protected = self
+            .protected_registers
+            .as_deref()
+            .unwrap_or(&[11, 225])
+            .contains(&register);
+
+        allowed && !protected
+    }
 } // }}}
 
 // HomeAssistant {{{
@@ -111,6 +210,25 @@ pub struct Mqtt {
     pub homeassistant: HomeAssistant,
 
     pub publish_individual_input: Option<bool>,
+
+    pub tls: Option<MqttTls>,
+
+    #[serde(default)]
+    pub protocol: MqttProtocol,
+    // v5 only. seconds the broker holds non-retained input messages for
+    // offline subscribers; 0 for as long as it likes
+    pub message_expiry: Option<u32>,
+
+    // must differ between bridges sharing a broker; lxp-bridge-{hostname} if
+    // not given
+    pub client_id: Option<String>,
+    // {namespace}/{client_id}/LWT if not given
+    pub lwt_topic: Option<String>,
+    // false asks the broker to keep our subscriptions and queue commands
+    // while we're disconnected
+    pub clean_session: Option<bool>,
+    // v5 only. seconds the broker keeps a persistent session for
+    pub session_expiry: Option<u32>,
 }
 impl Mqtt {
     pub fn enabled(&self) -> bool {
@@ -144,6 +262,110 @@ impl Mqtt {
     pub fn publish_individual_input(&self) -> bool {
         self.publish_individual_input == Some(true)
     }
+
+    pub fn tls(&self) -> Option<&MqttTls> {
+        self.tls.as_ref().filter(|tls| tls.enabled())
+    }
+
+    pub fn protocol(&self) -> MqttProtocol {
+        self.protocol
+    }
+
+    pub fn message_expiry(&self) -> Option<u32> {
+        match self.message_expiry.unwrap_or(300) {
+            0 => None,
+            secs => Some(secs),
+        }
+    }
+
+    pub fn client_id(&self) -> String {
+        self.client_id.clone().unwrap_or_else(|| {
+            format!(
+                "lxp-bridge-{}",
+                gethostname::gethostname().to_string_lossy()
+            )
+        })
+    }
+
+    pub fn lwt_topic(&self) -> String {
+        self.lwt_topic
+            .clone()
+            .unwrap_or_else(|| format!("{}/{}/LWT", self.namespace(), self.client_id()))
+    }
+
+    pub fn clean_session(&self) -> bool {
+        self.clean_session != Some(false)
+    }
+
+    pub fn session_expiry(&self) -> u32 {
+        self.session_expiry.unwrap_or(3600)
+    }
+
+    // the client ID to connect with. a persistent session is keyed on it, so
+    // an empty one (which the broker would make up) can't be used for that
+    pub fn session_client_id(&self) -> Result<String> {
+        let client_id = self.client_id();
+        if client_id.is_empty() && !self.clean_session() {
+            bail!("mqtt: clean_session: false needs a client_id");
+        }
+
+        Ok(client_id)
+    }
+} // }}}
+
+// MqttProtocol {{{
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MqttProtocol {
+    // MQTT 3.1.1
+    #[default]
+    V4,
+    V5,
+} // }}}
+
+// MqttTls {{{
+#[derive(Clone, Debug, Deserialize)]
+pub struct MqttTls {
+    #[serde(default = "Config::default_enabled")]
+    pub enabled: bool,
+
+    // PEM certificate(s) to trust the broker with; the system's if not given
+    pub ca_file: Option<String>,
+    // PEM client certificate (chain) and private key, for brokers which
+    // authenticate clients by certificate
+    pub cert_file: Option<String>,
+    pub key_file: Option<String>,
+    // protocols to offer with ALPN, eg x-amzn-mqtt-ca
+    #[serde(default)]
+    pub alpn: Vec<String>,
+    // accept any broker certificate. only for testing!
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+}
+impl MqttTls {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn ca_file(&self) -> Option<&str> {
+        self.ca_file.as_deref()
+    }
+
+    pub fn client_auth(&self) -> Result<Option<(&str, &str)>> {
+        match (self.cert_file.as_deref(), self.key_file.as_deref()) {
+            (Some(cert), Some(key)) => Ok(Some((cert, key))),
+            (None, None) => Ok(None),
+            _ => bail!("mqtt.tls: cert_file and key_file must be given together"),
+        }
+    }
+
+    pub fn alpn(&self) -> &[String] {
+        &self.alpn
+    }
+
+    pub fn insecure_skip_verify(&self) -> bool {
+        self.insecure_skip_verify
+    }
 } // }}}
 
 // Influx {{{
@@ -205,6 +427,9 @@ pub struct Scheduler {
     pub enabled: bool,
 
     pub timesync_cron: Option<String>,
+
+    #[serde(default)]
+    pub jobs: Vec<Job>,
 }
 impl Scheduler {
     pub fn enabled(&self) -> bool {
@@ -214,6 +439,316 @@ impl Scheduler {
     pub fn timesync_cron(&self) -> &Option<String> {
         &self.timesync_cron
     }
+
+    pub fn jobs(&self) -> &Vec<Job> {
+        &self.jobs
+    }
+} // }}}
+
+// Tariff {{{
+// Plans AC charge and forced discharge windows for one inverter from a list
+// of upcoming prices.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Tariff {
+    #[serde(default = "Config::default_enabled")]
+    pub enabled: bool,
+
+    #[serde(deserialize_with = "de_serial")]
+    pub datalog: Serial,
+    // http(s) URL or local file of prices
+    pub source: String,
+
+    pub target_soc: Option<u16>,
+    pub charge_power: Option<u32>,
+    // forced discharge is only planned for slots at least this price
+    pub discharge_above: Option<f64>,
+    pub discharge_soc: Option<u16>,
+    pub discharge_power: Option<u32>,
+    // turns bat_capacity (Ah) into energy
+    pub battery_voltage: Option<f64>,
+    pub interval: Option<u64>,
+}
+impl Tariff {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn datalog(&self) -> Serial {
+        self.datalog
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn target_soc(&self) -> u16 {
+        self.target_soc.unwrap_or(100)
+    }
+
+    pub fn charge_power(&self) -> u32 {
+        self.charge_power.unwrap_or(3000)
+    }
+
+    pub fn discharge_above(&self) -> Option<f64> {
+        self.discharge_above
+    }
+
+    pub fn discharge_soc(&self) -> u16 {
+        self.discharge_soc.unwrap_or(20)
+    }
+
+    pub fn discharge_power(&self) -> u32 {
+        self.discharge_power.unwrap_or(3000)
+    }
+
+    pub fn battery_voltage(&self) -> f64 {
+        self.battery_voltage.unwrap_or(51.2)
+    }
+
+    pub fn interval(&self) -> u64 {
+        self.interval.unwrap_or(1800) // 30 minutes
+    }
+} // }}}
+
+// Forecast {{{
+// Sets the AC charge SOC limit for one inverter from tomorrow's PV forecast
+// and recent consumption.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Forecast {
+    #[serde(default = "Config::default_enabled")]
+    pub enabled: bool,
+
+    #[serde(deserialize_with = "de_serial")]
+    pub datalog: Serial,
+    // http(s) URL or local file of the forecast
+    pub source: String,
+    // when to set the limit, on the inverter's clock; should be before the
+    // cheap window starts
+    pub cron: String,
+
+    // daily use (kWh) to assume until there is some history
+    pub consumption: Option<f64>,
+    pub history_days: Option<usize>,
+    pub min_soc: Option<u16>,
+    pub max_soc: Option<u16>,
+    // added on to the shortfall, as the forecast is only a forecast
+    pub margin_pct: Option<f64>,
+    pub battery_voltage: Option<f64>,
+}
+impl Forecast {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn datalog(&self) -> Serial {
+        self.datalog
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn cron(&self) -> &str {
+        &self.cron
+    }
+
+    pub fn consumption(&self) -> f64 {
+        self.consumption.unwrap_or(10.0)
+    }
+
+    pub fn history_days(&self) -> usize {
+        self.history_days.unwrap_or(7)
+    }
+
+    pub fn min_soc(&self) -> u16 {
+        self.min_soc.unwrap_or(10)
+    }
+
+    pub fn max_soc(&self) -> u16 {
+        self.max_soc.unwrap_or(100)
+    }
+
+    pub fn margin_pct(&self) -> f64 {
+        self.margin_pct.unwrap_or(10.0)
+    }
+
+    pub fn battery_voltage(&self) -> f64 {
+        self.battery_voltage.unwrap_or(51.2)
+    }
+} // }}}
+
+// Automation {{{
+#[derive(Clone, Debug, Deserialize)]
+pub struct Automation {
+    #[serde(default = "Config::default_enabled")]
+    pub enabled: bool,
+
+    // rules are checked whenever new inputs arrive, and this often (seconds)
+    // for the sake of time conditions
+    pub interval: Option<u64>,
+
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+impl Automation {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn interval(&self) -> u64 {
+        self.interval.unwrap_or(60)
+    }
+
+    pub fn rules(&self) -> &Vec<Rule> {
+        &self.rules
+    }
+} // }}}
+
+// Rule {{{
+// A command to send, as if it had arrived over MQTT, when the conditions in
+// `when` become true. eg "soc < 20 and time between 16:00 and 19:00"
+#[derive(Clone, Debug, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    pub when: String,
+    // the command topic without cmd/{datalog}/, eg set/forced_discharge
+    pub command: String,
+    #[serde(default, deserialize_with = "de_payload")]
+    pub payload: String,
+    // datalogs to check the rule for; all enabled inverters if empty
+    #[serde(default, deserialize_with = "de_serials")]
+    pub datalogs: Vec<Serial>,
+
+    // how far past its threshold a value must go back before the rule can
+    // fire again
+    pub hysteresis: Option<f64>,
+    // minimum seconds between firings
+    pub cooldown: Option<u64>,
+}
+impl Rule {
+    pub fn hysteresis(&self) -> f64 {
+        self.hysteresis.unwrap_or(0.0)
+    }
+
+    pub fn cooldown(&self) -> u64 {
+        self.cooldown.unwrap_or(0)
+    }
+} // }}}
+
+// ExportLimit {{{
+// Keeps one inverter's export under limit by adjusting a discharge power
+// percentage register from p_to_grid.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ExportLimit {
+    #[serde(default = "Config::default_enabled")]
+    pub enabled: bool,
+
+    #[serde(deserialize_with = "de_serial")]
+    pub datalog: Serial,
+    // W
+    pub limit: u32,
+
+    // 82 (ForcedDischgPowerCmd) or 65 (DischgPowerPercentCmd)
+    pub register: Option<u16>,
+    // fraction of the error corrected on each reading
+    pub gain: Option<f64>,
+    // W below limit that export may fall before discharge is raised again
+    pub deadband: Option<u32>,
+    // W the inverter discharges at 100%, to turn an error in W into percent
+    pub max_power: Option<u32>,
+    pub min_pct: Option<u16>,
+    pub max_pct: Option<u16>,
+    // minimum seconds between writes
+    pub min_interval: Option<u64>,
+}
+impl ExportLimit {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn datalog(&self) -> Serial {
+        self.datalog
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    pub fn register(&self) -> u16 {
+        self.register.unwrap_or(82)
+    }
+
+    pub fn gain(&self) -> f64 {
+        self.gain.unwrap_or(0.5)
+    }
+
+    pub fn deadband(&self) -> u32 {
+        self.deadband.unwrap_or(200)
+    }
+
+    pub fn max_power(&self) -> u32 {
+        self.max_power.unwrap_or(3600)
+    }
+
+    pub fn min_pct(&self) -> u16 {
+        self.min_pct.unwrap_or(0)
+    }
+
+    pub fn max_pct(&self) -> u16 {
+        self.max_pct.unwrap_or(100)
+    }
+
+    pub fn min_interval(&self) -> u64 {
+        self.min_interval.unwrap_or(30)
+    }
+} // }}}
+
+// Job {{{
+// A command to send on a cron schedule, as if it had arrived over MQTT.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Job {
+    pub name: Option<String>,
+    pub cron: String,
+    // the command topic without cmd/{datalog}/, eg set/hold/67
+    pub command: String,
+    #[serde(default, deserialize_with = "de_payload")]
+    pub payload: String,
+    // datalogs to send the command to; all enabled inverters if empty
+    #[serde(default, deserialize_with = "de_serials")]
+    pub datalogs: Vec<Serial>,
+}
+impl Job {
+    // used in logs and the scheduler/{name}/next_run topic. n is the job's
+    // position in the list, from 1.
+    pub fn name(&self, n: usize) -> String {
+        self.name.clone().unwrap_or_else(|| format!("job{}", n))
+    }
+
+    pub fn messages(&self) -> Vec<mqtt::Message> {
+        let targets = if self.datalogs.is_empty() {
+            vec!["all".to_owned()]
+        } else {
+            self.datalogs.iter().map(Serial::to_string).collect()
+        };
+
+        targets
+            .into_iter()
+            .map(|target| self.message(&target))
+            .collect()
+    }
+
+    // the command for just one of its inverters
+    pub fn message_for(&self, datalog: Serial) -> mqtt::Message {
+        self.message(&datalog.to_string())
+    }
+
+    fn message(&self, target: &str) -> mqtt::Message {
+        mqtt::Message {
+            topic: format!("cmd/{}/{}", target, self.command),
+            retain: false,
+            payload: self.payload.clone(),
+        }
+    }
 } // }}}
 
 #[derive(Debug)]
@@ -289,6 +824,10 @@ impl ConfigWrapper {
         Ref::map(self.config.borrow(), |b| &b.mqtt)
     }
 
+    pub fn mqtt_mut(&self) -> RefMut<Mqtt> {
+        RefMut::map(self.config.borrow_mut(), |b: &mut Config| &mut b.mqtt)
+    }
+
     pub fn influx(&self) -> Ref<Influx> {
         Ref::map(self.config.borrow(), |b| &b.influx)
     }
@@ -326,9 +865,56 @@ impl ConfigWrapper {
         Ref::map(self.config.borrow(), |b| &b.scheduler)
     }
 
+    pub fn tariff(&self) -> Ref<Option<Tariff>> {
+        Ref::map(self.config.borrow(), |b| &b.tariff)
+    }
+
+    pub fn forecast(&self) -> Ref<Option<Forecast>> {
+        Ref::map(self.config.borrow(), |b| &b.forecast)
+    }
+
+    pub fn automation(&self) -> Ref<Option<Automation>> {
+        Ref::map(self.config.borrow(), |b| &b.automation)
+    }
+
+    pub fn export_limit(&self) -> Ref<Option<ExportLimit>> {
+        Ref::map(self.config.borrow(), |b| &b.export_limit)
+    }
+
     pub fn loglevel(&self) -> String {
         self.config.borrow().loglevel.to_owned()
     }
+
+    pub fn read_only(&self) -> bool {
+        self.config.borrow().read_only
+    }
+
+    pub fn set_read_only(&self, read_only: bool) {
+        let mut c = self.config.borrow_mut();
+        c.read_only = read_only;
+    }
+
+    pub fn presets(&self) -> Ref<std::collections::BTreeMap<String, serde_yaml::Mapping>> {
+        Ref::map(self.config.borrow(), |b| &b.presets)
+    }
+
+    pub fn set_presets(&self, new: std::collections::BTreeMap<String, serde_yaml::Mapping>) {
+        let mut c = self.config.borrow_mut();
+        c.presets = new;
+    }
+
+    pub fn energy_state_file(&self) -> Option<String> {
+        self.config.borrow().energy_state_file.clone()
+    }
+
+    pub fn set_energy_state_file(&self, file: Option<String>) {
+        let mut c = self.config.borrow_mut();
+        c.energy_state_file = file;
+    }
+
+    pub fn preset_names(&self) -> Vec<String> {
+        self.presets().keys().cloned().collect()
+    }
 }
 
 impl Config {
@@ -336,7 +922,15 @@ impl Config {
         let content = std::fs::read_to_string(&file)
             .map_err(|err| anyhow!("error reading {}: {}", file, err))?;
 
-        Ok(serde_yaml::from_str(&content)?)
+        let config: Self = serde_yaml::from_str(&content)?;
+
+        // presets aren't otherwise looked at until one is applied, and a typo
+        // is better found now than then
+        for (name, entries) in &config.presets {
+            coordinator::presets::Preset::new(name, entries)?;
+        }
+
+        Ok(config)
     }
 
     fn default_mqtt_port() -> u16 {
@@ -366,10 +960,44 @@ impl Config {
     }
 }
 
-fn de_serial<'de, D>(deserializer: D) -> Result<Serial, D::Error>
+pub(crate) fn de_serial<'de, D>(deserializer: D) -> Result<Serial, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
     let raw = String::deserialize(deserializer)?;
     raw.parse().map_err(serde::de::Error::custom)
 }
+
+fn de_serials<'de, D>(deserializer: D) -> Result<Vec<Serial>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let raw = Vec::<String>::deserialize(deserializer)?;
+    raw.iter()
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn de_timezone<'de, D>(deserializer: D) -> Result<Option<chrono_tz::Tz>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+// Payloads can be written as plain YAML; numbers and booleans become their
+// string form and mappings become JSON, as for set/schedule or set/bits.
+fn de_payload<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match serde_yaml::Value::deserialize(deserializer)? {
+        serde_yaml::Value::Null => Ok(String::new()),
+        serde_yaml::Value::String(s) => Ok(s),
+        serde_yaml::Value::Bool(b) => Ok(b.to_string()),
+        serde_yaml::Value::Number(n) => Ok(n.to_string()),
+        value => serde_json::to_string(&value).map_err(serde::de::Error::custom),
+    }
+}
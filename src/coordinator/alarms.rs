@@ -0,0 +1,120 @@
+use crate::prelude::*;
+
+use lxp::packet::{ActiveCode, FaultCodeString, WarningCodeString};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlarmKind {
+    Fault,
+    Warning,
+}
+impl AlarmKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Fault => "fault",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlarmState {
+    Raised,
+    Cleared,
+}
+impl AlarmState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Raised => "raised",
+            Self::Cleared => "cleared",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AlarmEvent {
+    pub datalog: Serial,
+    pub kind: AlarmKind,
+    pub state: AlarmState,
+    pub bit: u8,
+    pub code: &'static str,
+    pub description: &'static str,
+    pub time: UnixTime,
+}
+
+// Remembers the last fault_code/warning_code seen from each inverter so we can
+// work out which individual bits have been raised or cleared since then.
+#[derive(Default)]
+pub struct AlarmStore {
+    codes: std::collections::HashMap<Serial, (u32, u32)>,
+}
+
+impl AlarmStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns an event for every bit that changed. The first time we see an
+    // inverter we have nothing to compare against, so treat everything as
+    // previously clear; that raises whatever is currently active and no more.
+    pub fn update(
+        &mut self,
+        datalog: Serial,
+        fault_code: u32,
+        warning_code: u32,
+        time: &UnixTime,
+    ) -> Vec<AlarmEvent> {
+        let (previous_fault, previous_warning) = self
+            .codes
+            .insert(datalog, (fault_code, warning_code))
+            .unwrap_or((0, 0));
+
+        let mut events = Self::diff(datalog, AlarmKind::Fault, previous_fault, fault_code, time);
+        events.append(&mut Self::diff(
+            datalog,
+            AlarmKind::Warning,
+            previous_warning,
+            warning_code,
+            time,
+        ));
+
+        events
+    }
+
+    fn diff(
+        datalog: Serial,
+        kind: AlarmKind,
+        previous: u32,
+        current: u32,
+        time: &UnixTime,
+    ) -> Vec<AlarmEvent> {
+        let raised = current & !previous;
+        let cleared = previous & !current;
+
+        [(AlarmState::Raised, raised), (AlarmState::Cleared, cleared)]
+            .into_iter()
+            .flat_map(|(state, bits)| {
+                Self::active(kind, bits)
+                    .into_iter()
+                    .map(move |code| AlarmEvent {
+                        datalog,
+                        kind,
+                        state,
+                        bit: code.bit,
+                        code: code.code,
+                        description: code.description,
+                        time: time.clone(),
+                    })
+            })
+            .collect()
+    }
+
+    fn active(kind: AlarmKind, bits: u32) -> Vec<ActiveCode> {
+        match kind {
+            AlarmKind::Fault => FaultCodeString::active(bits),
+            AlarmKind::Warning => WarningCodeString::active(bits),
+        }
+    }
+}
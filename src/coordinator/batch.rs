@@ -0,0 +1,84 @@
+use crate::prelude::*;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// registers per read; some dongles don't answer for more
+pub const MAX_CHUNK: u16 = 40;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Space {
+    Input,
+    Hold,
+    Param,
+}
+
+// One range of a read/batch payload, eg
+// {"space": "hold", "register": 0, "count": 80}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Range {
+    #[serde(alias = "type")]
+    pub space: Space,
+    pub register: u16,
+    #[serde(default = "Range::default_count")]
+    pub count: u16,
+}
+
+impl Range {
+    fn default_count() -> u16 {
+        1
+    }
+}
+
+// every value read, keyed by space and then register
+pub type Values = BTreeMap<Space, BTreeMap<u16, u16>>;
+
+// a JSON list of ranges
+pub fn parse(payload: &str) -> Result<Vec<Range>> {
+    let ranges: Vec<Range> =
+        serde_json::from_str(payload).map_err(|err| anyhow!("payload_batch: {}", err))?;
+
+    if ranges.is_empty() {
+        bail!("payload_batch: no ranges to read");
+    }
+    for range in &ranges {
+        if range.count == 0 || range.register.checked_add(range.count - 1).is_none() {
+            bail!(
+                "payload_batch: invalid range of {} from {}",
+                range.count,
+                range.register
+            );
+        }
+    }
+
+    Ok(ranges)
+}
+
+// The reads to make for ranges, in order. Parameters are read one register at
+// a time, everything else in chunks of up to MAX_CHUNK.
+pub fn chunks(ranges: &[Range]) -> Vec<Range> {
+    let mut chunks = Vec::new();
+
+    for range in ranges {
+        let size = match range.space {
+            Space::Param => 1,
+            Space::Input | Space::Hold => MAX_CHUNK,
+        };
+
+        let end = range.register as u32 + range.count as u32;
+        let mut register = range.register as u32;
+        while register < end {
+            let count = (end - register).min(size as u32);
+            chunks.push(Range {
+                space: range.space,
+                register: register as u16,
+                count: count as u16,
+            });
+            register += count;
+        }
+    }
+
+    chunks
+}
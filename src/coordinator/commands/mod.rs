@@ -4,5 +4,5 @@ pub mod read_param;
 pub mod set_hold;
 pub mod time_register_ops;
 pub mod timesync;
-pub mod update_hold;
+pub mod transaction;
 pub mod write_param;
@@ -7,6 +7,8 @@ use lxp::{
 
 use serde::Serialize;
 
+use super::transaction::Transaction;
+
 pub struct ReadTimeRegister {
     channels: Channels,
     inverter: config::Inverter,
@@ -27,7 +29,7 @@ pub enum Action {
 }
 
 impl Action {
-    fn register(&self) -> Result<u16> {
+    pub fn register(&self) -> Result<u16> {
         use Action::*;
         match self {
             AcCharge(1) => Ok(68),
@@ -56,6 +58,25 @@ impl Action {
             ForcedDischarge(n) => format!("{}/forced_discharge/{}", datalog, n),
         }
     }
+
+    // builds the retained {"start":"HH:MM","end":"HH:MM"} message from the
+    // four bytes held in the pair of registers
+    pub fn mqtt_message(&self, datalog: Serial, values: &[u8]) -> Result<mqtt::Message> {
+        if values.len() < 4 {
+            bail!("need 4 bytes for a time range, got {}", values.len());
+        }
+
+        let payload = MqttReplyPayload {
+            start: format!("{:02}:{:02}", values[0], values[1]),
+            end: format!("{:02}:{:02}", values[2], values[3]),
+        };
+
+        Ok(mqtt::Message {
+            topic: self.mqtt_reply_topic(datalog),
+            retain: true,
+            payload: serde_json::to_string(&payload)?,
+        })
+    }
 }
 
 impl ReadTimeRegister {
@@ -90,15 +111,7 @@ impl ReadTimeRegister {
         let reply = receiver.wait_for_reply(&packet).await?;
 
         if let Packet::TranslatedData(td) = reply {
-            let payload = MqttReplyPayload {
-                start: format!("{:02}:{:02}", td.values[0], td.values[1]),
-                end: format!("{:02}:{:02}", td.values[2], td.values[3]),
-            };
-            let message = mqtt::Message {
-                topic: self.action.mqtt_reply_topic(td.datalog),
-                retain: true,
-                payload: serde_json::to_string(&payload)?,
-            };
+            let message = self.action.mqtt_message(td.datalog, &td.values)?;
             let channel_data = mqtt::ChannelData::Message(message);
 
             if self.channels.to_mqtt.send(channel_data).is_err() {
@@ -134,22 +147,23 @@ impl SetTimeRegister {
         }
     }
 
+    // start and end are written together, so a failure part way through
+    // can't leave the slot half changed
     pub async fn run(&self) -> Result<()> {
-        self.set_register(self.action.register()?, &self.values[0..2])
-            .await?;
-        self.set_register(self.action.register()? + 1, &self.values[2..4])
+        let register = self.action.register()?;
+        let start = u16::from_le_bytes([self.values[0], self.values[1]]);
+        let end = u16::from_le_bytes([self.values[2], self.values[3]]);
+
+        let replies = Transaction::new(self.channels.clone(), self.inverter.clone())
+            .set(register, start)
+            .set(register + 1, end)
+            .run()
             .await?;
 
-        // FIXME: If we only update one of the two registers, we should probably
-        // still output the change we did manage to make here.
-        let payload = MqttReplyPayload {
-            start: format!("{:02}:{:02}", self.values[0], self.values[1]),
-            end: format!("{:02}:{:02}", self.values[2], self.values[3]),
-        };
-        let message = mqtt::Message {
-            topic: self.action.mqtt_reply_topic(self.inverter.datalog),
-            retain: true,
-            payload: serde_json::to_string(&payload)?,
+        // publish what the inverter now says it has, rather than what we asked for
+        let message = match replies.first() {
+            Some(Packet::TranslatedData(td)) => self.action.mqtt_message(td.datalog, &td.values)?,
+            _ => bail!("didn't get expected reply from inverter"),
         };
         let channel_data = mqtt::ChannelData::Message(message);
 
@@ -159,41 +173,4 @@ impl SetTimeRegister {
 
         Ok(())
     }
-
-    async fn set_register(&self, register: u16, values: &[u8]) -> Result<()> {
-        let packet = Packet::TranslatedData(TranslatedData {
-            datalog: self.inverter.datalog(),
-            device_function: DeviceFunction::WriteSingle,
-            inverter: self.inverter.serial(),
-            values: values.to_vec(),
-            register,
-        });
-
-        let mut receiver = self.channels.from_inverter.subscribe();
-
-        if self
-            .channels
-            .to_inverter
-            .send(lxp::inverter::ChannelData::Packet(packet.clone()))
-            .is_err()
-        {
-            bail!("send(to_inverter) failed - channel closed?");
-        }
-
-        let reply = receiver.wait_for_reply(&packet).await?;
-        if let Packet::TranslatedData(td) = reply {
-            if td.values != values {
-                bail!(
-                    "failed to set register {}, got back value {:?} (wanted {:?})",
-                    register,
-                    td.values,
-                    values
-                );
-            }
-        } else {
-            bail!("didn't get expected reply from inverter");
-        }
-
-        Ok(())
-    }
 }
@@ -1,12 +1,35 @@
 use crate::prelude::*;
 
-use chrono::TimeZone;
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use serde::Serialize;
 
 use lxp::{
     inverter::WaitForReply,
     packet::{DeviceFunction, TranslatedData},
 };
 
+// What we found the inverter's clock set to, as published to {datalog}/clock.
+// Times are wall clock times in timezone, which is what the inverter keeps.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Clock {
+    pub inverter_time: String,
+    pub expected_time: String,
+    pub timezone: String,
+    // positive when the inverter is ahead
+    pub drift_seconds: i64,
+    pub corrected: bool,
+}
+
+impl Clock {
+    pub fn message(&self, datalog: Serial) -> Result<mqtt::Message> {
+        Ok(mqtt::Message {
+            topic: format!("{}/clock", datalog),
+            retain: true,
+            payload: serde_json::to_string(self)?,
+        })
+    }
+}
+
 pub struct TimeSync {
     channels: Channels,
     inverter: config::Inverter,
@@ -17,7 +40,81 @@ impl TimeSync {
         Self { channels, inverter }
     }
 
-    pub async fn run(&self) -> Result<()> {
+    // Corrects the inverter's clock if it is out by more than time_tolerance.
+    pub async fn run(&self) -> Result<Clock> {
+        let tolerance = chrono::Duration::seconds(self.inverter.time_tolerance() as i64);
+        self.check(Some(tolerance)).await
+    }
+
+    // Only reports on the inverter's clock.
+    pub async fn read(&self) -> Result<Clock> {
+        self.check(None).await
+    }
+
+    // Sets the inverter's clock to time, or to now if not given, however far
+    // out it is.
+    pub async fn set(&self, time: Option<NaiveDateTime>) -> Result<Clock> {
+        let mut receiver = self.channels.from_inverter.subscribe();
+
+        let expected = self.expected_time();
+        let time = time.unwrap_or(expected);
+        self.write_time(&mut receiver, time).await?;
+
+        Ok(self.clock(time, expected, true))
+    }
+
+    async fn check(&self, tolerance: Option<chrono::Duration>) -> Result<Clock> {
+        let mut receiver = self.channels.from_inverter.subscribe();
+
+        let inverter_time = self.read_time(&mut receiver).await?;
+        let expected = self.expected_time();
+        let drift = inverter_time - expected;
+
+        debug!(
+            "inverter {} time difference is {}",
+            self.inverter.datalog(),
+            drift
+        );
+
+        let corrected = match tolerance {
+            Some(limit) if drift > limit || -drift > limit => {
+                self.write_time(&mut receiver, expected).await?;
+                true
+            }
+            _ => false,
+        };
+
+        Ok(self.clock(inverter_time, expected, corrected))
+    }
+
+    // Now as the inverter's clock should show it. Comparing wall clock times
+    // rather than instants means a clock left an hour out by a DST change is
+    // seen as drift and put right.
+    fn expected_time(&self) -> NaiveDateTime {
+        self.inverter.local_time()
+    }
+
+    fn clock(
+        &self,
+        inverter_time: NaiveDateTime,
+        expected: NaiveDateTime,
+        corrected: bool,
+    ) -> Clock {
+        const FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+        Clock {
+            inverter_time: inverter_time.format(FORMAT).to_string(),
+            expected_time: expected.format(FORMAT).to_string(),
+            timezone: self
+                .inverter
+                .timezone()
+                .map_or_else(|| "local".to_owned(), |tz| tz.name().to_owned()),
+            drift_seconds: (inverter_time - expected).num_seconds(),
+            corrected,
+        }
+    }
+
+    async fn read_time(&self, receiver: &mut lxp::inverter::Receiver) -> Result<NaiveDateTime> {
         let packet = Packet::TranslatedData(TranslatedData {
             datalog: self.inverter.datalog(),
             device_function: DeviceFunction::ReadHold,
@@ -26,83 +123,64 @@ impl TimeSync {
             values: vec![3, 0],
         });
 
-        let mut receiver = self.channels.from_inverter.subscribe();
+        self.send(&packet)?;
 
-        if self
-            .channels
-            .to_inverter
-            .send(lxp::inverter::ChannelData::Packet(packet.clone()))
-            .is_err()
-        {
-            bail!("send(to_inverter) failed - channel closed?");
-        }
+        let td = match receiver.wait_for_reply(&packet).await? {
+            Packet::TranslatedData(td) if td.values.len() >= 6 => td,
+            _ => bail!("didn't get expected reply from inverter"),
+        };
 
-        if let Packet::TranslatedData(td) = receiver.wait_for_reply(&packet).await? {
-            let year = td.values[0] as u32;
-            let month = td.values[1] as u32;
-            let day = td.values[2] as u32;
-            let hour = td.values[3] as u32;
-            let minute = td.values[4] as u32;
-            let second = td.values[5] as u32;
-
-            // pretend inverter is in Utc to avoid ambiguous time conversions and timezones
-            let dt = chrono::Utc
-                .with_ymd_and_hms(2000 + year as i32, month, day, hour, minute, second)
-                .unwrap();
-
-            // get current time in Utc, then add the appropriate offset so we can compare to
-            // inverter time correctly
-            let offset_in_sec =
-                chrono::Duration::seconds(chrono::Local::now().offset().local_minus_utc() as i64);
-            let now = Utils::utc() + offset_in_sec;
-
-            debug!(
-                "inverter {} time difference is {}",
-                self.inverter.datalog(),
-                dt - now
-            );
-
-            let limit = chrono::Duration::seconds(120);
-
-            if dt - now > limit || now - dt > limit {
-                let packet = self.set_time_packet(now);
-
-                if self
-                    .channels
-                    .to_inverter
-                    .send(lxp::inverter::ChannelData::Packet(packet.clone()))
-                    .is_err()
-                {
-                    bail!("send(to_inverter) failed - channel closed?");
-                }
-
-                if let Packet::TranslatedData(_) = receiver.wait_for_reply(&packet).await? {
-                    debug!("time set ok");
-                } else {
-                    warn!("time set didn't get confirmation reply!");
-                }
-            }
+        let v = |i: usize| td.values[i] as u32;
+        NaiveDate::from_ymd_opt(2000 + v(0) as i32, v(1), v(2))
+            .and_then(|date| date.and_hms_opt(v(3), v(4), v(5)))
+            .ok_or_else(|| anyhow!("inverter sent invalid time {:?}", &td.values[0..6]))
+    }
+
+    async fn write_time(
+        &self,
+        receiver: &mut lxp::inverter::Receiver,
+        time: NaiveDateTime,
+    ) -> Result<()> {
+        let packet = self.set_time_packet(time);
+
+        self.send(&packet)?;
+
+        if let Packet::TranslatedData(_) = receiver.wait_for_reply(&packet).await? {
+            debug!("time set ok");
+        } else {
+            warn!("time set didn't get confirmation reply!");
         }
 
         Ok(())
     }
 
-    fn set_time_packet(&self, now: chrono::DateTime<chrono::Utc>) -> Packet {
-        use chrono::{Datelike, Timelike};
-
+    fn set_time_packet(&self, time: NaiveDateTime) -> Packet {
         Packet::TranslatedData(TranslatedData {
             datalog: self.inverter.datalog(),
             device_function: DeviceFunction::WriteMulti,
             inverter: self.inverter.serial(),
             register: 12,
             values: vec![
-                (now.year() - 2000) as u8,
-                now.month() as u8,
-                now.day() as u8,
-                now.hour() as u8,
-                now.minute() as u8,
-                now.second() as u8,
+                (time.year() - 2000) as u8,
+                time.month() as u8,
+                time.day() as u8,
+                time.hour() as u8,
+                time.minute() as u8,
+                time.second() as u8,
             ],
         })
     }
+
+    fn send(&self, packet: &Packet) -> Result<()> {
+        if self
+            .channels
+            .to_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet.clone()))
+            .is_err()
+        {
+            bail!("send(to_inverter) failed - channel closed?");
+        }
+
+        Ok(())
+    }
 }
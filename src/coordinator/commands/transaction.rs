@@ -0,0 +1,281 @@
+use crate::prelude::*;
+
+use lxp::{
+    inverter::WaitForReply,
+    packet::{DeviceFunction, TranslatedData},
+};
+use std::collections::BTreeMap;
+
+// Returned when a transaction fails, saying what became of the registers so
+// it can be passed back on the result topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionFailed {
+    pub error: String,
+    // Ok if the original values were written back, or why they couldn't be
+    pub rollback: std::result::Result<(), String>,
+}
+
+impl std::fmt::Display for TransactionFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.rollback {
+            Ok(()) => write!(f, "{}; rolled back", self.error),
+            Err(err) => write!(f, "{}; rollback failed: {}", self.error, err),
+        }
+    }
+}
+
+impl std::error::Error for TransactionFailed {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Update {
+    Value(u16),
+    Bits { set: u16, clear: u16 },
+}
+
+impl Update {
+    fn applied(&self, current: u16) -> u16 {
+        match *self {
+            Self::Value(value) => value,
+            Self::Bits { set, clear } => (current | set) & !clear,
+        }
+    }
+}
+
+// Writes several holding registers as one change. The original values are
+// always read from the inverter first, then each run of consecutive registers is written in one go
+// (WriteSingle for a lone register, otherwise WriteMulti) and checked. If any
+// of that fails, whatever was written is put back to the original values.
+pub struct Transaction {
+    channels: Channels,
+    inverter: config::Inverter,
+    writes: BTreeMap<u16, Update>,
+}
+
+impl Transaction {
+    pub fn new(channels: Channels, inverter: config::Inverter) -> Self {
+        Self {
+            channels,
+            inverter,
+            writes: BTreeMap::new(),
+        }
+    }
+
+    pub fn set<U>(mut self, register: U, value: u16) -> Self
+    where
+        U: Into<u16>,
+    {
+        self.writes.insert(register.into(), Update::Value(value));
+        self
+    }
+
+    // switch bits on and/or off, leaving the rest of the register alone.
+    // several calls for the same register are combined into one write.
+    pub fn update_bits<U>(mut self, register: U, set: u16, clear: u16) -> Self
+    where
+        U: Into<u16>,
+    {
+        let register = register.into();
+        let write = match self.writes.get(&register) {
+            Some(Update::Value(value)) => Update::Value((value | set) & !clear),
+            Some(Update::Bits { set: s, clear: c }) => Update::Bits {
+                set: (s | set) & !clear,
+                clear: (c | clear) & !set,
+            },
+            None => Update::Bits { set, clear },
+        };
+        self.writes.insert(register, write);
+        self
+    }
+
+    pub fn registers(&self) -> Vec<u16> {
+        self.writes.keys().copied().collect()
+    }
+
+    // Returns the reply which confirmed each run of registers; the WriteSingle
+    // reply for a lone register, otherwise the ReadHold used to check it.
+    pub async fn run(&self) -> Result<Vec<Packet>> {
+        let mut receiver = self.channels.from_inverter.subscribe();
+
+        let runs = self.runs();
+
+        // nothing has been written yet, so failures here need no rollback.
+        // the originals always come from the inverter, as anything cached
+        // could be out of date by the time it's put back
+        let mut originals = Vec::new();
+        for (register, writes) in &runs {
+            let count = Self::count(*register, writes)?;
+            let (_, values) = self.read(&mut receiver, *register, count).await?;
+            originals.push(values);
+        }
+
+        let mut written = Vec::new();
+        let mut replies = Vec::new();
+        for ((register, writes), originals) in runs.iter().zip(originals) {
+            let values: Vec<u16> = writes
+                .iter()
+                .zip(&originals)
+                .map(|(write, original)| write.applied(*original))
+                .collect();
+
+            // a write which times out may still have happened, so it counts
+            // as written for rollback whatever the outcome
+            written.push((*register, originals));
+
+            match self.write(&mut receiver, *register, &values).await {
+                Ok(reply) => replies.push(reply),
+                Err(err) => return Err(self.rollback(&mut receiver, &written, err).await),
+            }
+        }
+
+        Ok(replies)
+    }
+
+    // consecutive registers, and the writes for each of them
+    fn runs(&self) -> Vec<(u16, Vec<Update>)> {
+        let mut runs: Vec<(u16, Vec<Update>)> = Vec::new();
+
+        for (register, write) in &self.writes {
+            match runs.last_mut() {
+                Some((start, writes)) if *start as usize + writes.len() == *register as usize => {
+                    writes.push(*write)
+                }
+                _ => runs.push((*register, vec![*write])),
+            }
+        }
+
+        runs
+    }
+
+    // how many registers a run covers, refusing one which would go past the
+    // last register
+    fn count(register: u16, writes: &[Update]) -> Result<u16> {
+        u16::try_from(writes.len())
+            .ok()
+            .filter(|count| register.checked_add(*count).is_some())
+            .ok_or_else(|| {
+                coordinator::safeguards::WriteRejected(format!(
+                    "{} registers from {} go past the last register",
+                    writes.len(),
+                    register
+                ))
+                .into()
+            })
+    }
+
+    async fn write(
+        &self,
+        receiver: &mut lxp::inverter::Receiver,
+        register: u16,
+        values: &[u16],
+    ) -> Result<Packet> {
+        let device_function = if values.len() == 1 {
+            DeviceFunction::WriteSingle
+        } else {
+            DeviceFunction::WriteMulti
+        };
+        let packet = Packet::TranslatedData(TranslatedData {
+            datalog: self.inverter.datalog(),
+            device_function,
+            inverter: self.inverter.serial(),
+            register,
+            values: values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        });
+
+        self.send(&packet)?;
+        let reply = receiver.wait_for_reply(&packet).await?;
+
+        // WriteSingle replies carry the value written; WriteMulti replies only
+        // say how many registers were written, so read them back to check.
+        let (reply, got) = if values.len() == 1 {
+            let got = vec![reply.value()];
+            (reply, got)
+        } else {
+            self.read(receiver, register, values.len() as u16).await?
+        };
+
+        if got != values {
+            bail!(
+                "failed to update register {}, got back {:?} (wanted {:?})",
+                register,
+                got,
+                values
+            );
+        }
+
+        Ok(reply)
+    }
+
+    // Puts back the original values of every run we tried to write, last
+    // first, and returns error as a TransactionFailed saying how that went.
+    async fn rollback(
+        &self,
+        receiver: &mut lxp::inverter::Receiver,
+        written: &[(u16, Vec<u16>)],
+        error: Error,
+    ) -> Error {
+        let mut failures = Vec::new();
+        for (register, originals) in written.iter().rev() {
+            if let Err(err) = self.write(receiver, *register, originals).await {
+                failures.push(err.to_string());
+            }
+        }
+
+        let rollback = if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures.join(", "))
+        };
+
+        TransactionFailed {
+            error: error.to_string(),
+            rollback,
+        }
+        .into()
+    }
+
+    async fn read(
+        &self,
+        receiver: &mut lxp::inverter::Receiver,
+        register: u16,
+        count: u16,
+    ) -> Result<(Packet, Vec<u16>)> {
+        let packet = Packet::TranslatedData(TranslatedData {
+            datalog: self.inverter.datalog(),
+            device_function: DeviceFunction::ReadHold,
+            inverter: self.inverter.serial(),
+            register,
+            values: count.to_le_bytes().to_vec(),
+        });
+
+        self.send(&packet)?;
+        let reply = receiver.wait_for_reply(&packet).await?;
+
+        let values: Vec<u16> = match &reply {
+            Packet::TranslatedData(td) => td.pairs().into_iter().map(|(_, v)| v).collect(),
+            _ => bail!("didn't get expected reply from inverter"),
+        };
+        if values.len() != count as usize {
+            bail!(
+                "asked for {} registers from {}, got {}",
+                count,
+                register,
+                values.len()
+            );
+        }
+
+        Ok((reply, values))
+    }
+
+    fn send(&self, packet: &Packet) -> Result<()> {
+        if self
+            .channels
+            .to_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet.clone()))
+            .is_err()
+        {
+            bail!("send(to_inverter) failed - channel closed?");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use crate::prelude::*;
+
+use lxp::packet::ReadInputAll;
+use serde::{Deserialize, Serialize};
+
+// The energy counters we track, in the same order as EnergyStore::readings.
+const QUANTITIES: [&str; 8] = [
+    "pv", "inv", "rec", "chg", "dischg", "eps", "to_grid", "to_user",
+];
+
+// Clean, monotonic energy totals (kWh) for one inverter, along with how much
+// each went up by since the previous ReadInputAll.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EnergyTotals {
+    pub e_pv_total: f64,
+    pub e_pv_interval: f64,
+    pub e_inv_total: f64,
+    pub e_inv_interval: f64,
+    pub e_rec_total: f64,
+    pub e_rec_interval: f64,
+    pub e_chg_total: f64,
+    pub e_chg_interval: f64,
+    pub e_dischg_total: f64,
+    pub e_dischg_interval: f64,
+    pub e_eps_total: f64,
+    pub e_eps_interval: f64,
+    pub e_to_grid_total: f64,
+    pub e_to_grid_interval: f64,
+    pub e_to_user_total: f64,
+    pub e_to_user_interval: f64,
+
+    pub datalog: Serial,
+    pub time: UnixTime,
+}
+
+impl EnergyTotals {
+    // Just the energy fields, for merging into other inputs.
+    pub fn fields(&self) -> Result<serde_json::Map<String, serde_json::Value>> {
+        let mut fields = match serde_json::to_value(self)? {
+            serde_json::Value::Object(map) => map,
+            _ => unreachable!(),
+        };
+        fields.remove("datalog");
+        fields.remove("time");
+
+        Ok(fields)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+struct Counter {
+    day: f64,
+    all: f64,
+    total: f64,
+}
+
+#[derive(Deserialize, Serialize)]
+struct State {
+    time: i64,
+    counters: [Counter; QUANTITIES.len()],
+}
+
+// Turns the raw e_*_day and e_*_all counters into totals which only ever go
+// up. e_*_all is preferred, but it occasionally jumps (eg after a firmware
+// reset), and e_*_day resets at the inverter's idea of midnight, so each new
+// reading is checked against what the inverter could plausibly have done
+// since the last one. Anything that doesn't fit is ignored and the counters
+// are rebaselined from there.
+//
+// The counters can be saved and loaded again, so after a restart the totals
+// carry on from where they were instead of starting over from e_*_all.
+#[derive(Default)]
+pub struct EnergyStore {
+    states: std::collections::HashMap<Serial, State>,
+}
+
+impl EnergyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A missing file is a first run, so starts empty.
+    pub fn load(file: &str) -> Result<Self> {
+        let content = match std::fs::read_to_string(file) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::new()),
+            Err(err) => bail!("error reading {}: {}", file, err),
+        };
+
+        let saved: std::collections::HashMap<String, State> = serde_json::from_str(&content)
+            .map_err(|err| anyhow!("error reading {}: {}", file, err))?;
+        let states = saved
+            .into_iter()
+            .map(|(datalog, state)| Ok((Serial::from_str(&datalog)?, state)))
+            .collect::<Result<_>>()?;
+
+        Ok(Self { states })
+    }
+
+    pub fn save(&self, file: &str) -> Result<()> {
+        let saved: std::collections::BTreeMap<String, &State> = self
+            .states
+            .iter()
+            .map(|(datalog, state)| (datalog.to_string(), state))
+            .collect();
+
+        // written alongside and renamed over, so a crash part way through
+        // can't leave a truncated file behind
+        let tmp = format!("{}.tmp", file);
+        std::fs::write(&tmp, serde_json::to_string(&saved)?)
+            .map_err(|err| anyhow!("error writing {}: {}", tmp, err))?;
+        std::fs::rename(&tmp, file).map_err(|err| anyhow!("error writing {}: {}", file, err))?;
+
+        Ok(())
+    }
+
+    // max_power is the most any single flow could be, in W.
+    pub fn update(&mut self, input: &ReadInputAll, max_power: u32) -> EnergyTotals {
+        let now = input.time.0.timestamp();
+        let readings = Self::readings(input);
+        let mut intervals = [0.0; QUANTITIES.len()];
+
+        match self.states.get_mut(&input.datalog) {
+            Some(state) => {
+                let elapsed = (now - state.time).max(0) as f64;
+                // the counters have 0.1kWh resolution, so allow for that too
+                let limit = max_power as f64 / 1000.0 * elapsed / 3600.0 + 0.1;
+
+                for (i, (day, all)) in readings.into_iter().enumerate() {
+                    let counter = &mut state.counters[i];
+                    let delta = match Self::delta(counter, day, all, limit) {
+                        Some(delta) => delta,
+                        None => {
+                            warn!(
+                                "{}: ignoring implausible e_{} reading (day {} -> {}, all {} -> {})",
+                                input.datalog, QUANTITIES[i], counter.day, day, counter.all, all
+                            );
+                            0.0
+                        }
+                    };
+
+                    counter.day = day;
+                    counter.all = all;
+                    counter.total += delta;
+                    intervals[i] = delta;
+                }
+
+                state.time = now;
+            }
+            None => {
+                // nothing to compare against yet; start the totals from the
+                // inverter's own lifetime counters
+                let mut counters = [Counter::default(); QUANTITIES.len()];
+                for (counter, (day, all)) in counters.iter_mut().zip(readings) {
+                    *counter = Counter {
+                        day,
+                        all,
+                        total: all,
+                    };
+                }
+                self.states.insert(
+                    input.datalog,
+                    State {
+                        time: now,
+                        counters,
+                    },
+                );
+            }
+        }
+
+        let counters = &self.states[&input.datalog].counters;
+        let total = |i: usize| Utils::round(counters[i].total, 1);
+        let interval = |i: usize| Utils::round(intervals[i], 1);
+
+        EnergyTotals {
+            e_pv_total: total(0),
+            e_pv_interval: interval(0),
+            e_inv_total: total(1),
+            e_inv_interval: interval(1),
+            e_rec_total: total(2),
+            e_rec_interval: interval(2),
+            e_chg_total: total(3),
+            e_chg_interval: interval(3),
+            e_dischg_total: total(4),
+            e_dischg_interval: interval(4),
+            e_eps_total: total(5),
+            e_eps_interval: interval(5),
+            e_to_grid_total: total(6),
+            e_to_grid_interval: interval(6),
+            e_to_user_total: total(7),
+            e_to_user_interval: interval(7),
+            datalog: input.datalog,
+            time: input.time.clone(),
+        }
+    }
+
+    fn readings(input: &ReadInputAll) -> [(f64, f64); QUANTITIES.len()] {
+        [
+            (input.e_pv_day, input.e_pv_all),
+            (input.e_inv_day, input.e_inv_all),
+            (input.e_rec_day, input.e_rec_all),
+            (input.e_chg_day, input.e_chg_all),
+            (input.e_dischg_day, input.e_dischg_all),
+            (input.e_eps_day, input.e_eps_all),
+            (input.e_to_grid_day, input.e_to_grid_all),
+            (input.e_to_user_day, input.e_to_user_all),
+        ]
+    }
+
+    fn delta(previous: &Counter, day: f64, all: f64, limit: f64) -> Option<f64> {
+        let plausible = |delta: f64| (0.0..=limit).contains(&delta);
+
+        let all_delta = all - previous.all;
+        let day_delta = day - previous.day;
+
+        if plausible(all_delta) {
+            Some(all_delta)
+        } else if plausible(day_delta) {
+            // lifetime counter has jumped, but today's still makes sense
+            Some(day_delta)
+        } else if day < previous.day && plausible(day) {
+            // today's counter reset at midnight; it holds everything since then
+            Some(day)
+        } else {
+            None
+        }
+    }
+}
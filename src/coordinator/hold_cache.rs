@@ -0,0 +1,131 @@
+use crate::prelude::*;
+
+use lxp::packet::{DeviceFunction, Register, TranslatedData};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// A holding register which doesn't hold what we last saw in it; most likely
+// changed on the inverter's LCD or through the vendor app. Values are scaled
+// the same as the hold/{register} topics. old is None if we hadn't seen the
+// register before.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SettingChange {
+    pub datalog: Serial,
+    pub register: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub old: Option<f64>,
+    pub new: f64,
+    pub time: UnixTime,
+}
+
+impl SettingChange {
+    fn new(datalog: Serial, register: u16, old: Option<u16>, new: u16) -> Self {
+        Self {
+            datalog,
+            register,
+            name: Register::try_from(register)
+                .ok()
+                .map(|r| format!("{:?}", r)),
+            old: old.map(|value| Self::scaled(register, value)),
+            new: Self::scaled(register, new),
+            time: UnixTime::now(),
+        }
+    }
+
+    fn scaled(register: u16, value: u16) -> f64 {
+        match lxp::packet::find_register_config(register) {
+            Some(config) => value as f64 * config.scale,
+            None => value as f64,
+        }
+    }
+}
+
+// The last value seen for each holding register of each inverter, and when we
+// saw it. Anything the inverter sends back is recorded, whoever asked for it,
+// so we can often answer from here instead of going back to the inverter.
+#[derive(Default)]
+pub struct HoldCache {
+    inverters: HashMap<Serial, HashMap<u16, (u16, Instant)>>,
+}
+
+impl HoldCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // ReadHold and WriteSingle replies carry the register values. WriteMulti
+    // replies only tell us how many registers were written, not what to, so
+    // those registers are forgotten and the next lookup goes to the inverter.
+    pub fn update(&mut self, td: &TranslatedData) {
+        let registers = self.inverters.entry(td.datalog).or_default();
+
+        match td.device_function {
+            DeviceFunction::ReadHold | DeviceFunction::WriteSingle => {
+                let now = Instant::now();
+                for (register, value) in td.pairs() {
+                    registers.insert(register, (value, now));
+                }
+            }
+            DeviceFunction::WriteMulti => {
+                let count = td.value();
+                for register in td.register..td.register.saturating_add(count) {
+                    registers.remove(&register);
+                }
+            }
+            DeviceFunction::ReadInput => {}
+        }
+    }
+
+    pub fn get(&self, datalog: Serial, register: u16, max_age: Duration) -> Option<u16> {
+        let (value, time) = self.inverters.get(&datalog)?.get(&register)?;
+
+        if time.elapsed() <= max_age {
+            Some(*value)
+        } else {
+            None
+        }
+    }
+
+    // Compares a ReadHold reply against what we have, however old, and returns
+    // the registers which differ or which we've never seen. Call this before
+    // update(), which would overwrite the old values.
+    pub fn changes(&self, td: &TranslatedData) -> Vec<SettingChange> {
+        let registers = self.inverters.get(&td.datalog);
+
+        td.pairs()
+            .into_iter()
+            .filter_map(|(register, value)| {
+                let old = registers
+                    .and_then(|r| r.get(&register))
+                    .map(|(old, _)| *old);
+
+                if old == Some(value) {
+                    None
+                } else {
+                    Some(SettingChange::new(td.datalog, register, old, value))
+                }
+            })
+            .collect()
+    }
+
+    // Only returns something if every register in the range is fresh.
+    pub fn get_range(
+        &self,
+        datalog: Serial,
+        register: u16,
+        count: u16,
+        max_age: Duration,
+    ) -> Option<Vec<u16>> {
+        (register..register.saturating_add(count))
+            .map(|r| self.get(datalog, r, max_age))
+            .collect()
+    }
+
+    // while disconnected, settings could be changed on the inverter itself
+    // without us seeing it
+    pub fn clear(&mut self, datalog: Serial) {
+        self.inverters.remove(&datalog);
+    }
+}
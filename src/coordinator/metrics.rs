@@ -0,0 +1,98 @@
+use crate::prelude::*;
+
+use lxp::packet::ReadInputAll;
+use serde::Serialize;
+
+// Energy-flow figures worked out from a ReadInputAll, so that dashboards
+// don't all have to repeat the same arithmetic.
+//
+// Powers are in W and describe the moment the inputs were read. Percentages
+// are over today's energy counters, and are left out when there is nothing
+// to divide by yet (eg self-consumption before the sun comes up).
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HouseholdMetrics {
+    pub p_consumption: i32, // house load
+    pub p_pv_to_battery: i32,
+    pub p_pv_to_load: i32,
+    pub p_grid_to_battery: i32,
+
+    pub e_consumption_day: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub self_consumption_pct: Option<f64>, // share of PV used on site
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub self_sufficiency_pct: Option<f64>, // share of consumption not from grid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery_efficiency_pct: Option<f64>, // round-trip, discharged / charged
+}
+
+impl HouseholdMetrics {
+    pub fn new(input: &ReadInputAll) -> Self {
+        let p_pv = input.p_pv as i32;
+        let p_charge = input.p_charge as i32;
+        let p_to_grid = input.p_to_grid as i32;
+        let p_to_user = input.p_to_user as i32;
+
+        // what the inverter puts out, less what it takes back for AC
+        // charging, plus net grid import
+        let p_consumption =
+            (input.p_inv as i32 - input.p_rec as i32 + p_to_user - p_to_grid).max(0);
+
+        // PV is assumed to charge the battery before anything else; whatever
+        // charging is left over must have come in over AC
+        let p_pv_to_battery = (p_charge - input.p_rec as i32).clamp(0, p_pv);
+        let p_pv_to_load = (p_pv - p_pv_to_battery - p_to_grid).clamp(0, p_consumption);
+        let p_grid_to_battery = (p_charge - p_pv_to_battery).clamp(0, p_to_user);
+
+        let e_consumption_day = Self::e_consumption_day(
+            input.e_inv_day,
+            input.e_rec_day,
+            input.e_to_user_day,
+            input.e_to_grid_day,
+        );
+
+        Self {
+            p_consumption,
+            p_pv_to_battery,
+            p_pv_to_load,
+            p_grid_to_battery,
+            e_consumption_day,
+            self_consumption_pct: Self::percentage(
+                input.e_pv_day - input.e_to_grid_day,
+                input.e_pv_day,
+            ),
+            self_sufficiency_pct: Self::percentage(
+                e_consumption_day - input.e_to_user_day,
+                e_consumption_day,
+            ),
+            battery_efficiency_pct: Self::percentage(input.e_dischg_day, input.e_chg_day),
+        }
+    }
+
+    // today's house load, worked out the same way as p_consumption
+    pub fn e_consumption_day(
+        e_inv_day: f64,
+        e_rec_day: f64,
+        e_to_user_day: f64,
+        e_to_grid_day: f64,
+    ) -> f64 {
+        Utils::round(
+            (e_inv_day - e_rec_day + e_to_user_day - e_to_grid_day).max(0.0),
+            1,
+        )
+    }
+
+    pub fn fields(&self) -> Result<serde_json::Map<String, serde_json::Value>> {
+        match serde_json::to_value(self)? {
+            serde_json::Value::Object(map) => Ok(map),
+            _ => unreachable!(),
+        }
+    }
+
+    fn percentage(part: f64, whole: f64) -> Option<f64> {
+        if whole > 0.0 {
+            Some(Utils::round((part / whole * 100.0).clamp(0.0, 100.0), 1))
+        } else {
+            None
+        }
+    }
+}
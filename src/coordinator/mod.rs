@@ -1,6 +1,15 @@
 use crate::prelude::*;
 
+pub mod alarms;
+pub mod batch;
 pub mod commands;
+pub mod energy;
+pub mod hold_cache;
+pub mod metrics;
+pub mod presets;
+pub mod safeguards;
+pub mod schedule;
+pub mod write_limiter;
 
 use lxp::packet::{DeviceFunction, TcpFunction};
 
@@ -11,18 +20,53 @@ pub enum ChannelData {
 
 pub type InputsStore = std::collections::HashMap<Serial, lxp::packet::ReadInputs>;
 
+// holding registers are read in blocks of 40, starting at each of these
+pub const HOLD_BLOCKS: [u16; 6] = [0, 40, 80, 120, 160, 200];
+
+// how long inputs/all waits for the ReadInputAll2 of the same read, which
+// inverters without those registers never send
+const INPUTS_ALL2_WAIT: std::time::Duration = std::time::Duration::from_secs(5);
+
+// how often the energy counters are saved to energy_state_file, besides at
+// shutdown
+const ENERGY_SAVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(300);
+
 pub struct Coordinator {
     config: ConfigWrapper,
     channels: Channels,
+    hold_cache: RefCell<hold_cache::HoldCache>,
+    // (datalog, register) of ReadHolds sent by holdings_refresher which
+    // haven't been replied to yet
+    refreshing: RefCell<std::collections::HashSet<(Serial, u16)>>,
+    // the last preset applied to each inverter, and whether the registers
+    // matched it when we last said so
+    active_presets: RefCell<std::collections::HashMap<Serial, (presets::Preset, Option<bool>)>>,
+    write_limiter: RefCell<write_limiter::WriteLimiter>,
+    // when to give up waiting for ReadInputAll2 and publish each inverter's
+    // inputs/all without it
+    pending_inputs_all: RefCell<std::collections::HashMap<Serial, tokio::time::Instant>>,
 }
 
 impl Coordinator {
     pub fn new(config: ConfigWrapper, channels: Channels) -> Self {
-        Self { config, channels }
+        Self {
+            config,
+            channels,
+            hold_cache: RefCell::new(hold_cache::HoldCache::new()),
+            refreshing: RefCell::new(std::collections::HashSet::new()),
+            active_presets: RefCell::new(std::collections::HashMap::new()),
+            write_limiter: RefCell::new(write_limiter::WriteLimiter::new()),
+            pending_inputs_all: RefCell::new(std::collections::HashMap::new()),
+        }
     }
 
     pub async fn start(&self) -> Result<()> {
-        futures::try_join!(self.inverter_receiver(), self.mqtt_receiver())?;
+        futures::try_join!(
+            self.inverter_receiver(),
+            self.mqtt_receiver(),
+            self.holdings_refresher(),
+            self.pending_writes()
+        )?;
 
         Ok(())
     }
@@ -43,7 +87,10 @@ impl Coordinator {
             match receiver.recv().await? {
                 mqtt::ChannelData::Shutdown => break,
                 mqtt::ChannelData::Message(message) => {
-                    let _ = self.process_message(message).await;
+                    let _ = self.process_message(message, None).await;
+                }
+                mqtt::ChannelData::WithReply(message, reply) => {
+                    let _ = self.process_message(message, Some(reply)).await;
                 }
             }
         }
@@ -51,26 +98,82 @@ impl Coordinator {
         Ok(())
     }
 
-    async fn process_message(&self, message: mqtt::Message) -> Result<()> {
+    // Runs the command in message for each inverter it's for, publishing each
+    // result. reply is where a v5 caller wants those results too.
+    async fn process_message(
+        &self,
+        message: mqtt::Message,
+        reply: Option<mqtt::Reply>,
+    ) -> Result<()> {
+        let (message, envelope) = message.split_envelope();
+
         for inverter in self.config.inverters_for_message(&message)? {
-            match message.to_command(inverter) {
+            let command = match &envelope {
+                Some(envelope) if envelope.missing_value => Err(anyhow!("missing value")),
+                _ => message.to_command(inverter.clone()),
+            };
+
+            match command {
                 Ok(command) => {
                     debug!("parsed command {:?}", command);
 
-                    let topic_reply = command.to_result_topic();
+                    // set/preset can take its name from the payload, so
+                    // answer on the topic the caller will be listening to
+                    let topic_reply = match command {
+                        Command::SetPreset(_, _) => message.to_result_topic(&inverter)?,
+                        _ => command.to_result_topic(),
+                    };
+                    // a batch is no use without what it read, so that's its reply
+                    // even without an envelope
+                    let reply_with_value = matches!(command, Command::ReadBatch(_, _));
+                    let started = std::time::Instant::now();
                     let result = self.process_command(command).await;
 
-                    let reply = mqtt::ChannelData::Message(mqtt::Message {
-                        topic: topic_reply,
-                        retain: false,
-                        payload: if result.is_ok() { "OK" } else { "FAIL" }.to_string(),
-                    });
-                    if self.channels.to_mqtt.send(reply).is_err() {
-                        bail!("send(to_mqtt) failed - channel closed?");
+                    // a held back write gets a second result once it's made
+                    if let Some(write_limiter::WriteSkipped::Coalesced { register, .. }) =
+                        result.as_ref().err().and_then(|err| err.downcast_ref())
+                    {
+                        self.write_limiter.borrow_mut().reply_to(
+                            inverter.datalog(),
+                            *register,
+                            write_limiter::Reply {
+                                topic: topic_reply.clone(),
+                                envelope: envelope.clone(),
+                            },
+                        );
+                    }
+
+                    let (result, note) = Self::skipped_to_note(result);
+                    let payload = Self::result_payload(
+                        envelope.as_ref(),
+                        &result,
+                        note,
+                        started.elapsed(),
+                        reply_with_value,
+                    )?;
+
+                    if let Err(err) = &result {
+                        warn!("{}", err);
                     }
+
+                    self.publish_result(topic_reply, payload, reply.clone())?;
                 }
                 Err(err) => {
                     error!("{:?}", err);
+
+                    // someone who sent an envelope or a response topic is
+                    // waiting for an answer, so tell them why nothing happened
+                    if envelope.is_some() || reply.is_some() {
+                        let payload = Self::result_payload(
+                            envelope.as_ref(),
+                            &Err(err),
+                            None,
+                            std::time::Duration::ZERO,
+                            false,
+                        )?;
+                        let topic = message.to_result_topic(&inverter)?;
+                        self.publish_result(topic, payload, reply.clone())?;
+                    }
                 }
             }
         }
@@ -78,7 +181,140 @@ impl Coordinator {
         Ok(())
     }
 
-    async fn process_command(&self, command: Command) -> Result<()> {
+    fn publish_result(
+        &self,
+        topic: String,
+        payload: String,
+        reply: Option<mqtt::Reply>,
+    ) -> Result<()> {
+        // commands also come from the scheduler and automation, which work
+        // without MQTT
+        if !self.config.mqtt().enabled() {
+            return Ok(());
+        }
+
+        let message = mqtt::Message {
+            topic,
+            retain: false,
+            payload,
+        };
+        let data = match reply {
+            Some(reply) => mqtt::ChannelData::WithReply(message, reply),
+            None => mqtt::ChannelData::Message(message),
+        };
+        if self.channels.to_mqtt.send(data).is_err() {
+            bail!("send(to_mqtt) failed - channel closed?");
+        }
+
+        Ok(())
+    }
+
+    // JSON for commands which came in an envelope, otherwise plain OK or FAIL
+    fn result_payload(
+        envelope: Option<&mqtt::CommandEnvelope>,
+        result: &Result<Option<serde_json::Value>>,
+        note: Option<String>,
+        duration: std::time::Duration,
+        reply_with_value: bool,
+    ) -> Result<String> {
+        Ok(match envelope {
+            Some(envelope) => {
+                let mut result = envelope.result(result, duration);
+                result.note = note;
+                serde_json::to_string(&result)?
+            }
+            None => match result {
+                Ok(Some(value)) if reply_with_value => value.to_string(),
+                Ok(_) => match note {
+                    Some(note) => format!("OK: {}", note),
+                    None => "OK".to_string(),
+                },
+                // tell the caller why we refused or what became of a failed
+                // transaction, but keep plain FAIL for anything else as
+                // that's what existing users expect
+                Err(err) => match Self::failure_reason(err) {
+                    Some(reason) => format!("FAIL: {}", reason),
+                    None => "FAIL".to_string(),
+                },
+            },
+        })
+    }
+
+    // tells everyone waiting on a held back write what became of it. Nobody
+    // listening any more is no reason to stop writing the rest.
+    fn reply_pending(
+        &self,
+        pending: &write_limiter::Pending,
+        result: &Result<Option<serde_json::Value>>,
+        note: String,
+    ) {
+        for reply in &pending.replies {
+            let published = Self::result_payload(
+                reply.envelope.as_ref(),
+                result,
+                Some(note.clone()),
+                std::time::Duration::ZERO,
+                false,
+            )
+            .and_then(|payload| self.publish_result(reply.topic.clone(), payload, None));
+            if let Err(err) = published {
+                warn!("could not reply on {}: {}", reply.topic, err);
+            }
+        }
+    }
+
+    // A write which was skipped or held back isn't a failure, but the result
+    // should say what happened to it.
+    fn skipped_to_note(
+        result: Result<Option<serde_json::Value>>,
+    ) -> (Result<Option<serde_json::Value>>, Option<String>) {
+        match result {
+            Err(err) => match err.downcast::<write_limiter::WriteSkipped>() {
+                Ok(skipped) => (Ok(skipped.value()), Some(skipped.to_string())),
+                Err(err) => (Err(err), None),
+            },
+            result => (result, None),
+        }
+    }
+
+    // Why a command failed, for the errors worth passing back on the result topic.
+    fn failure_reason(err: &Error) -> Option<String> {
+        if let Some(rejected) = err.downcast_ref::<safeguards::WriteRejected>() {
+            Some(rejected.to_string())
+        } else {
+            err.downcast_ref::<commands::transaction::TransactionFailed>()
+                .map(|failed| failed.to_string())
+        }
+    }
+
+    // Returns whatever the inverter sent back, if anything, for JSON results.
+    async fn process_command(&self, command: Command) -> Result<Option<serde_json::Value>> {
+        // presets make several writes, so report on each of them instead
+        if let Command::SetPreset(inverter, name) = command {
+            return self.set_preset(inverter, &name).await;
+        }
+        // and a schedule replies with the whole of the new schedule
+        if let Command::SetSchedule(inverter, schedule) = command {
+            return self.set_schedule(inverter, &schedule).await;
+        }
+        // clock commands reply with what they found the clock set to
+        if let Command::ReadTime(inverter) = command {
+            return self.clock(inverter, None).await;
+        }
+        if let Command::SetTime(inverter, time) = command {
+            return self.clock(inverter, Some(time)).await;
+        }
+        // batches reply with everything they read
+        if let Command::ReadBatch(inverter, ranges) = command {
+            return self.read_batch(inverter, &ranges).await;
+        }
+
+        let packet = self.run_command(command).await?;
+
+        Ok(packet.as_ref().and_then(Self::readback))
+    }
+
+    async fn run_command(&self, command: Command) -> Result<Option<Packet>> {
         use commands::time_register_ops::Action;
         use lxp::packet::{Register, Register21Bit};
         use Command::*;
@@ -110,15 +346,9 @@ impl Coordinator {
                     .await
             }
             SetHold(inverter, register, value) => {
-                let mut scaled_value = value;
-                let config = lxp::packet::find_register_config(register);
-
-                if !config.is_none() {
-                    scaled_value = scaled_value / config.unwrap().scale;
-                }
-
-                self.set_hold(inverter, register, scaled_value as u16).await
-            },
+                let value = safeguards::raw_hold_value(register, value)?;
+                self.set_hold(inverter, register, value).await
+            }
             WriteParam(inverter, register, value) => {
                 self.write_param(inverter, register, value).await
             }
@@ -166,6 +396,23 @@ impl Coordinator {
                 )
                 .await
             }
+            SetBit(inverter, register, name, enable) => {
+                let bit = lxp::packet::find_register_bit(register, &name)?;
+                self.update_hold(inverter, register, bit, enable).await
+            }
+            SetBits(inverter, register, bits) => {
+                let mut set = 0;
+                let mut clear = 0;
+                for (name, enable) in bits {
+                    let bit = lxp::packet::find_register_bit(register, &name)?;
+                    if enable {
+                        set |= bit;
+                    } else {
+                        clear |= bit;
+                    }
+                }
+                self.update_hold_bits(inverter, register, set, clear).await
+            }
             ChargeRate(inverter, pct) => {
                 self.set_hold(inverter, Register::ChargePowerPercentCmd, pct)
                     .await
@@ -189,19 +436,235 @@ impl Coordinator {
                 self.set_hold(inverter, Register::DischgCutOffSocEod, pct)
                     .await
             }
+            SetPreset(_, _) | SetSchedule(_, _) | ReadTime(_) | SetTime(_, _) | ReadBatch(_, _) => {
+                unreachable!() // see process_command
+            }
         }
     }
 
+    // Applies all of a preset's changes as one transaction, so either they all
+    // take or the registers are put back as they were. Returns "OK" for each
+    // change, keyed as in the config.
+    async fn set_preset(
+        &self,
+        inverter: config::Inverter,
+        name: &str,
+    ) -> Result<Option<serde_json::Value>> {
+        let preset = match self.config.presets().get(name) {
+            Some(entries) => presets::Preset::new(name, entries)?,
+            None => bail!("unknown preset {}", name),
+        };
+
+        let mut transaction =
+            commands::transaction::Transaction::new(self.channels.clone(), inverter.clone());
+        for (_, change) in &preset.changes {
+            transaction = match *change {
+                presets::Change::Hold { register, value } => {
+                    safeguards::check_hold_write(
+                        self.config.read_only(),
+                        &inverter,
+                        register,
+                        Some(value),
+                    )?;
+                    transaction.set(register, value)
+                }
+                presets::Change::Bits {
+                    register,
+                    set,
+                    clear,
+                } => {
+                    safeguards::check_hold_write(
+                        self.config.read_only(),
+                        &inverter,
+                        register,
+                        None,
+                    )?;
+                    transaction.update_bits(register, set, clear)
+                }
+            };
+        }
+
+        let registers = transaction.registers();
+        self.check_write_interval(&inverter, &registers)?;
+        let result = transaction.run().await;
+        self.written(&inverter, &registers);
+        if let Ok(replies) = &result {
+            for reply in replies {
+                self.cache_hold(reply);
+            }
+        }
+
+        result?;
+
+        // only announced once it's taken; a rolled back preset isn't active
+        self.active_presets
+            .borrow_mut()
+            .insert(inverter.datalog(), (preset.clone(), None));
+        self.publish_preset_state(inverter.datalog())?;
+
+        let results = preset
+            .changes
+            .iter()
+            .map(|(key, _)| (key.to_owned(), serde_json::Value::String("OK".to_string())))
+            .collect();
+
+        Ok(Some(serde_json::Value::Object(results)))
+    }
+
+    // Works out the schedule the inverter would end up with, refuses it if it
+    // doesn't make sense, then writes whichever registers differ as one
+    // transaction. The whole schedule is published to {datalog}/schedule.
+    async fn set_schedule(
+        &self,
+        inverter: config::Inverter,
+        schedule: &schedule::Schedule,
+    ) -> Result<Option<serde_json::Value>> {
+        // read afresh, as a register changed on the inverter since it was
+        // cached would otherwise be taken as already right
+        let mut current = std::collections::BTreeMap::new();
+        for (register, count) in schedule::Schedule::REGISTERS {
+            let values = self.read_hold_values(&inverter, register, count).await?;
+            current.extend((register..).zip(values));
+        }
+
+        let live = schedule::Schedule::from_registers(|r| current.get(&r).copied())?;
+        let wanted = schedule.applied_to(&live)?;
+
+        let register21 = *current
+            .get(&21)
+            .ok_or_else(|| anyhow!("no value for register 21"))?;
+        let mut transaction =
+            commands::transaction::Transaction::new(self.channels.clone(), inverter.clone());
+        for (register, value) in wanted.register_values(register21) {
+            if current.get(&register) == Some(&value) {
+                continue;
+            }
+
+            safeguards::check_hold_write(
+                self.config.read_only(),
+                &inverter,
+                register,
+                Some(value),
+            )?;
+            transaction = transaction.set(register, value);
+        }
+
+        let registers = transaction.registers();
+        if !registers.is_empty() {
+            self.check_write_interval(&inverter, &registers)?;
+            let result = transaction.run().await;
+            self.written(&inverter, &registers);
+            for reply in result? {
+                self.cache_hold(&reply);
+            }
+        }
+
+        let payload = serde_json::to_value(&wanted)?;
+        if self.config.mqtt().enabled() {
+            let message = mqtt::Message {
+                topic: format!("{}/schedule", inverter.datalog()),
+                retain: true,
+                payload: payload.to_string(),
+            };
+            if self
+                .channels
+                .to_mqtt
+                .send(mqtt::ChannelData::Message(message))
+                .is_err()
+            {
+                bail!("send(to_mqtt) failed - channel closed?");
+            }
+        }
+
+        Ok(Some(payload))
+    }
+
+    // Reads the inverter's clock, or sets it when set is given (to now if that
+    // is None), and publishes the result to {datalog}/clock.
+    async fn clock(
+        &self,
+        inverter: config::Inverter,
+        set: Option<Option<chrono::NaiveDateTime>>,
+    ) -> Result<Option<serde_json::Value>> {
+        let datalog = inverter.datalog();
+        let timesync = commands::timesync::TimeSync::new(self.channels.clone(), inverter.clone());
+
+        let clock = match set {
+            Some(time) => {
+                for register in 12..=14 {
+                    safeguards::check_hold_write(
+                        self.config.read_only(),
+                        &inverter,
+                        register,
+                        None,
+                    )?;
+                }
+                timesync.set(time).await?
+            }
+            None => timesync.read().await?,
+        };
+
+        if self.config.mqtt().enabled() {
+            let message = mqtt::ChannelData::Message(clock.message(datalog)?);
+            if self.channels.to_mqtt.send(message).is_err() {
+                bail!("send(to_mqtt) failed - channel closed?");
+            }
+        }
+
+        Ok(Some(serde_json::to_value(&clock)?))
+    }
+
+    // Publishes {datalog}/preset with the last preset applied and whether the
+    // holding registers still match it, whenever that changes.
+    fn publish_preset_state(&self, datalog: Serial) -> Result<()> {
+        let state = {
+            let mut active_presets = self.active_presets.borrow_mut();
+            let (preset, published) = match active_presets.get_mut(&datalog) {
+                Some(active) => active,
+                None => return Ok(()),
+            };
+
+            // however old the cached values are, they're the latest we have
+            let cache = self.hold_cache.borrow();
+            let matches =
+                preset.matches(|register| cache.get(datalog, register, std::time::Duration::MAX));
+            if *published == Some(matches) {
+                return Ok(());
+            }
+            *published = Some(matches);
+
+            serde_json::json!({ "name": preset.name, "matches": matches })
+        };
+
+        if self.config.mqtt().enabled() {
+            let message = mqtt::Message {
+                topic: format!("{}/preset", datalog),
+                retain: true,
+                payload: state.to_string(),
+            };
+            if self
+                .channels
+                .to_mqtt
+                .send(mqtt::ChannelData::Message(message))
+                .is_err()
+            {
+                bail!("send(to_mqtt) failed - channel closed?");
+            }
+        }
+
+        Ok(())
+    }
+
     async fn read_inputs<U>(
         &self,
         inverter: config::Inverter,
         register: U,
         count: u16,
-    ) -> Result<()>
+    ) -> Result<Option<Packet>>
     where
         U: Into<u16>,
     {
-        commands::read_inputs::ReadInputs::new(
+        let packet = commands::read_inputs::ReadInputs::new(
             self.channels.clone(),
             inverter.clone(),
             register,
@@ -210,14 +673,19 @@ impl Coordinator {
         .run()
         .await?;
 
-        Ok(())
+        Ok(Some(packet))
     }
 
-    async fn read_hold<U>(&self, inverter: config::Inverter, register: U, count: u16) -> Result<()>
+    async fn read_hold<U>(
+        &self,
+        inverter: config::Inverter,
+        register: U,
+        count: u16,
+    ) -> Result<Option<Packet>>
     where
         U: Into<u16>,
     {
-        commands::read_hold::ReadHold::new(
+        let packet = commands::read_hold::ReadHold::new(
             self.channels.clone(),
             inverter.clone(),
             register,
@@ -225,33 +693,94 @@ impl Coordinator {
         )
         .run()
         .await?;
+        self.cache_hold(&packet);
 
-        Ok(())
+        Ok(Some(packet))
     }
 
-    async fn read_param<U>(&self, inverter: config::Inverter, register: U) -> Result<()>
+    async fn read_param<U>(&self, inverter: config::Inverter, register: U) -> Result<Option<Packet>>
     where
         U: Into<u16>,
     {
-        commands::read_param::ReadParam::new(self.channels.clone(), inverter.clone(), register)
-            .run()
-            .await?;
+        let packet =
+            commands::read_param::ReadParam::new(self.channels.clone(), inverter.clone(), register)
+                .run()
+                .await?;
 
-        Ok(())
+        Ok(Some(packet))
+    }
+
+    // Reads each range in turn, in chunks the dongle can manage, and replies
+    // with every value keyed by space and register.
+    async fn read_batch(
+        &self,
+        inverter: config::Inverter,
+        ranges: &[batch::Range],
+    ) -> Result<Option<serde_json::Value>> {
+        use batch::Space;
+
+        let mut values = batch::Values::new();
+
+        for chunk in batch::chunks(ranges) {
+            let packet = match chunk.space {
+                Space::Input => {
+                    self.read_inputs(inverter.clone(), chunk.register, chunk.count)
+                        .await
+                }
+                Space::Hold => {
+                    self.read_hold(inverter.clone(), chunk.register, chunk.count)
+                        .await
+                }
+                Space::Param => self.read_param(inverter.clone(), chunk.register).await,
+            }
+            .map_err(|err| {
+                anyhow!(
+                    "read/batch: reading {:?} {} (count {}): {}",
+                    chunk.space,
+                    chunk.register,
+                    chunk.count,
+                    err
+                )
+            })?;
+
+            if let Some(pairs) = packet.as_ref().and_then(Self::pairs) {
+                values.entry(chunk.space).or_default().extend(pairs);
+            }
+        }
+
+        Ok(Some(serde_json::to_value(values)?))
     }
 
     async fn read_time_register(
         &self,
         inverter: config::Inverter,
         action: commands::time_register_ops::Action,
-    ) -> Result<()> {
+    ) -> Result<Option<Packet>> {
+        // each time range is two registers of two bytes each
+        if let Some(values) = self.cached_hold(&inverter, action.register()?, 2) {
+            let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+            let message = action.mqtt_message(inverter.datalog(), &bytes)?;
+            if self
+                .channels
+                .to_mqtt
+                .send(mqtt::ChannelData::Message(message))
+                .is_err()
+            {
+                bail!("send(to_mqtt) failed - channel closed?");
+            }
+
+            return Ok(None);
+        }
+
         commands::time_register_ops::ReadTimeRegister::new(
             self.channels.clone(),
             inverter.clone(),
             action,
         )
         .run()
-        .await
+        .await?;
+
+        Ok(None)
     }
 
     async fn write_param<U>(
@@ -259,11 +788,13 @@ impl Coordinator {
         inverter: config::Inverter,
         register: U,
         value: u16,
-    ) -> Result<()>
+    ) -> Result<Option<Packet>>
     where
         U: Into<u16>,
     {
-        commands::write_param::WriteParam::new(
+        safeguards::check_param_write(self.config.read_only(), &inverter)?;
+
+        let packet = commands::write_param::WriteParam::new(
             self.channels.clone(),
             inverter.clone(),
             register,
@@ -272,7 +803,7 @@ impl Coordinator {
         .run()
         .await?;
 
-        Ok(())
+        Ok(Some(packet))
     }
 
     async fn set_time_register(
@@ -280,26 +811,102 @@ impl Coordinator {
         inverter: config::Inverter,
         action: commands::time_register_ops::Action,
         values: [u8; 4],
-    ) -> Result<()> {
-        commands::time_register_ops::SetTimeRegister::new(
+    ) -> Result<Option<Packet>> {
+        let register = action.register()?;
+        let registers = [register, register + 1];
+        for register in registers {
+            safeguards::check_hold_write(self.config.read_only(), &inverter, register, None)?;
+        }
+        self.check_write_interval(&inverter, &registers)?;
+
+        let result = commands::time_register_ops::SetTimeRegister::new(
             self.channels.clone(),
             inverter.clone(),
             action,
             values,
         )
         .run()
-        .await
+        .await;
+        self.written(&inverter, &registers);
+        result?;
+
+        Ok(None)
     }
 
-    async fn set_hold<U>(&self, inverter: config::Inverter, register: U, value: u16) -> Result<()>
+    async fn set_hold<U>(
+        &self,
+        inverter: config::Inverter,
+        register: U,
+        value: u16,
+    ) -> Result<Option<Packet>>
     where
         U: Into<u16>,
     {
-        commands::set_hold::SetHold::new(self.channels.clone(), inverter.clone(), register, value)
-            .run()
-            .await?;
+        let register = register.into();
+        safeguards::check_hold_write(self.config.read_only(), &inverter, register, Some(value))?;
 
-        Ok(())
+        self.limited_write(inverter, register, value).await
+    }
+
+    // Writes value now, unless the register already holds it or was written
+    // less than write_interval ago.
+    async fn limited_write(
+        &self,
+        inverter: config::Inverter,
+        register: u16,
+        value: u16,
+    ) -> Result<Option<Packet>> {
+        // the latest write wins, so one which puts the register back as it
+        // is makes any pending write unnecessary
+        if self.cached_hold(&inverter, register, 1) == Some(vec![value]) {
+            let skipped = write_limiter::WriteSkipped::Unchanged { register, value };
+            let cancelled = self
+                .write_limiter
+                .borrow_mut()
+                .cancel(inverter.datalog(), register);
+            if let Some(cancelled) = cancelled {
+                self.reply_pending(&cancelled, &Ok(Some(value.into())), skipped.to_string());
+            }
+            return Err(skipped.into());
+        }
+
+        let skipped = self.write_limiter.borrow_mut().defer(
+            &inverter,
+            register,
+            value,
+            tokio::time::Instant::now(),
+        );
+        if let Some(skipped) = skipped {
+            info!("{}: {}", inverter.datalog(), skipped);
+            return Err(skipped.into());
+        }
+
+        self.write_hold(inverter, register, value).await
+    }
+
+    // set_hold without the checks on how recently the register was written
+    async fn write_hold(
+        &self,
+        inverter: config::Inverter,
+        register: u16,
+        value: u16,
+    ) -> Result<Option<Packet>> {
+        let packet = commands::set_hold::SetHold::new(
+            self.channels.clone(),
+            inverter.clone(),
+            register,
+            value,
+        )
+        .run()
+        .await?;
+        self.cache_hold(&packet);
+        self.write_limiter.borrow_mut().written(
+            inverter.datalog(),
+            register,
+            tokio::time::Instant::now(),
+        );
+
+        Ok(Some(packet))
     }
 
     async fn update_hold<U>(
@@ -308,44 +915,249 @@ impl Coordinator {
         register: U,
         bit: u16,
         enable: bool,
-    ) -> Result<()>
+    ) -> Result<Option<Packet>>
     where
         U: Into<u16>,
     {
-        commands::update_hold::UpdateHold::new(
+        if enable {
+            self.update_hold_bits(inverter, register, bit, 0).await
+        } else {
+            self.update_hold_bits(inverter, register, 0, bit).await
+        }
+    }
+
+    async fn update_hold_bits<U>(
+        &self,
+        inverter: config::Inverter,
+        register: U,
+        set: u16,
+        clear: u16,
+    ) -> Result<Option<Packet>>
+    where
+        U: Into<u16>,
+    {
+        let register = register.into();
+        safeguards::check_hold_write(self.config.read_only(), &inverter, register, None)?;
+
+        // build on a write still waiting to go out, as that's what the
+        // register will hold by the time this one does. Otherwise read it
+        // afresh, as the other bits may have been changed on the LCD or in
+        // the app since it was cached.
+        let pending = self
+            .write_limiter
+            .borrow()
+            .pending_value(inverter.datalog(), register);
+        let current = match pending {
+            Some(value) => value,
+            None => self.read_hold_values(&inverter, register, 1).await?[0],
+        };
+
+        self.limited_write(inverter, register, (current | set) & !clear)
+            .await
+    }
+
+    // Writes which go out as one transaction can't be held back in part, so
+    // they're refused if any of their registers was written too recently.
+    fn check_write_interval(&self, inverter: &config::Inverter, registers: &[u16]) -> Result<()> {
+        let now = tokio::time::Instant::now();
+        for register in registers {
+            let wait = self.write_limiter.borrow().wait(inverter, *register, now);
+            if let Some(wait) = wait {
+                return Err(safeguards::WriteRejected(format!(
+                    "register {} was written less than {}s ago, try again in {}s",
+                    register,
+                    inverter.write_interval(),
+                    wait.as_secs_f64().ceil() as u64
+                ))
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn written(&self, inverter: &config::Inverter, registers: &[u16]) {
+        let now = tokio::time::Instant::now();
+        let mut write_limiter = self.write_limiter.borrow_mut();
+        for register in registers {
+            write_limiter.written(inverter.datalog(), *register, now);
+        }
+    }
+
+    // Replies are also cached by inverter_receiver, but that can't happen
+    // while it is busy in inverter_connected, so cache them here too.
+    fn cache_hold(&self, packet: &Packet) {
+        if let Packet::TranslatedData(td) = packet {
+            self.hold_cache.borrow_mut().update(td);
+        }
+    }
+
+    // always from the inverter, and exactly count of them
+    async fn read_hold_values(
+        &self,
+        inverter: &config::Inverter,
+        register: u16,
+        count: u16,
+    ) -> Result<Vec<u16>> {
+        let packet = commands::read_hold::ReadHold::new(
             self.channels.clone(),
             inverter.clone(),
             register,
-            bit,
-            enable,
+            count,
         )
         .run()
         .await?;
+        self.cache_hold(&packet);
 
-        Ok(())
+        let values: Vec<u16> = match packet {
+            Packet::TranslatedData(td) => td.pairs().into_iter().map(|(_, v)| v).collect(),
+            _ => bail!("didn't get expected reply from inverter"),
+        };
+        if values.len() != count as usize {
+            bail!(
+                "reading register {}: wanted {} values, got {}",
+                register,
+                count,
+                values.len()
+            );
+        }
+
+        Ok(values)
+    }
+
+    fn cached_hold(
+        &self,
+        inverter: &config::Inverter,
+        register: u16,
+        count: u16,
+    ) -> Option<Vec<u16>> {
+        let max_age = std::time::Duration::from_secs(inverter.hold_cache_max_age());
+
+        self.hold_cache
+            .borrow()
+            .get_range(inverter.datalog(), register, count, max_age)
+    }
+
+    // the register values in a reply packet; a single number when there's
+    // only one, otherwise an object keyed by register
+    fn readback(packet: &Packet) -> Option<serde_json::Value> {
+        let pairs = Self::pairs(packet)?;
+
+        match pairs[..] {
+            [(_, value)] => Some(value.into()),
+            _ => Some(serde_json::Value::Object(
+                pairs
+                    .into_iter()
+                    .map(|(register, value)| (register.to_string(), value.into()))
+                    .collect(),
+            )),
+        }
+    }
+
+    // (register, value) for each register in a reply packet
+    fn pairs(packet: &Packet) -> Option<Vec<(u16, u16)>> {
+        match packet {
+            Packet::TranslatedData(td) => Some(td.pairs()),
+            Packet::ReadParam(rp) => Some(rp.pairs()),
+            Packet::WriteParam(wp) => Some(wp.pairs()),
+            Packet::Heartbeat(_) => None,
+        }
     }
 
     async fn inverter_receiver(&self) -> Result<()> {
         use lxp::inverter::ChannelData::*;
+        use tokio::time::{sleep_until, Duration, Instant};
 
         let mut receiver = self.channels.from_inverter.subscribe();
 
         let mut inputs_store = InputsStore::new();
+        let mut alarm_store = alarms::AlarmStore::new();
+        let mut energy_store = match self.config.energy_state_file() {
+            Some(file) => energy::EnergyStore::load(&file).unwrap_or_else(|err| {
+                warn!("{}; energy totals start over", err);
+                energy::EnergyStore::new()
+            }),
+            None => energy::EnergyStore::new(),
+        };
+        let mut save_energy =
+            tokio::time::interval_at(Instant::now() + ENERGY_SAVE_INTERVAL, ENERGY_SAVE_INTERVAL);
 
         loop {
-            match receiver.recv().await? {
-                Packet(packet) => {
-                    self.process_inverter_packet(packet, &mut inputs_store)
+            let next = self
+                .pending_inputs_all
+                .borrow()
+                .values()
+                .min()
+                .copied()
+                .unwrap_or_else(|| Instant::now() + Duration::from_secs(3600));
+
+            tokio::select! {
+                data = receiver.recv() => match data? {
+                    Packet(packet) => {
+                        self.process_inverter_packet(
+                            packet,
+                            &mut inputs_store,
+                            &mut alarm_store,
+                            &mut energy_store,
+                        )
                         .await?;
-                }
-                Connected(serial) => {
-                    if let Err(e) = self.inverter_connected(serial).await {
-                        error!("{}", e);
+                    }
+                    Connected(serial) => {
+                        if let Err(e) = self.inverter_connected(serial).await {
+                            error!("{}", e);
+                        }
+                    }
+                    Disconnect(datalog) => self.hold_cache.borrow_mut().clear(datalog),
+                    Shutdown => break,
+                },
+                _ = save_energy.tick() => self.save_energy(&energy_store),
+                _ = sleep_until(next) => {
+                    let now = Instant::now();
+                    let datalogs: Vec<Serial> = self
+                        .pending_inputs_all
+                        .borrow()
+                        .iter()
+                        .filter(|(_, at)| **at <= now)
+                        .map(|(datalog, _)| *datalog)
+                        .collect();
+
+                    for datalog in datalogs {
+                        self.pending_inputs_all.borrow_mut().remove(&datalog);
+                        if let Some(entry) = inputs_store.get(&datalog) {
+                            self.publish_inputs_all(entry, datalog)?;
+                        }
                     }
                 }
-                // this loop holds no state so doesn't care about inverter disconnects
-                Disconnect(_) => {}
-                Shutdown => break,
+            }
+        }
+
+        self.save_energy(&energy_store);
+
+        Ok(())
+    }
+
+    fn save_energy(&self, energy_store: &energy::EnergyStore) {
+        if let Some(file) = self.config.energy_state_file() {
+            // not worth stopping over; the totals are still right until the
+            // next restart
+            if let Err(err) = energy_store.save(&file) {
+                warn!("{}", err);
+            }
+        }
+    }
+
+    // inputs/all from the latest ReadInputAll, once its ReadInputAll2 is no
+    // longer worth waiting for
+    fn publish_inputs_all(&self, entry: &lxp::packet::ReadInputs, datalog: Serial) -> Result<()> {
+        if !self.config.mqtt().enabled() {
+            return Ok(());
+        }
+
+        if let Some(input) = entry.read_input_all() {
+            let message = mqtt::Message::for_input_all(&entry.combined(input)?, datalog)?;
+            let message = mqtt::ChannelData::Message(message);
+            if self.channels.to_mqtt.send(message).is_err() {
+                bail!("send(to_mqtt) failed - channel closed?");
             }
         }
 
@@ -356,9 +1168,33 @@ impl Coordinator {
         &self,
         packet: lxp::packet::Packet,
         inputs_store: &mut InputsStore,
+        alarm_store: &mut alarms::AlarmStore,
+        energy_store: &mut energy::EnergyStore,
     ) -> Result<()> {
         debug!("RX: {:?}", packet);
 
+        // replies to our own refresh reads only publish what has changed, so
+        // have to be compared with the cache before they go into it
+        let setting_changes = self.refresh_changes(&packet);
+        self.cache_hold(&packet);
+
+        // holding register changes might affect whether the active preset
+        // still matches
+        let hold_datalog = match &packet {
+            Packet::TranslatedData(td) if td.device_function != DeviceFunction::ReadInput => {
+                Some(td.datalog)
+            }
+            _ => None,
+        };
+
+        // fault/warning changes found in this packet, published last so they
+        // follow the inputs they were derived from
+        let mut alarm_events = Vec::new();
+        // likewise, values derived from a complete set of inputs
+        let mut derived = None;
+        // and the combined inputs/all record
+        let mut inputs_all = None;
+
         if let Packet::TranslatedData(td) = &packet {
             // temporary special greppable logging for Param packets as I try to
             // work out what they do :)
@@ -380,24 +1216,72 @@ impl Coordinator {
 
                 match td.read_input() {
                     Ok(ReadInput::ReadInputAll(r_all)) => {
+                        alarm_events = alarm_store.update(
+                            r_all.datalog,
+                            r_all.fault_code,
+                            r_all.warning_code,
+                            &r_all.time,
+                        );
+
+                        let (totals, fields) = self.derive_inputs(&r_all, energy_store)?;
+                        entry.set_derived(fields.clone());
+
                         info!("Saving ReadInputAll");
-                        // no need for MQTT here, done below
-                        self.save_input_all(r_all).await?
+                        // inputs/all waits a moment for this read's
+                        // ReadInputAll2, unless that came first. a read
+                        // replaces an earlier one which is still waiting
+                        if entry.read_input_all2_near(&r_all.time).is_some() {
+                            self.pending_inputs_all.borrow_mut().remove(&r_all.datalog);
+                            inputs_all = Some(mqtt::Message::for_input_all(
+                                &entry.combined(&r_all)?,
+                                r_all.datalog,
+                            )?);
+                        } else {
+                            self.pending_inputs_all.borrow_mut().insert(
+                                r_all.datalog,
+                                tokio::time::Instant::now() + INPUTS_ALL2_WAIT,
+                            );
+                        }
+                        entry.set_read_input_all(r_all.clone());
+                        self.save_input_all(r_all, &fields).await?;
+                        derived = Some((totals, fields));
                     }
-                    Ok(ReadInput::ReadInputAll2(_r_all)) => {
+                    Ok(ReadInput::ReadInputAll2(r_all2)) => {
+                        let datalog = r_all2.datalog;
+
                         info!("Saving ReadInputAll2");
-                        // no need for MQTT here, done below
-                        // self.save_input_all2(r_all).await?
+                        entry.set_read_input_all2(r_all2.clone());
+
+                        // completes inputs/all for this read's ReadInputAll,
+                        // if that is waiting for it
+                        if self.pending_inputs_all.borrow().contains_key(&datalog) {
+                            if let Some(input) = entry.read_input_all_near(&r_all2.time) {
+                                self.pending_inputs_all.borrow_mut().remove(&datalog);
+                                inputs_all = Some(mqtt::Message::for_input_all(
+                                    &entry.combined(input)?,
+                                    datalog,
+                                )?);
+                            }
+                        }
+
+                        self.save_input_all2(r_all2).await?
                     }
 
                     Ok(ReadInput::ReadInput1(r1)) => {
                         info!("Saving ReadInput1");
                         entry.set_read_input_1(r1);
-                    },
+                    }
                     Ok(ReadInput::ReadInput2(r2)) => {
+                        alarm_events = alarm_store.update(
+                            r2.datalog,
+                            r2.fault_code,
+                            r2.warning_code,
+                            &r2.time,
+                        );
+
                         info!("Saving ReadInput2");
                         entry.set_read_input_2(r2);
-                    },
+                    }
                     Ok(ReadInput::ReadInput3(r3)) => {
                         let datalog = r3.datalog;
 
@@ -405,15 +1289,16 @@ impl Coordinator {
                         entry.set_read_input_3(r3);
 
                         if let Some(input) = entry.to_input_all() {
-                            if self.config.mqtt().enabled() {
-                                let message = mqtt::Message::for_input_all(&input, datalog)?;
-                                let channel_data = mqtt::ChannelData::Message(message);
-                                if self.channels.to_mqtt.send(channel_data).is_err() {
-                                    bail!("send(to_mqtt) failed - channel closed?");
-                                }
-                            }
+                            let (totals, fields) = self.derive_inputs(&input, energy_store)?;
+                            entry.set_derived(fields.clone());
 
-                            self.save_input_all(Box::new(input)).await?;
+                            inputs_all = Some(mqtt::Message::for_input_all(
+                                &entry.combined(&input)?,
+                                datalog,
+                            )?);
+
+                            self.save_input_all(Box::new(input), &fields).await?;
+                            derived = Some((totals, fields));
                         }
                     }
                     Err(x) => warn!("ignoring {:?}", x),
@@ -425,7 +1310,15 @@ impl Coordinator {
             // returns a Vec of messages to send. could be none;
             // not every packet produces an MQ message (eg, heartbeats),
             // and some produce >1 (multi-register ReadHold)
-            match Self::packet_to_messages(packet, self.config.mqtt().publish_individual_input()) {
+            let messages = match (setting_changes, packet) {
+                (Some(changes), Packet::TranslatedData(td)) => {
+                    mqtt::Message::for_setting_changes(td, &changes)
+                }
+                (_, packet) => {
+                    Self::packet_to_messages(packet, self.config.mqtt().publish_individual_input())
+                }
+            };
+            match messages {
                 Ok(messages) => {
                     for message in messages {
                         let message = mqtt::ChannelData::Message(message);
@@ -440,6 +1333,21 @@ impl Coordinator {
                     error!("{}", e);
                 }
             }
+
+            if let Some(message) = inputs_all {
+                let message = mqtt::ChannelData::Message(message);
+                if self.channels.to_mqtt.send(message).is_err() {
+                    bail!("send(to_mqtt) failed - channel closed?");
+                }
+            }
+        }
+
+        if let Some((totals, fields)) = derived {
+            self.publish_derived(totals, fields)?;
+        }
+        self.publish_alarm_events(alarm_events)?;
+        if let Some(datalog) = hold_datalog {
+            self.publish_preset_state(datalog)?;
         }
 
         Ok(())
@@ -463,18 +1371,13 @@ impl Coordinator {
 
         // We can only read holding registers in blocks of 40. Provisionally,
         // there are 6 pages of 40 values.
-        self.read_hold(inverter.clone(), 0_u16, 40).await?;
-        self.read_hold(inverter.clone(), 40_u16, 40).await?;
-        self.read_hold(inverter.clone(), 80_u16, 40).await?;
-        self.read_hold(inverter.clone(), 120_u16, 40).await?;
-        self.read_hold(inverter.clone(), 160_u16, 40).await?;
-        self.read_hold(inverter.clone(), 200_u16, 40).await?;
+        for register in HOLD_BLOCKS {
+            self.read_hold(inverter.clone(), register, 40).await?;
+        }
 
         // Also send any special interpretive topics which are derived from
-        // the holding registers.
-        //
-        // FIXME: this is a further 12 round-trips to the inverter to read values
-        // we have already taken, just above. We should be able to do better!
+        // the holding registers. These are answered from the cache filled by
+        // the reads above rather than going back to the inverter.
         for num in &[1, 2, 3] {
             self.read_time_register(
                 inverter.clone(),
@@ -501,9 +1404,228 @@ impl Coordinator {
         Ok(())
     }
 
-    async fn save_input_all(&self, input: Box<lxp::packet::ReadInputAll>) -> Result<()> {
+    // Re-reads the holding registers of each connected inverter every
+    // refresh_holdings_interval, so settings changed on the inverter itself or
+    // through the vendor app still reach MQTT. Only connected inverters are
+    // refreshed; the first refresh is one interval after connecting.
+    async fn holdings_refresher(&self) -> Result<()> {
+        use lxp::inverter::ChannelData::*;
+        use tokio::time::{sleep_until, Duration, Instant};
+
+        let mut receiver = self.channels.from_inverter.subscribe();
+        let mut due: std::collections::HashMap<Serial, Instant> = Default::default();
+
+        loop {
+            let next = due
+                .values()
+                .min()
+                .copied()
+                .unwrap_or_else(|| Instant::now() + Duration::from_secs(3600));
+
+            tokio::select! {
+                data = receiver.recv() => match data {
+                    Ok(Connected(datalog)) => {
+                        if let Some(interval) = self.refresh_holdings_interval(datalog) {
+                            due.insert(datalog, Instant::now() + interval);
+                        }
+                    }
+                    Ok(Disconnect(datalog)) => {
+                        due.remove(&datalog);
+                    }
+                    Ok(Shutdown) | Err(broadcast::error::RecvError::Closed) => break,
+                    // packets are inverter_receiver's business, so falling
+                    // behind on them here doesn't matter
+                    Ok(Packet(_)) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                },
+                _ = sleep_until(next) => {
+                    let now = Instant::now();
+                    let datalogs: Vec<Serial> = due
+                        .iter()
+                        .filter(|(_, at)| **at <= now)
+                        .map(|(datalog, _)| *datalog)
+                        .collect();
+
+                    for datalog in datalogs {
+                        if let Err(e) = self.refresh_holdings(datalog).await {
+                            warn!("refreshing holdings for {} failed: {}", datalog, e);
+                        }
+
+                        match self.refresh_holdings_interval(datalog) {
+                            Some(interval) => due.insert(datalog, Instant::now() + interval),
+                            None => due.remove(&datalog),
+                        };
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Writes held back by set_hold once write_interval has passed. Only the
+    // latest write to each register is still pending by then.
+    async fn pending_writes(&self) -> Result<()> {
+        use lxp::inverter::ChannelData::*;
+        use tokio::time::{sleep_until, Duration, Instant};
+
+        let mut receiver = self.channels.from_inverter.subscribe();
+
+        loop {
+            // new writes can be held back at any time, so look again soon
+            let next = Instant::now() + Duration::from_secs(1);
+            let next = self
+                .write_limiter
+                .borrow()
+                .next_due()
+                .map_or(next, |due| due.min(next));
+
+            tokio::select! {
+                data = receiver.recv() => match data {
+                    Ok(Shutdown) | Err(broadcast::error::RecvError::Closed) => break,
+                    _ => {}
+                },
+                _ = sleep_until(next) => {
+                    let due = self.write_limiter.borrow_mut().due(Instant::now());
+                    for pending in due {
+                        let datalog = pending.inverter.datalog();
+                        let result = self
+                            .write_hold(pending.inverter.clone(), pending.register, pending.value)
+                            .await;
+
+                        match &result {
+                            Ok(_) => info!(
+                                "{}: wrote coalesced register {} = {}",
+                                datalog, pending.register, pending.value
+                            ),
+                            Err(e) => warn!(
+                                "{}: writing coalesced register {} = {} failed: {}",
+                                datalog, pending.register, pending.value, e
+                            ),
+                        }
+
+                        let note = match result {
+                            Ok(_) => "set",
+                            Err(_) => "could not be set",
+                        };
+                        let note = format!(
+                            "coalesced, register {} {} to {}",
+                            pending.register, note, pending.value
+                        );
+                        let result = result.map(|packet| packet.as_ref().and_then(Self::readback));
+                        self.reply_pending(&pending, &result, note);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn refresh_holdings_interval(&self, datalog: Serial) -> Option<std::time::Duration> {
+        self.config
+            .enabled_inverter_with_datalog(datalog)?
+            .refresh_holdings_interval()
+            .map(std::time::Duration::from_secs)
+    }
+
+    // The replies are published by inverter_receiver, which recognises them
+    // from the refreshing set.
+    async fn refresh_holdings(&self, datalog: Serial) -> Result<()> {
+        let inverter = match self.config.enabled_inverter_with_datalog(datalog) {
+            Some(inverter) => inverter,
+            None => return Ok(()),
+        };
+
+        debug!("refreshing holding registers for inverter {}", datalog);
+
+        for register in HOLD_BLOCKS {
+            self.refreshing.borrow_mut().insert((datalog, register));
+
+            let result = commands::read_hold::ReadHold::new(
+                self.channels.clone(),
+                inverter.clone(),
+                register,
+                40,
+            )
+            .run()
+            .await;
+
+            if let Err(e) = result {
+                self.refreshing.borrow_mut().remove(&(datalog, register));
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    // If this is the reply to a refresh read, the registers which have changed
+    // since we last saw them.
+    fn refresh_changes(&self, packet: &Packet) -> Option<Vec<hold_cache::SettingChange>> {
+        let td = match packet {
+            Packet::TranslatedData(td) if td.device_function == DeviceFunction::ReadHold => td,
+            _ => return None,
+        };
+
+        if !self
+            .refreshing
+            .borrow_mut()
+            .remove(&(td.datalog, td.register))
+        {
+            return None;
+        }
+
+        let changes = self.hold_cache.borrow().changes(td);
+        for change in changes.iter().filter(|change| change.old.is_some()) {
+            info!(
+                "{} register {} changed from {} to {}",
+                change.datalog,
+                change.register,
+                change.old.unwrap_or_default(),
+                change.new
+            );
+        }
+
+        Some(changes)
+    }
+
+    // Works out energy totals and household metrics for a complete set of
+    // inputs. Returns the totals (which have their own database table) and
+    // every derived value as fields to go alongside the inputs.
+    fn derive_inputs(
+        &self,
+        input: &lxp::packet::ReadInputAll,
+        energy_store: &mut energy::EnergyStore,
+    ) -> Result<(
+        energy::EnergyTotals,
+        serde_json::Map<String, serde_json::Value>,
+    )> {
+        let max_power = self
+            .config
+            .enabled_inverter_with_datalog(input.datalog)
+            .map(|inverter| inverter.max_power())
+            .unwrap_or(u32::MAX);
+
+        let totals = energy_store.update(input, max_power);
+
+        let mut fields = totals.fields()?;
+        fields.extend(metrics::HouseholdMetrics::new(input).fields()?);
+
+        Ok((totals, fields))
+    }
+
+    // derived values are sent to influx as extra fields on the inputs
+    async fn save_input_all(
+        &self,
+        input: Box<lxp::packet::ReadInputAll>,
+        derived: &serde_json::Map<String, serde_json::Value>,
+    ) -> Result<()> {
         if self.config.influx().enabled() {
-            let channel_data = influx::ChannelData::InputData(serde_json::to_value(&input)?);
+            let mut data = serde_json::to_value(&input)?;
+            if let Some(data) = data.as_object_mut() {
+                data.extend(derived.clone());
+            }
+            let channel_data = influx::ChannelData::InputData(data);
             if self.channels.to_influx.send(channel_data).is_err() {
                 bail!("send(to_influx) failed - channel closed?");
             }
@@ -519,6 +1641,90 @@ impl Coordinator {
         Ok(())
     }
 
+    async fn save_input_all2(&self, input: Box<lxp::packet::ReadInputAll2>) -> Result<()> {
+        if self.config.influx().enabled() {
+            let channel_data = influx::ChannelData::Inputs2Data(serde_json::to_value(&input)?);
+            if self.channels.to_influx.send(channel_data).is_err() {
+                bail!("send(to_influx) failed - channel closed?");
+            }
+        }
+
+        if self.config.have_enabled_database() {
+            let channel_data = database::ChannelData::ReadInputAll2(input);
+            if self.channels.to_database.send(channel_data).is_err() {
+                bail!("send(to_database) failed - channel closed?");
+            }
+        }
+
+        Ok(())
+    }
+
+    // Derived values go to MQTT as individual input topics (there is no separate
+    // influx measurement; save_input_all already included them).
+    fn publish_derived(
+        &self,
+        totals: energy::EnergyTotals,
+        fields: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<()> {
+        // like the individual inputs, these are already in inputs/all
+        if self.config.mqtt().enabled() && self.config.mqtt().publish_individual_input() {
+            for message in mqtt::Message::for_derived(totals.datalog, &fields) {
+                let channel_data = mqtt::ChannelData::Message(message);
+                if self.channels.to_mqtt.send(channel_data).is_err() {
+                    bail!("send(to_mqtt) failed - channel closed?");
+                }
+            }
+        }
+
+        if self.config.have_enabled_database() {
+            let channel_data = database::ChannelData::EnergyTotals(totals);
+            if self.channels.to_database.send(channel_data).is_err() {
+                bail!("send(to_database) failed - channel closed?");
+            }
+        }
+
+        Ok(())
+    }
+
+    // Each event goes to MQTT as {datalog}/events, and to influx/databases so
+    // there is a history of when faults and warnings came and went.
+    fn publish_alarm_events(&self, events: Vec<alarms::AlarmEvent>) -> Result<()> {
+        for event in events {
+            info!(
+                "{} {:?} {} {:?}: {}",
+                event.datalog, event.kind, event.code, event.state, event.description
+            );
+
+            if self.config.mqtt().enabled() {
+                let message = mqtt::Message {
+                    topic: format!("{}/events", event.datalog),
+                    retain: false,
+                    payload: serde_json::to_string(&event)?,
+                };
+                let channel_data = mqtt::ChannelData::Message(message);
+                if self.channels.to_mqtt.send(channel_data).is_err() {
+                    bail!("send(to_mqtt) failed - channel closed?");
+                }
+            }
+
+            if self.config.influx().enabled() {
+                let channel_data = influx::ChannelData::EventData(serde_json::to_value(&event)?);
+                if self.channels.to_influx.send(channel_data).is_err() {
+                    bail!("send(to_influx) failed - channel closed?");
+                }
+            }
+
+            if self.config.have_enabled_database() {
+                let channel_data = database::ChannelData::AlarmEvent(event);
+                if self.channels.to_database.send(channel_data).is_err() {
+                    bail!("send(to_database) failed - channel closed?");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     fn packet_to_messages(
         packet: Packet,
         publish_individual_input: bool,
@@ -0,0 +1,122 @@
+use crate::prelude::*;
+
+use serde_yaml::Value;
+
+// holding registers with named bits; see lxp::packet::find_register_bit
+const BIT_REGISTERS: [u16; 4] = [21, 110, 120, 179];
+
+// One write needed to apply a preset. Hold values are raw register values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    Hold { register: u16, value: u16 },
+    Bits { register: u16, set: u16, clear: u16 },
+}
+
+impl Change {
+    pub fn register(&self) -> u16 {
+        match *self {
+            Self::Hold { register, .. } => register,
+            Self::Bits { register, .. } => register,
+        }
+    }
+
+    // whether current, the register's live value, already has this change
+    pub fn applied(&self, current: u16) -> bool {
+        match *self {
+            Self::Hold { value, .. } => current == value,
+            Self::Bits { set, clear, .. } => current & set == set && current & clear == 0,
+        }
+    }
+}
+
+// A named set of changes from config, kept in the order they were written
+// and keyed as they were written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preset {
+    pub name: String,
+    pub changes: Vec<(String, Change)>,
+}
+
+impl Preset {
+    // Keys are either a holding register number or name (eg AcChargeSocLimit)
+    // with a value as for set/hold, or a bit name (eg ac_charge or
+    // eco_mode_enable) with a value of on/off.
+    pub fn new(name: &str, entries: &serde_yaml::Mapping) -> Result<Self> {
+        let changes = entries
+            .iter()
+            .map(|(key, value)| {
+                let key = match key {
+                    Value::String(key) => key.to_owned(),
+                    Value::Number(key) => key.to_string(),
+                    _ => bail!("preset {}: bad key {:?}", name, key),
+                };
+                let change = Self::change(&key, value)
+                    .map_err(|err| anyhow!("preset {}: {}: {}", name, key, err))?;
+
+                Ok((key, change))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            name: name.to_owned(),
+            changes,
+        })
+    }
+
+    // current gives the live value of a register, if known. Registers we
+    // don't know the value of count as not matching.
+    pub fn matches<F>(&self, current: F) -> bool
+    where
+        F: Fn(u16) -> Option<u16>,
+    {
+        self.changes.iter().all(|(_, change)| {
+            current(change.register()).map_or(false, |value| change.applied(value))
+        })
+    }
+
+    fn change(key: &str, value: &Value) -> Result<Change> {
+        if let Some(register) = key.parse().ok().or_else(|| lxp::packet::find_register(key)) {
+            let value = match value {
+                Value::Number(value) => value.as_f64(),
+                Value::String(value) => value.parse().ok(),
+                _ => None,
+            }
+            .ok_or_else(|| anyhow!("{:?} is not a number", value))?;
+
+            return Ok(Change::Hold {
+                register,
+                value: coordinator::safeguards::raw_hold_value(register, value)?,
+            });
+        }
+
+        for register in BIT_REGISTERS {
+            // allow ac_charge for ac_charge_enable, like the set/ac_charge command
+            let bit = lxp::packet::find_register_bit(register, key)
+                .or_else(|_| lxp::packet::find_register_bit(register, &format!("{}_enable", key)));
+
+            if let Ok(bit) = bit {
+                let enable = match value {
+                    Value::Bool(enable) => *enable,
+                    Value::String(enable) => mqtt::Message::is_truthy(enable),
+                    _ => bail!("{:?} should be on or off", value),
+                };
+
+                return Ok(if enable {
+                    Change::Bits {
+                        register,
+                        set: bit,
+                        clear: 0,
+                    }
+                } else {
+                    Change::Bits {
+                        register,
+                        set: 0,
+                        clear: bit,
+                    }
+                });
+            }
+        }
+
+        bail!("unknown register or bit")
+    }
+}
@@ -0,0 +1,76 @@
+use crate::prelude::*;
+
+// Returned when a write is refused before it reaches the inverter, so the
+// reason can be passed back on the result topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteRejected(pub String);
+
+impl std::fmt::Display for WriteRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "write rejected: {}", self.0)
+    }
+}
+
+impl std::error::Error for WriteRejected {}
+
+// Checks a write to a holding register against the read_only flags, the
+// inverter's writable/protected register lists and, when we know the value up
+// front, the register's limits. Bitfield updates pass None for value.
+pub fn check_hold_write(
+    read_only: bool,
+    inverter: &config::Inverter,
+    register: u16,
+    value: Option<u16>,
+) -> Result<()> {
+    check_read_only(read_only, inverter)?;
+
+    if !inverter.register_writable(register) {
+        return Err(WriteRejected(format!(
+            "register {} is not writable on {}",
+            register,
+            inverter.datalog()
+        ))
+        .into());
+    }
+
+    if let (Some(limits), Some(value)) = (lxp::packet::find_register_limits(register), value) {
+        limits
+            .check(register, value)
+            .map_err(|err| WriteRejected(err.to_string()))?;
+    }
+
+    Ok(())
+}
+
+// Parameters have their own register numbering, so only read_only applies.
+pub fn check_param_write(read_only: bool, inverter: &config::Inverter) -> Result<()> {
+    check_read_only(read_only, inverter)
+}
+
+// set/hold takes the human-readable value; turns it back into what is stored
+// in the register, refusing anything that doesn't come out as a whole u16.
+pub fn raw_hold_value(register: u16, value: f64) -> Result<u16> {
+    let raw = match lxp::packet::find_register_config(register) {
+        Some(config) => value / config.scale,
+        None => value,
+    };
+
+    let rounded = raw.round();
+    if (raw - rounded).abs() > 1e-6 || !(0.0..=u16::MAX as f64).contains(&rounded) {
+        return Err(WriteRejected(format!(
+            "value {} cannot be stored in register {}",
+            value, register
+        ))
+        .into());
+    }
+
+    Ok(rounded as u16)
+}
+
+fn check_read_only(read_only: bool, inverter: &config::Inverter) -> Result<()> {
+    if read_only || inverter.read_only() {
+        return Err(WriteRejected(format!("{} is read-only", inverter.datalog())).into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,428 @@
+use crate::prelude::*;
+
+use coordinator::commands::time_register_ops::Action;
+use coordinator::safeguards::WriteRejected;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// each mode has this many time slots, in consecutive pairs of registers
+pub const SLOTS: u16 = 3;
+
+// Time {{{
+// A time of day as HH:MM. Out of range values parse, so that applied_to() can
+// report them along with anything else wrong with the schedule.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Time {
+    pub hour: u8,
+    pub minute: u8,
+}
+
+impl Time {
+    // registers hold the hour in the low byte and the minute in the high byte
+    fn from_register(value: u16) -> Self {
+        let [hour, minute] = value.to_le_bytes();
+        Self { hour, minute }
+    }
+
+    fn register(&self) -> u16 {
+        u16::from_le_bytes([self.hour, self.minute])
+    }
+
+    fn valid(&self) -> bool {
+        self.hour <= 23 && self.minute <= 59
+    }
+}
+
+impl FromStr for Time {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            Some((hour, minute)) => Ok(Self {
+                hour: hour.parse()?,
+                minute: minute.parse()?,
+            }),
+            None => bail!("badly formatted time {}, use HH:MM", s),
+        }
+    }
+}
+
+impl std::fmt::Display for Time {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+impl Serialize for Time {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Time {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+} // }}}
+
+// Slot {{{
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Slot {
+    pub start: Time,
+    pub end: Time,
+}
+
+impl Slot {
+    // 00:00-00:00 is what the inverter has in slots which aren't in use
+    pub fn is_unused(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn overlaps(&self, other: &Slot) -> bool {
+        !self.is_unused() && !other.is_unused() && self.start < other.end && other.start < self.end
+    }
+}
+
+impl std::fmt::Display for Slot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+} // }}}
+
+// Kind {{{
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    AcCharge,
+    AcFirst,
+    ChargePriority,
+    ForcedDischarge,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 4] = [
+        Kind::AcCharge,
+        Kind::AcFirst,
+        Kind::ChargePriority,
+        Kind::ForcedDischarge,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::AcCharge => "ac_charge",
+            Self::AcFirst => "ac_first",
+            Self::ChargePriority => "charge_priority",
+            Self::ForcedDischarge => "forced_discharge",
+        }
+    }
+
+    // slot n is 1-based, as in set/ac_charge/{n}
+    pub fn action(&self, n: u16) -> Action {
+        match self {
+            Self::AcCharge => Action::AcCharge(n),
+            Self::AcFirst => Action::AcFirst(n),
+            Self::ChargePriority => Action::ChargePriority(n),
+            Self::ForcedDischarge => Action::ForcedDischarge(n),
+        }
+    }
+
+    fn slot_registers(&self) -> std::ops::Range<u16> {
+        // every kind supports slot 1
+        let first = self.action(1).register().unwrap();
+        first..first + SLOTS * 2
+    }
+
+    // AC first has no rate, SOC limit or enable bit of its own
+    fn rate_register(&self) -> Option<lxp::packet::Register> {
+        use lxp::packet::Register::*;
+        match self {
+            Self::AcCharge => Some(AcChargePowerCmd),
+            Self::AcFirst => None,
+            Self::ChargePriority => Some(ChargePriorityPowerCmd),
+            Self::ForcedDischarge => Some(ForcedDischgPowerCmd),
+        }
+    }
+
+    fn soc_limit_register(&self) -> Option<lxp::packet::Register> {
+        use lxp::packet::Register::*;
+        match self {
+            Self::AcCharge => Some(AcChargeSocLimit),
+            Self::AcFirst => None,
+            Self::ChargePriority => Some(ChargePrioritySocLimit),
+            Self::ForcedDischarge => Some(ForcedDischgSocLimit),
+        }
+    }
+
+    // in register 21
+    fn enable_bit(&self) -> Option<u16> {
+        use lxp::packet::Register21Bit::*;
+        match self {
+            Self::AcCharge => Some(AcChargeEnable as u16),
+            Self::AcFirst => None,
+            Self::ChargePriority => Some(ChargePriorityEnable as u16),
+            Self::ForcedDischarge => Some(ForcedDischargeEnable as u16),
+        }
+    }
+
+    fn charges(&self) -> bool {
+        matches!(self, Self::AcCharge | Self::ChargePriority)
+    }
+} // }}}
+
+// Mode {{{
+// The settings for one kind of time slot. Anything left out is left as it is
+// on the inverter. Slots replace all of the existing ones; any not given are
+// cleared.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mode {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slots: Option<Vec<Slot>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_pct: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub soc_limit_pct: Option<u16>,
+}
+
+impl Mode {
+    fn merged(&self, current: &Mode) -> Mode {
+        Mode {
+            enabled: self.enabled.or(current.enabled),
+            slots: self.slots.clone().or_else(|| current.slots.clone()),
+            rate_pct: self.rate_pct.or(current.rate_pct),
+            soc_limit_pct: self.soc_limit_pct.or(current.soc_limit_pct),
+        }
+    }
+
+    fn used_slots(&self) -> Vec<Slot> {
+        self.slots
+            .iter()
+            .flatten()
+            .filter(|slot| !slot.is_unused())
+            .copied()
+            .collect()
+    }
+
+    fn validate(&self, kind: Kind) -> Result<()> {
+        let name = kind.name();
+
+        if kind.enable_bit().is_none() && self.enabled.is_some() {
+            bail!("{} can't be enabled or disabled", name);
+        }
+        if kind.rate_register().is_none() && self.rate_pct.is_some() {
+            bail!("{} has no rate", name);
+        }
+        if kind.soc_limit_register().is_none() && self.soc_limit_pct.is_some() {
+            bail!("{} has no SOC limit", name);
+        }
+
+        for (field, pct) in [
+            ("rate_pct", self.rate_pct),
+            ("soc_limit_pct", self.soc_limit_pct),
+        ] {
+            if let Some(pct) = pct.filter(|pct| *pct > 100) {
+                bail!("{}.{} {} is over 100", name, field, pct);
+            }
+        }
+
+        let slots = self.slots.as_deref().unwrap_or_default();
+        if slots.len() > SLOTS as usize {
+            bail!("{} has {} slots, the most is {}", name, slots.len(), SLOTS);
+        }
+
+        for slot in slots {
+            if !slot.start.valid() || !slot.end.valid() {
+                bail!("{} slot {} is not a valid time range", name, slot);
+            }
+            if !slot.is_unused() && slot.end <= slot.start {
+                bail!("{} slot {} ends before it starts", name, slot);
+            }
+        }
+
+        let used = self.used_slots();
+        for (i, slot) in used.iter().enumerate() {
+            if let Some(other) = used[i + 1..].iter().find(|other| slot.overlaps(other)) {
+                bail!("{} slots {} and {} overlap", name, slot, other);
+            }
+        }
+
+        Ok(())
+    }
+} // }}}
+
+// Schedule {{{
+// A day's time-of-use settings, as taken by set/schedule and published to
+// {datalog}/schedule. Modes left out are left as they are on the inverter.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ac_charge: Option<Mode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ac_first: Option<Mode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub charge_priority: Option<Mode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forced_discharge: Option<Mode>,
+}
+
+impl Schedule {
+    // every holding register a schedule is made of, as (register, count)
+    pub const REGISTERS: [(u16, u16); 3] = [(21, 1), (66, 24), (152, 6)];
+
+    pub fn mode(&self, kind: Kind) -> Option<&Mode> {
+        match kind {
+            Kind::AcCharge => self.ac_charge.as_ref(),
+            Kind::AcFirst => self.ac_first.as_ref(),
+            Kind::ChargePriority => self.charge_priority.as_ref(),
+            Kind::ForcedDischarge => self.forced_discharge.as_ref(),
+        }
+    }
+
+    fn mode_mut(&mut self, kind: Kind) -> &mut Option<Mode> {
+        match kind {
+            Kind::AcCharge => &mut self.ac_charge,
+            Kind::AcFirst => &mut self.ac_first,
+            Kind::ChargePriority => &mut self.charge_priority,
+            Kind::ForcedDischarge => &mut self.forced_discharge,
+        }
+    }
+
+    // The whole schedule as held in the registers; get returns None for any
+    // register we don't have a value for.
+    pub fn from_registers<F>(get: F) -> Result<Self>
+    where
+        F: Fn(u16) -> Option<u16>,
+    {
+        let get = |register: u16| get(register).ok_or_else(|| anyhow!("no value for {}", register));
+
+        let mut schedule = Self::default();
+        for kind in Kind::ALL {
+            let registers = kind.slot_registers();
+            let slots = registers
+                .step_by(2)
+                .map(|register| {
+                    Ok(Slot {
+                        start: Time::from_register(get(register)?),
+                        end: Time::from_register(get(register + 1)?),
+                    })
+                })
+                .collect::<Result<_>>()?;
+
+            let mode = Mode {
+                enabled: match kind.enable_bit() {
+                    Some(bit) => Some(get(21)? & bit == bit),
+                    None => None,
+                },
+                slots: Some(slots),
+                rate_pct: kind.rate_register().map(|r| get(r as u16)).transpose()?,
+                soc_limit_pct: kind
+                    .soc_limit_register()
+                    .map(|r| get(r as u16))
+                    .transpose()?,
+            };
+            *schedule.mode_mut(kind) = Some(mode);
+        }
+
+        Ok(schedule)
+    }
+
+    // Lays self over current, returning the schedule the inverter would end
+    // up with. Each mode given in self is checked, then that no forced
+    // discharge slot would overlap a charging one, unless both were already
+    // there. Problems are returned as WriteRejected.
+    pub fn applied_to(&self, current: &Schedule) -> Result<Schedule> {
+        let mut merged = current.clone();
+        for kind in Kind::ALL {
+            if let Some(mode) = self.mode(kind) {
+                let base = current.mode(kind).cloned().unwrap_or_default();
+                *merged.mode_mut(kind) = Some(mode.merged(&base));
+            }
+        }
+
+        self.check(&merged)
+            .map_err(|err| WriteRejected(err.to_string()))?;
+
+        Ok(merged)
+    }
+
+    fn check(&self, merged: &Schedule) -> Result<()> {
+        for kind in Kind::ALL {
+            if let Some(mode) = self.mode(kind) {
+                mode.validate(kind)?;
+            }
+        }
+
+        let given = |kind| self.mode(kind).map_or(false, |mode| mode.slots.is_some());
+        let used = |kind| merged.mode(kind).map(Mode::used_slots).unwrap_or_default();
+
+        for kind in Kind::ALL.into_iter().filter(Kind::charges) {
+            if !given(kind) && !given(Kind::ForcedDischarge) {
+                continue;
+            }
+
+            let charging = used(kind);
+            for slot in used(Kind::ForcedDischarge) {
+                if let Some(other) = charging.iter().find(|other| slot.overlaps(other)) {
+                    bail!(
+                        "forced_discharge slot {} overlaps {} slot {}",
+                        slot,
+                        kind.name(),
+                        other
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // The raw register values for everything in this schedule. register21 is
+    // the current value of register 21, which the enable bits live in.
+    pub fn register_values(&self, register21: u16) -> BTreeMap<u16, u16> {
+        let mut values = BTreeMap::new();
+        let mut bits = register21;
+
+        for kind in Kind::ALL {
+            let mode = match self.mode(kind) {
+                Some(mode) => mode,
+                None => continue,
+            };
+
+            if let (Some(enabled), Some(bit)) = (mode.enabled, kind.enable_bit()) {
+                bits = if enabled { bits | bit } else { bits & !bit };
+            }
+
+            if let Some(slots) = &mode.slots {
+                let mut slots = slots.iter();
+                for register in kind.slot_registers().step_by(2) {
+                    let slot = slots.next().copied().unwrap_or_default();
+                    values.insert(register, slot.start.register());
+                    values.insert(register + 1, slot.end.register());
+                }
+            }
+
+            if let (Some(pct), Some(register)) = (mode.rate_pct, kind.rate_register()) {
+                values.insert(register as u16, pct);
+            }
+            if let (Some(pct), Some(register)) = (mode.soc_limit_pct, kind.soc_limit_register()) {
+                values.insert(register as u16, pct);
+            }
+        }
+
+        if bits != register21 {
+            values.insert(21, bits);
+        }
+
+        values
+    }
+} // }}}
@@ -0,0 +1,188 @@
+use crate::prelude::*;
+
+use std::collections::HashMap;
+use tokio::time::{Duration, Instant};
+
+// Returned instead of writing a holding register, so the result topic can say
+// why. Neither is a failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteSkipped {
+    // the register already holds value
+    Unchanged {
+        register: u16,
+        value: u16,
+    },
+    // the register was written less than write_interval ago, so value will be
+    // written in delay_secs unless another write to it comes first
+    Coalesced {
+        register: u16,
+        value: u16,
+        delay_secs: u64,
+        replaced: Option<u16>,
+    },
+}
+
+impl WriteSkipped {
+    // what the register holds, for JSON results
+    pub fn value(&self) -> Option<serde_json::Value> {
+        match self {
+            Self::Unchanged { value, .. } => Some((*value).into()),
+            Self::Coalesced { .. } => None,
+        }
+    }
+}
+
+impl std::fmt::Display for WriteSkipped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unchanged { register, value } => {
+                write!(f, "unchanged, register {} already {}", register, value)
+            }
+            Self::Coalesced {
+                register,
+                value,
+                delay_secs,
+                replaced,
+            } => {
+                write!(
+                    f,
+                    "coalesced, register {} will be set to {} in {}s",
+                    register, value, delay_secs
+                )?;
+                if let Some(replaced) = replaced {
+                    write!(f, " instead of {}", replaced)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for WriteSkipped {}
+
+// Where to say what became of a held back write: the result topic of a
+// command which asked for it, and its envelope if it had one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reply {
+    pub topic: String,
+    pub envelope: Option<mqtt::CommandEnvelope>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Pending {
+    pub inverter: config::Inverter,
+    pub register: u16,
+    pub value: u16,
+    pub due: Instant,
+    // everyone whose write this one stands in for
+    pub replies: Vec<Reply>,
+}
+
+// When each holding register was last written, and the latest write to each
+// which is waiting for write_interval to pass.
+#[derive(Default)]
+pub struct WriteLimiter {
+    last_write: HashMap<(Serial, u16), Instant>,
+    pending: HashMap<(Serial, u16), Pending>,
+}
+
+impl WriteLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // None if the write can go now. Otherwise it is held on to, replacing any
+    // earlier one to the same register.
+    pub fn defer(
+        &mut self,
+        inverter: &config::Inverter,
+        register: u16,
+        value: u16,
+        now: Instant,
+    ) -> Option<WriteSkipped> {
+        let key = (inverter.datalog(), register);
+
+        let due = match self.wait(inverter, register, now) {
+            Some(wait) => now + wait,
+            None => {
+                self.pending.remove(&key);
+                return None;
+            }
+        };
+
+        // whoever was waiting on the write this replaces hears about this one
+        let (replaced, replies) = match self.pending.remove(&key) {
+            Some(pending) => (Some(pending.value), pending.replies),
+            None => (None, Vec::new()),
+        };
+        let pending = Pending {
+            inverter: inverter.clone(),
+            register,
+            value,
+            due,
+            replies,
+        };
+        self.pending.insert(key, pending);
+
+        Some(WriteSkipped::Coalesced {
+            register,
+            value,
+            delay_secs: (due - now).as_secs_f64().ceil() as u64,
+            replaced,
+        })
+    }
+
+    // how long until register can be written again, if not now
+    pub fn wait(
+        &self,
+        inverter: &config::Inverter,
+        register: u16,
+        now: Instant,
+    ) -> Option<Duration> {
+        let interval = Duration::from_secs(inverter.write_interval());
+
+        match self.last_write.get(&(inverter.datalog(), register)) {
+            Some(last) if now < *last + interval => Some(*last + interval - now),
+            _ => None,
+        }
+    }
+
+    // the value waiting to be written to register, if any
+    pub fn pending_value(&self, datalog: Serial, register: u16) -> Option<u16> {
+        self.pending.get(&(datalog, register)).map(|p| p.value)
+    }
+
+    // to be told what becomes of the write pending for register
+    pub fn reply_to(&mut self, datalog: Serial, register: u16, reply: Reply) {
+        if let Some(pending) = self.pending.get_mut(&(datalog, register)) {
+            pending.replies.push(reply);
+        }
+    }
+
+    // a newer write made a pending one unnecessary
+    pub fn cancel(&mut self, datalog: Serial, register: u16) -> Option<Pending> {
+        self.pending.remove(&(datalog, register))
+    }
+
+    pub fn written(&mut self, datalog: Serial, register: u16, now: Instant) {
+        self.last_write.insert((datalog, register), now);
+    }
+
+    // removes and returns the pending writes which can go now
+    pub fn due(&mut self, now: Instant) -> Vec<Pending> {
+        let keys: Vec<(Serial, u16)> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.due <= now)
+            .map(|(key, _)| *key)
+            .collect();
+
+        keys.iter()
+            .filter_map(|key| self.pending.remove(key))
+            .collect()
+    }
+
+    pub fn next_due(&self) -> Option<Instant> {
+        self.pending.values().map(|pending| pending.due).min()
+    }
+}
@@ -5,6 +5,9 @@ use sqlx::{any::AnyConnectOptions, AnyPool, ConnectOptions};
 #[derive(PartialEq, Clone, Debug)]
 pub enum ChannelData {
     ReadInputAll(Box<lxp::packet::ReadInputAll>),
+    ReadInputAll2(Box<lxp::packet::ReadInputAll2>),
+    AlarmEvent(coordinator::alarms::AlarmEvent),
+    EnergyTotals(coordinator::energy::EnergyTotals),
     Shutdown,
 }
 
@@ -153,6 +156,55 @@ impl Database {
             values
         );
 
+        let inputs2_query = format!(
+            r#"
+            INSERT INTO inputs2
+              ( v_eps_l1, v_eps_l2, p_eps_l1, p_eps_l2, s_eps_l1, s_eps_l2,
+                e_eps_l1_day, e_eps_l2_day, e_eps_l1_all, e_eps_l2_all,
+
+                afci_ch1_current, afci_ch2_current, afci_ch3_current, afci_ch4_current,
+                register_144,
+                afci_arc_ch1, afci_arc_ch2, afci_arc_ch3, afci_arc_ch4,
+                afci_max_arc_ch1, afci_max_arc_ch2, afci_max_arc_ch3, afci_max_arc_ch4,
+
+                p_ac_couple, p_load, e_load_day, e_load_all, eps_overload_ctrl_time,
+
+                p_inv_s, p_inv_t, p_rec_s, p_rec_t,
+                p_to_grid_s, p_to_grid_t, p_to_user_s, p_to_user_t,
+                p_gen_s, p_gen_t, inv_rms_curr_s, inv_rms_curr_t, pf_s,
+                v_grid_l1, v_grid_l2, v_gen_l1, v_gen_l2,
+                p_inv_l1, p_inv_l2, p_rec_l1, p_rec_l2,
+                p_to_grid_l1, p_to_grid_l2, p_to_user_l1, p_to_user_l2,
+                pf_t,
+
+                datalog, created_at
+              )
+            VALUES {} "#,
+            self.placeholders(56)?
+        );
+
+        let alarm_events_query = format!(
+            r#"
+            INSERT INTO alarm_events
+              ( kind, state, bit, code, description, datalog, created_at )
+            VALUES {} "#,
+            self.placeholders(7)?
+        );
+
+        let energy_query = format!(
+            r#"
+            INSERT INTO energy
+              ( e_pv_total, e_pv_interval, e_inv_total, e_inv_interval,
+                e_rec_total, e_rec_interval, e_chg_total, e_chg_interval,
+                e_dischg_total, e_dischg_interval, e_eps_total, e_eps_interval,
+                e_to_grid_total, e_to_grid_interval, e_to_user_total, e_to_user_interval,
+
+                datalog, created_at
+              )
+            VALUES {} "#,
+            self.placeholders(18)?
+        );
+
         loop {
             use ChannelData::*;
 
@@ -164,6 +216,25 @@ impl Database {
                         tokio::time::sleep(std::time::Duration::from_secs(10)).await;
                     }
                 }
+                ReadInputAll2(data) => {
+                    while let Err(err) = self.insert_inputs2(&inputs2_query, &data).await {
+                        error!("INSERT failed: {:?} - retrying in 10s", err);
+                        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+                    }
+                }
+                AlarmEvent(event) => {
+                    while let Err(err) = self.insert_alarm_event(&alarm_events_query, &event).await
+                    {
+                        error!("INSERT failed: {:?} - retrying in 10s", err);
+                        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+                    }
+                }
+                EnergyTotals(totals) => {
+                    while let Err(err) = self.insert_energy(&energy_query, &totals).await {
+                        error!("INSERT failed: {:?} - retrying in 10s", err);
+                        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+                    }
+                }
             }
         }
 
@@ -272,6 +343,138 @@ impl Database {
         Ok(())
     }
 
+    async fn insert_inputs2(&self, query: &str, data: &lxp::packet::ReadInputAll2) -> Result<()> {
+        let mut conn = self.connection().await?;
+
+        sqlx::query(query)
+            .bind(data.v_eps_l1)
+            .bind(data.v_eps_l2)
+            .bind(data.p_eps_l1 as i32)
+            .bind(data.p_eps_l2 as i32)
+            .bind(data.s_eps_l1 as i32)
+            .bind(data.s_eps_l2 as i32)
+            .bind(data.e_eps_l1_day)
+            .bind(data.e_eps_l2_day)
+            .bind(data.e_eps_l1_all)
+            .bind(data.e_eps_l2_all)
+            .bind(data.afci_ch1_current as i32)
+            .bind(data.afci_ch2_current as i32)
+            .bind(data.afci_ch3_current as i32)
+            .bind(data.afci_ch4_current as i32)
+            .bind(data.register_144 as i32)
+            .bind(data.afci_arc_ch1 as i32)
+            .bind(data.afci_arc_ch2 as i32)
+            .bind(data.afci_arc_ch3 as i32)
+            .bind(data.afci_arc_ch4 as i32)
+            .bind(data.afci_max_arc_ch1 as i32)
+            .bind(data.afci_max_arc_ch2 as i32)
+            .bind(data.afci_max_arc_ch3 as i32)
+            .bind(data.afci_max_arc_ch4 as i32)
+            .bind(data.p_ac_couple as i32)
+            .bind(data.p_load as i32)
+            .bind(data.e_load_day)
+            .bind(data.e_load_all)
+            .bind(data.eps_overload_ctrl_time as i32)
+            .bind(data.p_inv_s as i32)
+            .bind(data.p_inv_t as i32)
+            .bind(data.p_rec_s as i32)
+            .bind(data.p_rec_t as i32)
+            .bind(data.p_to_grid_s as i32)
+            .bind(data.p_to_grid_t as i32)
+            .bind(data.p_to_user_s as i32)
+            .bind(data.p_to_user_t as i32)
+            .bind(data.p_gen_s as i32)
+            .bind(data.p_gen_t as i32)
+            .bind(data.inv_rms_curr_s)
+            .bind(data.inv_rms_curr_t)
+            .bind(data.pf_s)
+            .bind(data.v_grid_l1)
+            .bind(data.v_grid_l2)
+            .bind(data.v_gen_l1)
+            .bind(data.v_gen_l2)
+            .bind(data.p_inv_l1 as i32)
+            .bind(data.p_inv_l2 as i32)
+            .bind(data.p_rec_l1 as i32)
+            .bind(data.p_rec_l2 as i32)
+            .bind(data.p_to_grid_l1 as i32)
+            .bind(data.p_to_grid_l2 as i32)
+            .bind(data.p_to_user_l1 as i32)
+            .bind(data.p_to_user_l2 as i32)
+            .bind(data.pf_t)
+            .bind(data.datalog.to_string())
+            .bind(data.time.0)
+            .persistent(true)
+            .fetch_optional(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn insert_alarm_event(
+        &self,
+        query: &str,
+        event: &coordinator::alarms::AlarmEvent,
+    ) -> Result<()> {
+        let mut conn = self.connection().await?;
+
+        sqlx::query(query)
+            .bind(event.kind.as_str())
+            .bind(event.state.as_str())
+            .bind(event.bit as i32)
+            .bind(event.code)
+            .bind(event.description)
+            .bind(event.datalog.to_string())
+            .bind(event.time.0)
+            .persistent(true)
+            .fetch_optional(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn insert_energy(
+        &self,
+        query: &str,
+        totals: &coordinator::energy::EnergyTotals,
+    ) -> Result<()> {
+        let mut conn = self.connection().await?;
+
+        sqlx::query(query)
+            .bind(totals.e_pv_total)
+            .bind(totals.e_pv_interval)
+            .bind(totals.e_inv_total)
+            .bind(totals.e_inv_interval)
+            .bind(totals.e_rec_total)
+            .bind(totals.e_rec_interval)
+            .bind(totals.e_chg_total)
+            .bind(totals.e_chg_interval)
+            .bind(totals.e_dischg_total)
+            .bind(totals.e_dischg_interval)
+            .bind(totals.e_eps_total)
+            .bind(totals.e_eps_interval)
+            .bind(totals.e_to_grid_total)
+            .bind(totals.e_to_grid_interval)
+            .bind(totals.e_to_user_total)
+            .bind(totals.e_to_user_interval)
+            .bind(totals.datalog.to_string())
+            .bind(totals.time.0)
+            .persistent(true)
+            .fetch_optional(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    // builds a VALUES list of `count` placeholders in the style the database expects
+    fn placeholders(&self, count: usize) -> Result<String> {
+        let placeholders: Vec<String> = match self.database()? {
+            DatabaseType::MySQL => (1..=count).map(|_| "?".to_string()).collect(),
+            _ => (1..=count).map(|i| format!("${}", i)).collect(),
+        };
+
+        Ok(format!("({})", placeholders.join(", ")))
+    }
+
     fn values_for_mysql() -> &'static str {
         r#"(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
             ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
@@ -0,0 +1,310 @@
+use crate::prelude::*;
+
+use lxp::packet::{DeviceFunction, ReadInput, TranslatedData};
+use serde::Serialize;
+
+// Controller {{{
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    // we don't know what the register holds yet
+    Waiting,
+    // export is between limit - deadband and limit
+    InBand,
+    // a change is wanted but the register is already at min_pct/max_pct
+    AtLimit,
+    // a change is wanted but we wrote too recently
+    RateLimited,
+    Write,
+}
+
+// What the controller made of one p_to_grid reading, as published to
+// {datalog}/export_limit.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct State {
+    pub p_to_grid: u32,
+    pub limit: u32,
+    pub register: u16,
+    // what the register was last seen to hold
+    pub pct: Option<u16>,
+    pub wanted_pct: Option<u16>,
+    pub action: Action,
+}
+
+// A proportional controller: each reading outside the band moves the
+// register by gain times the error, aiming for the middle of the band.
+pub struct Controller {
+    config: config::ExportLimit,
+    pct: Option<u16>,
+    last_write: Option<i64>,
+}
+
+impl Controller {
+    pub fn new(config: config::ExportLimit) -> Self {
+        Self {
+            config,
+            pct: None,
+            last_write: None,
+        }
+    }
+
+    pub fn pct(&self) -> Option<u16> {
+        self.pct
+    }
+
+    // the register's value as read back from the inverter, or as the inverter
+    // confirms a write. pct only changes here, so a write which doesn't
+    // happen is tried again rather than taken as done.
+    pub fn observe(&mut self, pct: u16) {
+        self.pct = Some(pct);
+    }
+
+    // the register was written with a value we don't know, so wait until it
+    // has been read again.
+    pub fn forget(&mut self) {
+        self.pct = None;
+    }
+
+    // now is in seconds
+    pub fn update(&mut self, p_to_grid: u32, now: i64) -> State {
+        let (action, wanted_pct) = self.decide(p_to_grid, now);
+
+        if action == Action::Write {
+            self.last_write = Some(now);
+        }
+
+        State {
+            p_to_grid,
+            limit: self.config.limit(),
+            register: self.config.register(),
+            pct: self.pct,
+            wanted_pct,
+            action,
+        }
+    }
+
+    fn decide(&self, p_to_grid: u32, now: i64) -> (Action, Option<u16>) {
+        let pct = match self.pct {
+            Some(pct) => pct,
+            None => return (Action::Waiting, None),
+        };
+
+        let limit = self.config.limit() as f64;
+        let deadband = self.config.deadband() as f64;
+        let p_to_grid = p_to_grid as f64;
+
+        if p_to_grid <= limit && p_to_grid >= limit - deadband {
+            return (Action::InBand, None);
+        }
+
+        let target = limit - deadband / 2.0;
+        let step =
+            self.config.gain() * (target - p_to_grid) / self.config.max_power() as f64 * 100.0;
+        // always move by at least 1%, or a small gain could never get there
+        let step = if step > 0.0 {
+            step.ceil()
+        } else {
+            step.floor()
+        };
+
+        let min_pct = self.config.min_pct() as f64;
+        let max_pct = self.config.max_pct().max(self.config.min_pct()) as f64;
+        let wanted = (pct as f64 + step).clamp(min_pct, max_pct) as u16;
+
+        if wanted == pct {
+            return (Action::AtLimit, None);
+        }
+
+        if let Some(last_write) = self.last_write {
+            if now - last_write < self.config.min_interval() as i64 {
+                return (Action::RateLimited, Some(wanted));
+            }
+        }
+
+        (Action::Write, Some(wanted))
+    }
+} // }}}
+
+// ExportLimit {{{
+pub struct ExportLimit {
+    config: ConfigWrapper,
+    channels: Channels,
+}
+
+impl ExportLimit {
+    pub fn new(config: ConfigWrapper, channels: Channels) -> Self {
+        Self { config, channels }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        use lxp::inverter::ChannelData;
+
+        let export_limit = self.config.export_limit().clone();
+        if export_limit.is_none() {
+            info!("export_limit config not found, skipping");
+            return Ok(());
+        }
+
+        let export_limit = export_limit.unwrap();
+        if !export_limit.enabled() {
+            info!("export_limit disabled, skipping");
+            return Ok(());
+        }
+
+        let register = export_limit.register();
+        if register != 65 && register != 82 {
+            bail!("export_limit: register must be 65 or 82, not {}", register);
+        }
+
+        let inverter = match self
+            .config
+            .enabled_inverter_with_datalog(export_limit.datalog())
+        {
+            Some(inverter) => inverter,
+            None => bail!(
+                "export_limit: no enabled inverter with datalog {}",
+                export_limit.datalog()
+            ),
+        };
+
+        info!(
+            "export_limit starting, keeping export under {}W with register {}",
+            export_limit.limit(),
+            register
+        );
+
+        let mut controller = Controller::new(export_limit.clone());
+        let mut last_read: Option<i64> = None;
+        let mut receiver = self.channels.from_inverter.subscribe();
+
+        loop {
+            let td = match receiver.recv().await {
+                Ok(ChannelData::Packet(Packet::TranslatedData(td)))
+                    if td.datalog == inverter.datalog() =>
+                {
+                    td
+                }
+                Ok(ChannelData::Shutdown) | Err(broadcast::error::RecvError::Closed) => break,
+                _ => continue,
+            };
+
+            match td.device_function {
+                // whoever asked, this tells us what the register holds now
+                DeviceFunction::ReadHold | DeviceFunction::WriteSingle => {
+                    if let Some((_, value)) = td.pairs().into_iter().find(|(r, _)| *r == register) {
+                        controller.observe(value);
+                    }
+                    continue;
+                }
+                // only says how many registers were written (eg by a preset),
+                // not what to
+                DeviceFunction::WriteMulti => {
+                    let count = td.value();
+                    if (td.register..td.register.saturating_add(count)).contains(&register) {
+                        controller.forget();
+                    }
+                    continue;
+                }
+                DeviceFunction::ReadInput => {}
+                _ => continue,
+            }
+
+            let p_to_grid = match td.read_input() {
+                Ok(ReadInput::ReadInputAll(r)) => r.p_to_grid,
+                Ok(ReadInput::ReadInput1(r)) => r.p_to_grid,
+                _ => continue,
+            };
+
+            let now = Utils::utc().timestamp();
+
+            if controller.pct().is_none()
+                && last_read.map_or(true, |t| now - t >= export_limit.min_interval() as i64)
+            {
+                self.read_register(&inverter, register)?;
+                last_read = Some(now);
+            }
+
+            let state = controller.update(p_to_grid as u32, now);
+            debug!("export_limit: {:?}", state);
+
+            if state.action == Action::Write {
+                if let Some(pct) = state.wanted_pct {
+                    info!(
+                        "export_limit: {}W export, setting register {} to {}%",
+                        p_to_grid, register, pct
+                    );
+                    self.write_register(&inverter, register, pct)?;
+                }
+            }
+
+            self.publish(&inverter, &state)?;
+        }
+
+        info!("export_limit exiting");
+
+        Ok(())
+    }
+
+    // the reply comes back through the loop above
+    fn read_register(&self, inverter: &config::Inverter, register: u16) -> Result<()> {
+        let packet = Packet::TranslatedData(TranslatedData {
+            datalog: inverter.datalog(),
+            device_function: DeviceFunction::ReadHold,
+            inverter: inverter.serial(),
+            register,
+            values: 1_u16.to_le_bytes().to_vec(),
+        });
+
+        if self
+            .channels
+            .to_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet))
+            .is_err()
+        {
+            bail!("send(to_inverter) failed - channel closed?");
+        }
+
+        Ok(())
+    }
+
+    // through the coordinator, so the usual write safeguards apply
+    fn write_register(&self, inverter: &config::Inverter, register: u16, pct: u16) -> Result<()> {
+        let message = mqtt::Message {
+            topic: format!("cmd/{}/set/hold/{}", inverter.datalog(), register),
+            retain: false,
+            payload: pct.to_string(),
+        };
+        if self
+            .channels
+            .from_mqtt
+            .send(mqtt::ChannelData::Message(message))
+            .is_err()
+        {
+            bail!("send(from_mqtt) failed - channel closed?");
+        }
+
+        Ok(())
+    }
+
+    fn publish(&self, inverter: &config::Inverter, state: &State) -> Result<()> {
+        if !self.config.mqtt().enabled() {
+            return Ok(());
+        }
+
+        let message = mqtt::Message {
+            topic: format!("{}/export_limit", inverter.datalog()),
+            retain: true,
+            payload: serde_json::to_string(state)?,
+        };
+        if self
+            .channels
+            .to_mqtt
+            .send(mqtt::ChannelData::Message(message))
+            .is_err()
+        {
+            debug!("could not publish export_limit state, MQTT not connected?");
+        }
+
+        Ok(())
+    }
+} // }}}
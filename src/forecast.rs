@@ -0,0 +1,376 @@
+use crate::prelude::*;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use coordinator::metrics::HouseholdMetrics;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// the forecast is totalled over this long from when the limit is set
+const HORIZON_HOURS: i64 = 24;
+
+// Period {{{
+// One period of a Solcast-style forecast; pv_estimate is the average power
+// (kW) over the period ending at period_end.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Period {
+    pub pv_estimate: f64,
+    #[serde(deserialize_with = "Utils::de_rfc3339")]
+    pub period_end: DateTime<Utc>,
+    #[serde(default = "Period::default_period", deserialize_with = "de_period")]
+    pub period: Duration,
+}
+
+impl Period {
+    fn default_period() -> Duration {
+        Duration::minutes(30)
+    }
+}
+
+// ISO 8601 durations as Solcast uses them, eg PT30M or PT1H
+fn de_period<'de, D>(deserializer: D) -> std::result::Result<Duration, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    let parsed = s.strip_prefix("PT").and_then(|s| {
+        let unit = s.chars().last()?;
+        let number: i64 = s[..s.len() - unit.len_utf8()].parse().ok()?;
+        match unit {
+            'H' => Some(Duration::hours(number)),
+            'M' => Some(Duration::minutes(number)),
+            'S' => Some(Duration::seconds(number)),
+            _ => None,
+        }
+    });
+
+    parsed.ok_or_else(|| serde::de::Error::custom(format!("unsupported period {}", s)))
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Periods {
+    List(Vec<Period>),
+    Forecasts { forecasts: Vec<Period> },
+}
+
+// a bare list of periods, or one under "forecasts"
+pub fn parse_forecast(json: &str) -> Result<Vec<Period>> {
+    match serde_json::from_str(json)? {
+        Periods::List(periods) | Periods::Forecasts { forecasts: periods } => Ok(periods),
+    }
+}
+
+// expected generation (kWh) between from and to, counting only the part of
+// each period which falls inside them
+pub fn pv_energy(periods: &[Period], from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    let kwh: f64 = periods
+        .iter()
+        .map(|p| {
+            let start = (p.period_end - p.period).max(from);
+            let end = p.period_end.min(to);
+            if end > start {
+                p.pv_estimate * (end - start).num_seconds() as f64 / 3600.0
+            } else {
+                0.0
+            }
+        })
+        .sum();
+
+    Utils::round(kwh, 2)
+} // }}}
+
+// ConsumptionHistory {{{
+// The last e_consumption_day seen on each day, which by the end of a day is
+// that day's total.
+#[derive(Clone, Debug, Default)]
+pub struct ConsumptionHistory {
+    days: BTreeMap<NaiveDate, f64>,
+}
+
+impl ConsumptionHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, date: NaiveDate, kwh: f64) {
+        self.days.insert(date, kwh);
+    }
+
+    // average of up to the last `days` complete days before today, and how
+    // many days that was
+    pub fn average(&mut self, today: NaiveDate, days: usize) -> Option<(f64, usize)> {
+        // anything older than we'll ever use can go
+        while self.days.len() > days + 1 {
+            self.days.pop_first();
+        }
+
+        let complete: Vec<f64> = self
+            .days
+            .range(..today)
+            .rev()
+            .take(days)
+            .map(|(_, kwh)| *kwh)
+            .collect();
+
+        if complete.is_empty() {
+            None
+        } else {
+            let average = complete.iter().sum::<f64>() / complete.len() as f64;
+            Some((Utils::round(average, 2), complete.len()))
+        }
+    }
+} // }}}
+
+// Target {{{
+// The SOC to charge to overnight, and how it was arrived at. Published to
+// {datalog}/forecast/target.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Target {
+    pub pv_kwh: f64,
+    pub consumption_kwh: f64,
+    // days of history consumption_kwh is the average of; 0 if it is the
+    // configured default
+    pub consumption_days: usize,
+    pub capacity_kwh: f64,
+    pub shortfall_kwh: f64,
+    pub soc: u16,
+    pub reason: String,
+}
+
+impl Target {
+    // Enough charge to cover what tomorrow's PV won't (plus margin_pct), on
+    // top of min_soc, and no more than max_soc.
+    pub fn new(
+        config: &config::Forecast,
+        pv_kwh: f64,
+        consumption_kwh: f64,
+        consumption_days: usize,
+        capacity_kwh: f64,
+    ) -> Self {
+        let shortfall_kwh = Utils::round(
+            (consumption_kwh - pv_kwh).max(0.0) * (1.0 + config.margin_pct() / 100.0),
+            2,
+        );
+
+        let (min_soc, max_soc) = (config.min_soc(), config.max_soc().max(config.min_soc()));
+        let soc = if capacity_kwh > 0.0 {
+            // rounded first so float noise can't add a percent
+            let pct = Utils::round(shortfall_kwh / capacity_kwh * 100.0, 2);
+            let wanted = min_soc as f64 + pct.ceil();
+            wanted.min(max_soc as f64) as u16
+        } else {
+            max_soc
+        };
+
+        let reason = if shortfall_kwh == 0.0 {
+            format!(
+                "{}kWh PV forecast covers {}kWh expected use, charging to {}%",
+                pv_kwh, consumption_kwh, soc
+            )
+        } else {
+            format!(
+                "{}kWh PV forecast leaves {}kWh of {}kWh expected use to cover (with {}% margin), charging to {}%",
+                pv_kwh,
+                shortfall_kwh,
+                consumption_kwh,
+                config.margin_pct(),
+                soc
+            )
+        };
+
+        Self {
+            pv_kwh,
+            consumption_kwh,
+            consumption_days,
+            capacity_kwh: Utils::round(capacity_kwh, 2),
+            shortfall_kwh,
+            soc,
+            reason,
+        }
+    }
+} // }}}
+
+// Forecast {{{
+pub struct Forecast {
+    config: ConfigWrapper,
+    channels: Channels,
+    // kept in memory only, so after a restart the configured consumption is
+    // used until a day has been seen through
+    history: RefCell<ConsumptionHistory>,
+    tariff_target_soc: tariff::TargetSoc,
+}
+
+impl Forecast {
+    pub fn new(
+        config: ConfigWrapper,
+        channels: Channels,
+        tariff_target_soc: tariff::TargetSoc,
+    ) -> Self {
+        Self {
+            config,
+            channels,
+            history: RefCell::new(ConsumptionHistory::new()),
+            tariff_target_soc,
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let forecast = self.config.forecast().clone();
+        if forecast.is_none() {
+            info!("forecast config not found, skipping");
+            return Ok(());
+        }
+
+        let forecast = forecast.unwrap();
+        if !forecast.enabled() {
+            info!("forecast disabled, skipping");
+            return Ok(());
+        }
+
+        let inverter = match self
+            .config
+            .enabled_inverter_with_datalog(forecast.datalog())
+        {
+            Some(inverter) => inverter,
+            None => bail!(
+                "forecast: no enabled inverter with datalog {}",
+                forecast.datalog()
+            ),
+        };
+
+        info!("forecast starting");
+
+        let scheduler = Scheduler::new(self.config.clone(), self.channels.clone());
+        futures::try_join!(
+            self.record_consumption(&inverter),
+            // cron is on the inverter's clock, like the days consumption is
+            // recorded for
+            scheduler.every_in(
+                "forecast".to_owned(),
+                forecast.cron().to_owned(),
+                inverter.timezone(),
+                || async { self.run(&forecast, &inverter).await.map(|_| ()) }
+            )
+        )?;
+
+        info!("forecast exiting");
+
+        Ok(())
+    }
+
+    // Keeps track of each day's consumption from the inputs the inverter
+    // sends, whoever asked for them. Days are the inverter's, as that's when
+    // its daily totals reset.
+    async fn record_consumption(&self, inverter: &config::Inverter) -> Result<()> {
+        use lxp::inverter::ChannelData;
+        use lxp::packet::{DeviceFunction, ReadInput};
+
+        let mut receiver = self.channels.from_inverter.subscribe();
+
+        loop {
+            let td = match receiver.recv().await {
+                Ok(ChannelData::Packet(Packet::TranslatedData(td)))
+                    if td.datalog == inverter.datalog()
+                        && td.device_function == DeviceFunction::ReadInput =>
+                {
+                    td
+                }
+                Ok(ChannelData::Shutdown) | Err(broadcast::error::RecvError::Closed) => break,
+                _ => continue,
+            };
+
+            let kwh = match td.read_input() {
+                Ok(ReadInput::ReadInputAll(r)) => HouseholdMetrics::e_consumption_day(
+                    r.e_inv_day,
+                    r.e_rec_day,
+                    r.e_to_user_day,
+                    r.e_to_grid_day,
+                ),
+                Ok(ReadInput::ReadInput1(r)) => HouseholdMetrics::e_consumption_day(
+                    r.e_inv_day,
+                    r.e_rec_day,
+                    r.e_to_user_day,
+                    r.e_to_grid_day,
+                ),
+                _ => continue,
+            };
+
+            self.history
+                .borrow_mut()
+                .record(inverter.local_time().date(), kwh);
+        }
+
+        Ok(())
+    }
+
+    // Works out the target from the latest forecast and publishes it. If
+    // tariff is planning for this inverter too, the target becomes its
+    // target_soc from its next plan; otherwise register 67 is set directly
+    // through set/ac_charge_soc_limit_pct.
+    pub async fn run(
+        &self,
+        forecast: &config::Forecast,
+        inverter: &config::Inverter,
+    ) -> Result<Target> {
+        let periods = parse_forecast(&Utils::fetch(forecast.source()).await?)?;
+        let now = Utils::utc();
+        let pv_kwh = pv_energy(&periods, now, now + Duration::hours(HORIZON_HOURS));
+
+        let capacity_kwh =
+            tariff::battery_capacity(&self.channels, inverter, forecast.battery_voltage()).await?;
+
+        let today = inverter.local_time().date();
+        let history = self
+            .history
+            .borrow_mut()
+            .average(today, forecast.history_days());
+        let (consumption_kwh, consumption_days) = history.unwrap_or((forecast.consumption(), 0));
+
+        let target = Target::new(
+            forecast,
+            pv_kwh,
+            consumption_kwh,
+            consumption_days,
+            capacity_kwh,
+        );
+        info!("forecast: {}", target.reason);
+
+        tariff::publish(
+            &self.config,
+            &self.channels,
+            format!("{}/forecast/target", inverter.datalog()),
+            &target,
+        )?;
+
+        if self.tariff_enabled(inverter) {
+            info!("forecast: tariff target_soc is now {}%", target.soc);
+            self.tariff_target_soc.set(Some(target.soc));
+            return Ok(target);
+        }
+
+        let message = mqtt::Message {
+            topic: format!("cmd/{}/set/ac_charge_soc_limit_pct", inverter.datalog()),
+            retain: false,
+            payload: target.soc.to_string(),
+        };
+        if self
+            .channels
+            .from_mqtt
+            .send(mqtt::ChannelData::Message(message))
+            .is_err()
+        {
+            bail!("send(from_mqtt) failed - channel closed?");
+        }
+
+        Ok(target)
+    }
+
+    // tariff sets register 67 with every schedule it sends, so when it's
+    // planning for this inverter we hand it our target instead of racing it
+    fn tariff_enabled(&self, inverter: &config::Inverter) -> bool {
+        self.config
+            .tariff()
+            .as_ref()
+            .is_some_and(|t| t.enabled() && t.datalog() == inverter.datalog())
+    }
+} // }}}
@@ -82,6 +82,7 @@ pub struct Device {
 pub struct Config {
     inverter: config::Inverter,
     mqtt_config: config::Mqtt,
+    presets: Vec<String>,
 }
 
 // https://www.home-assistant.io/integrations/sensor.mqtt/
@@ -163,14 +164,34 @@ pub struct Text {
     pattern: String,
 }
 
+// https://www.home-assistant.io/integrations/select.mqtt/
+#[derive(Debug, Serialize)]
+pub struct Select {
+    name: String,
+    state_topic: String,
+    command_topic: String,
+    value_template: String,
+    options: Vec<String>,
+    unique_id: String,
+    device: Device,
+    availability: Availability,
+}
+
 impl Config {
     pub fn new(inverter: &config::Inverter, mqtt_config: &config::Mqtt) -> Self {
         Self {
             inverter: inverter.clone(),
             mqtt_config: mqtt_config.clone(),
+            presets: Vec::new(),
         }
     }
 
+    // names of the configured presets, offered by a select entity
+    pub fn with_presets(mut self, presets: Vec<String>) -> Self {
+        self.presets = presets;
+        self
+    }
+
     pub fn sensors(&self) -> Vec<mqtt::Message> {
         let base = Entity {
             key: &String::default(),
@@ -608,6 +629,52 @@ impl Config {
                 name: "Power to Grid",
                 ..power.clone()
             },
+            Entity {
+                key: "p_consumption",
+                name: "Consumption Power",
+                ..power.clone()
+            },
+            Entity {
+                key: "p_pv_to_battery",
+                name: "Power from PV to Battery",
+                ..power.clone()
+            },
+            Entity {
+                key: "p_pv_to_load",
+                name: "Power from PV to Load",
+                ..power.clone()
+            },
+            Entity {
+                key: "p_grid_to_battery",
+                name: "Power from Grid to Battery",
+                ..power.clone()
+            },
+            Entity {
+                key: "e_consumption_day",
+                name: "Consumption (Today)",
+                ..energy.clone()
+            },
+            Entity {
+                key: "self_consumption_pct",
+                name: "Self-consumption (Today)",
+                state_class: Some("measurement"),
+                unit_of_measurement: Some("%"),
+                ..base.clone()
+            },
+            Entity {
+                key: "self_sufficiency_pct",
+                name: "Self-sufficiency (Today)",
+                state_class: Some("measurement"),
+                unit_of_measurement: Some("%"),
+                ..base.clone()
+            },
+            Entity {
+                key: "battery_efficiency_pct",
+                name: "Battery Round-trip Efficiency (Today)",
+                state_class: Some("measurement"),
+                unit_of_measurement: Some("%"),
+                ..base.clone()
+            },
             Entity {
                 key: "p_eps",
                 name: "Active EPS Power",
@@ -743,6 +810,46 @@ impl Config {
                 name: "Energy of Inverter (Today)",
                 ..energy.clone()
             },
+            Entity {
+                key: "e_pv_total",
+                name: "PV Generation (Tracked total)",
+                ..energy.clone()
+            },
+            Entity {
+                key: "e_chg_total",
+                name: "Battery Charge (Tracked total)",
+                ..energy.clone()
+            },
+            Entity {
+                key: "e_dischg_total",
+                name: "Battery Discharge (Tracked total)",
+                ..energy.clone()
+            },
+            Entity {
+                key: "e_to_user_total",
+                name: "Energy from Grid (Tracked total)",
+                ..energy.clone()
+            },
+            Entity {
+                key: "e_to_grid_total",
+                name: "Energy to Grid (Tracked total)",
+                ..energy.clone()
+            },
+            Entity {
+                key: "e_eps_total",
+                name: "Energy from EPS (Tracked total)",
+                ..energy.clone()
+            },
+            Entity {
+                key: "e_rec_total",
+                name: "Energy of AC Charging (Tracked total)",
+                ..energy.clone()
+            },
+            Entity {
+                key: "e_inv_total",
+                name: "Energy of Inverter (Tracked total)",
+                ..energy.clone()
+            },
             Entity {
                 key: "e_eps_l1_all",
                 name: "Energy of EPS L1 (All time)",
@@ -1156,6 +1263,10 @@ impl Config {
             self.number(Register::GenCoolDownTime, "Generator Cool Down Time (min)")?,
         ];
 
+        if !self.presets.is_empty() {
+            r.push(self.preset_select()?);
+        }
+
         r.append(&mut self.sensors());
 
         Ok(r)
@@ -1310,6 +1421,33 @@ impl Config {
         })
     }
 
+    fn preset_select(&self) -> Result<mqtt::Message> {
+        let config = Select {
+            name: "Preset".to_string(),
+            state_topic: format!(
+                "{}/{}/preset",
+                self.mqtt_config.namespace(),
+                self.inverter.datalog()
+            ),
+            command_topic: format!(
+                "{}/cmd/{}/set/preset",
+                self.mqtt_config.namespace(),
+                self.inverter.datalog()
+            ),
+            value_template: "{{ value_json.name }}".to_string(),
+            options: self.presets.clone(),
+            unique_id: self.unique_id("preset"),
+            device: self.device(),
+            availability: self.availability(),
+        };
+
+        Ok(mqtt::Message {
+            topic: self.ha_discovery_topic("select", "preset"),
+            retain: true,
+            payload: serde_json::to_string(&config)?,
+        })
+    }
+
     fn unique_id(&self, name: &str) -> String {
         format!("lxp_{}_{}", self.inverter.datalog(), name)
     }
@@ -1324,7 +1462,7 @@ impl Config {
 
     fn availability(&self) -> Availability {
         Availability {
-            topic: format!("{}/LWT", self.mqtt_config.namespace()),
+            topic: self.mqtt_config.lwt_topic(),
         }
     }
 }
@@ -1,13 +1,21 @@
 use crate::prelude::*;
 
 use chrono::TimeZone;
-use rinfluxdb::line_protocol::{r#async::Client, LineBuilder};
+use rinfluxdb::line_protocol::{r#async::Client, Line, LineBuilder};
 
 static INPUTS_MEASUREMENT: &str = "inputs";
+static INPUTS2_MEASUREMENT: &str = "inputs2";
+static EVENTS_MEASUREMENT: &str = "events";
+
+// keys which are sent as tags rather than fields, per measurement
+static INPUTS_TAGS: &[&str] = &["datalog"];
+static EVENTS_TAGS: &[&str] = &["datalog", "kind", "state", "code"];
 
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub enum ChannelData {
     InputData(serde_json::Value),
+    Inputs2Data(serde_json::Value),
+    EventData(serde_json::Value),
     Shutdown,
 }
 
@@ -59,45 +67,18 @@ impl Influx {
         let mut receiver = self.channels.to_influx.subscribe();
 
         loop {
-            let mut line = LineBuilder::new(INPUTS_MEASUREMENT);
-
-            match receiver.recv().await? {
+            let line = match receiver.recv().await? {
                 Shutdown => break,
-                InputData(data) => {
-                    for (key, value) in data.as_object().unwrap() {
-                        let key = key.to_string();
-
-                        line = if key == "time" {
-                            let value = value.as_i64().unwrap_or_else(|| {
-                                panic!("cannot represent {value} as i64 for {key}")
-                            });
-                            line.set_timestamp(chrono::Utc.timestamp_opt(value, 0).unwrap())
-                        } else if key == "datalog" {
-                            let value = value.as_str().unwrap_or_else(|| {
-                                panic!("cannot represent {value} as str for {key}")
-                            });
-                            line.insert_tag(key, value)
-                        } else if value.is_f64() {
-                            let value = value.as_f64().unwrap_or_else(|| {
-                                panic!("cannot represent {value} as f64 for {key}")
-                            });
-                            line.insert_field(key, value)
-                        } else {
-                            // can't be anything other than int
-                            let value = value.as_i64().unwrap_or_else(|| {
-                                panic!("cannot represent {value} as i64 for {key}")
-                            });
-                            line.insert_field(key, value)
-                        }
-                    }
-
-                    let lines = vec![line.build()];
-
-                    while let Err(err) = client.send(&self.database(), &lines).await {
-                        error!("push failed: {:?} - retrying in 10s", err);
-                        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
-                    }
-                }
+                InputData(data) => Self::line(INPUTS_MEASUREMENT, INPUTS_TAGS, &data),
+                Inputs2Data(data) => Self::line(INPUTS2_MEASUREMENT, INPUTS_TAGS, &data),
+                EventData(data) => Self::line(EVENTS_MEASUREMENT, EVENTS_TAGS, &data),
+            };
+
+            let lines = vec![line];
+
+            while let Err(err) = client.send(&self.database(), &lines).await {
+                error!("push failed: {:?} - retrying in 10s", err);
+                tokio::time::sleep(std::time::Duration::from_secs(10)).await;
             }
         }
 
@@ -106,6 +87,41 @@ impl Influx {
         Ok(())
     }
 
+    fn line(measurement: &str, tags: &[&str], data: &serde_json::Value) -> Line {
+        let mut line = LineBuilder::new(measurement);
+
+        for (key, value) in data.as_object().unwrap() {
+            let key = key.to_string();
+
+            line = if key == "time" {
+                let value = value
+                    .as_i64()
+                    .unwrap_or_else(|| panic!("cannot represent {value} as i64 for {key}"));
+                line.set_timestamp(chrono::Utc.timestamp_opt(value, 0).unwrap())
+            } else if tags.contains(&key.as_str()) {
+                let value = value
+                    .as_str()
+                    .unwrap_or_else(|| panic!("cannot represent {value} as str for {key}"));
+                line.insert_tag(key, value)
+            } else if value.is_string() {
+                line.insert_field(key, value.as_str().unwrap().to_string())
+            } else if value.is_f64() {
+                let value = value
+                    .as_f64()
+                    .unwrap_or_else(|| panic!("cannot represent {value} as f64 for {key}"));
+                line.insert_field(key, value)
+            } else {
+                // can't be anything other than int
+                let value = value
+                    .as_i64()
+                    .unwrap_or_else(|| panic!("cannot represent {value} as i64 for {key}"));
+                line.insert_field(key, value)
+            }
+        }
+
+        line.build()
+    }
+
     fn database(&self) -> String {
         self.config.influx().database().to_string()
     }
@@ -1,8 +1,12 @@
+pub mod automation;
+pub mod backup;
 pub mod channels;
 pub mod command;
 pub mod config;
 pub mod coordinator;
 pub mod database;
+pub mod export_limit;
+pub mod forecast;
 pub mod home_assistant;
 pub mod influx;
 pub mod lxp;
@@ -10,12 +14,14 @@ pub mod mqtt;
 pub mod options;
 pub mod prelude;
 pub mod scheduler;
+pub mod tariff;
 pub mod unixtime;
 pub mod utils;
 
 const CARGO_PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 use crate::prelude::*;
+use options::Subcommand;
 
 pub async fn app() -> Result<()> {
     let options = Options::new();
@@ -40,11 +46,27 @@ pub async fn app() -> Result<()> {
         .write_style(env_logger::WriteStyle::Never)
         .init();
 
+    match options.subcommand {
+        Some(Subcommand::Backup { datalog, output }) => {
+            return backup::backup(config, datalog, &output).await
+        }
+        Some(Subcommand::Restore {
+            file,
+            datalog,
+            dry_run,
+        }) => return backup::restore(config, &file, datalog, dry_run).await,
+        None => {}
+    }
+
     info!("lxp-bridge {} starting", CARGO_PKG_VERSION);
 
     let channels = Channels::new();
 
     let scheduler = Scheduler::new(config.clone(), channels.clone());
+    let tariff = Tariff::new(config.clone(), channels.clone());
+    let forecast = Forecast::new(config.clone(), channels.clone(), tariff.target_soc());
+    let automation = Automation::new(config.clone(), channels.clone());
+    let export_limit = ExportLimit::new(config.clone(), channels.clone());
     let mqtt = Mqtt::new(config.clone(), channels.clone());
     let influx = Influx::new(config.clone(), channels.clone());
     let coordinator = Coordinator::new(config.clone(), channels.clone());
@@ -65,6 +87,10 @@ pub async fn app() -> Result<()> {
         start_databases(databases),
         start_inverters(inverters),
         scheduler.start(),
+        tariff.start(),
+        forecast.start(),
+        automation.start(),
+        export_limit.start(),
         mqtt.start(),
         influx.start(),
         coordinator.start()
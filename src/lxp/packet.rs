@@ -530,14 +530,32 @@ pub struct ReadInput3 {
 } // }}}
 
 // {{{ ReadInputs
+const READ_CYCLE_SECS: i64 = 30;
+
 #[derive(Default, Clone, Debug)]
 pub struct ReadInputs {
     read_input_1: Option<ReadInput1>,
     read_input_2: Option<ReadInput2>,
     read_input_3: Option<ReadInput3>,
+
+    read_input_all: Option<Box<ReadInputAll>>,
+    read_input_all2: Option<Box<ReadInputAll2>>,
+
+    // values we work out from the inputs ourselves, such as energy totals
+    derived: serde_json::Map<String, serde_json::Value>,
 }
 
 impl ReadInputs {
+    pub fn set_read_input_all(&mut self, i: Box<ReadInputAll>) {
+        self.read_input_all = Some(i);
+    }
+    pub fn set_read_input_all2(&mut self, i: Box<ReadInputAll2>) {
+        self.read_input_all2 = Some(i);
+    }
+    pub fn set_derived(&mut self, derived: serde_json::Map<String, serde_json::Value>) {
+        self.derived = derived;
+    }
+
     pub fn set_read_input_1(&mut self, i: ReadInput1) {
         self.read_input_1 = Some(i);
     }
@@ -661,6 +679,57 @@ impl ReadInputs {
             _ => None,
         }
     }
+
+    pub fn read_input_all(&self) -> Option<&ReadInputAll> {
+        self.read_input_all.as_deref()
+    }
+
+    // The ReadInputAll we have, if it was read in the same cycle as `time`.
+    pub fn read_input_all_near(&self, time: &UnixTime) -> Option<&ReadInputAll> {
+        self.read_input_all
+            .as_deref()
+            .filter(|input| Self::same_cycle(&input.time, time))
+    }
+
+    // Likewise for ReadInputAll2.
+    pub fn read_input_all2_near(&self, time: &UnixTime) -> Option<&ReadInputAll2> {
+        self.read_input_all2
+            .as_deref()
+            .filter(|input2| Self::same_cycle(&input2.time, time))
+    }
+
+    // Merges the ReadInputAll2 from the same read cycle (if we have one) and
+    // any derived values into the given inputs, giving the combined record
+    // published as inputs/all. time and datalog are taken from `input`.
+    pub fn combined(&self, input: &ReadInputAll) -> Result<serde_json::Value> {
+        let mut data = serde_json::to_value(input)?;
+
+        if let Some(data) = data.as_object_mut() {
+            if let Some(input2) = self
+                .read_input_all2
+                .as_ref()
+                .filter(|input2| Self::same_cycle(&input2.time, &input.time))
+            {
+                if let serde_json::Value::Object(extra) = serde_json::to_value(input2)? {
+                    for (key, value) in extra {
+                        data.entry(key).or_insert(value);
+                    }
+                }
+            }
+
+            for (key, value) in &self.derived {
+                data.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+
+        Ok(data)
+    }
+
+    // the packets of one read arrive within a few seconds of each other;
+    // anything further apart is left over from an earlier read
+    fn same_cycle(a: &UnixTime, b: &UnixTime) -> bool {
+        (a.0 - b.0).num_seconds().abs() <= READ_CYCLE_SECS
+    }
 } // }}}
 
 // {{{ TcpFunction
@@ -921,6 +990,117 @@ pub fn find_register_config(register: u16) -> Option<RegisterConfig<'static>> {
     None
 }
 
+// Acceptable raw values for a holding register, used to validate writes.
+// Registers without limits accept any u16.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RegisterLimits {
+    pub min: u16,
+    pub max: u16,
+    pub step: u16,
+}
+
+impl RegisterLimits {
+    pub fn check(&self, register: u16, value: u16) -> Result<()> {
+        if value < self.min || value > self.max {
+            bail!(
+                "value {} out of range for register {} ({}-{})",
+                value,
+                register,
+                self.min,
+                self.max
+            );
+        }
+        // a step of 0 (or 1) allows any value in range
+        if self.step > 1 && (value - self.min) % self.step != 0 {
+            bail!(
+                "value {} for register {} must be a multiple of {}",
+                value,
+                register,
+                self.step
+            );
+        }
+
+        Ok(())
+    }
+}
+
+pub fn find_register_limits(register: u16) -> Option<RegisterLimits> {
+    use Register::*;
+
+    let percent = RegisterLimits {
+        min: 0,
+        max: 100,
+        step: 1,
+    };
+    let tenth_percent = RegisterLimits {
+        min: 0,
+        max: 1000,
+        step: 1,
+    };
+    // grid protection points; wide enough for 120V and 230V grids, but
+    // nowhere near enough to switch protection off
+    let grid_volts = RegisterLimits {
+        min: 800,
+        max: 3000,
+        step: 1,
+    };
+    let grid_freq = RegisterLimits {
+        min: 4500,
+        max: 6500,
+        step: 1,
+    };
+
+    match Register::try_from(register).ok()? {
+        Language => Some(RegisterLimits {
+            min: 0,
+            max: 1,
+            step: 1,
+        }),
+        ActivePowerPercentCmd
+        | ChargePowerPercentCmd
+        | DischgPowerPercentCmd
+        | AcChargePowerCmd
+        | AcChargeSocLimit
+        | ChargePriorityPowerCmd
+        | ChargePrioritySocLimit
+        | ForcedDischgPowerCmd
+        | ForcedDischgSocLimit
+        | MaxBackFlow
+        | DischgCutOffSocEod
+        | EpsDischgCutoffSocEod
+        | AcChargeStartSocLimit
+        | AcChargeEndSocLimit
+        | BatLowSoc
+        | BatLowBackSoc
+        | BatLowtoUtilitySoc
+        | SocCurveSoc1
+        | SocCurveSoc2
+        | GenChargeStartSoc
+        | GenChargeEndSoc
+        | GridPeakShavingSoc
+        | SmartLoadOnSoc
+        | SmartLoadOffSoc
+        | GridPeakShavingSoc1
+        | ACCoupleStartSoc
+        | ACCoupleEndSoc
+        | BatStopChargeSoc => Some(percent),
+        ChargePowerPercentCmd2
+        | DischgPowerPercentCmd2
+        | AcChargePowerCmd2
+        | ChargePriorityPowerCmd2
+        | ForcedDischgPowerCmd2
+        | ActivePowerPercentCmd2 => Some(tenth_percent),
+        GridVoltConnLow | GridVoltConnHigh | GridVoltLimit1Low | GridVoltLimit1High
+        | GridVoltLimit2Low | GridVoltLimit2High | GridVoltLimit3Low | GridVoltLimit3High
+        | GridVoltMovAvgHigh => Some(grid_volts),
+        GridFreqConnLow | GridFreqConnHigh | GridFreqLimit1Low | GridFreqLimit1High
+        | GridFreqLimit2Low | GridFreqLimit2High | GridFreqLimit3Low | GridFreqLimit3High => {
+            Some(grid_freq)
+        }
+        _ => None,
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
 #[repr(u16)]
 pub enum Register21Bit {
@@ -1001,6 +1181,57 @@ pub enum Register179Bit {
     OnGridAlwaysOn = 1 << 15,
 }
 
+// Looks up a holding register by its name in Register, ignoring case and
+// underscores as find_register_bit does, so "AcChargeSocLimit" and
+// "ac_charge_soc_limit" both find 67.
+pub fn find_register(name: &str) -> Option<u16> {
+    use std::collections::HashMap;
+    use std::sync::OnceLock;
+
+    static NAMES: OnceLock<HashMap<String, u16>> = OnceLock::new();
+
+    let normalise = |s: &str| s.replace('_', "").to_ascii_lowercase();
+
+    let names = NAMES.get_or_init(|| {
+        (0..=u16::MAX)
+            .filter_map(|register| Register::try_from(register).ok())
+            .map(|register| (normalise(&format!("{:?}", register)), register.into()))
+            .collect()
+    });
+
+    names.get(&normalise(name)).copied()
+}
+
+// Looks up a named bit of one of the bitfield holding registers, for use with
+// set/bit/{register}/{name}. Names are the enum variants above; case and
+// underscores are ignored, so eco_mode_enable and EcoModeEnable both work.
+pub fn find_register_bit(register: u16, name: &str) -> Result<u16> {
+    // each variant of these enums is a single bit, so trying all sixteen
+    // finds every one of them, including any added later
+    fn find<T>(name: &str) -> Option<u16>
+    where
+        T: TryFrom<u16> + std::fmt::Debug + Into<u16>,
+    {
+        let normalise = |s: &str| s.replace('_', "").to_ascii_lowercase();
+        let name = normalise(name);
+
+        (0..16)
+            .filter_map(|n| T::try_from(1 << n).ok())
+            .find(|bit| normalise(&format!("{:?}", bit)) == name)
+            .map(Into::into)
+    }
+
+    let bit = match register {
+        21 => find::<Register21Bit>(name),
+        110 => find::<Register110Bit>(name),
+        120 => find::<Register120Bit>(name),
+        179 => find::<Register179Bit>(name),
+        _ => bail!("register {} has no named bits", register),
+    };
+
+    bit.ok_or_else(|| anyhow!("unknown bit {} for register {}", name, register))
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
 #[repr(u16)]
 pub enum Register233Bit {
@@ -1546,7 +1777,7 @@ impl Register235Bits {
     pub fn new(data: u16) -> Self {
         Self {
             no_full_charge_days: (data & 0b11111111) as u8,
-            no_full_charge_days_num_set: ((data & 0b11111111) >> 8) as u8,
+            no_full_charge_days_num_set: ((data >> 8) & 0b11111111) as u8,
         }
     }
 } // }}}
@@ -2330,6 +2561,28 @@ impl StatusString {
     }
 }
 
+// ActiveCode {{{
+// One set bit of a fault_code or warning_code register, split into the short
+// code (eg "E003") and its description.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ActiveCode {
+    pub bit: u8,
+    pub code: &'static str,
+    pub description: &'static str,
+}
+
+impl ActiveCode {
+    fn new(bit: usize, text: &'static str) -> Self {
+        let (code, description) = text.split_once(": ").unwrap_or(("", text));
+
+        Self {
+            bit: bit as u8,
+            code,
+            description,
+        }
+    }
+} // }}}
+
 pub struct WarningCodeString;
 impl WarningCodeString {
     pub fn from_value(value: u32) -> &'static str {
@@ -2343,6 +2596,14 @@ impl WarningCodeString {
             .unwrap()
     }
 
+    // from_value only reports the lowest set bit; this returns all of them.
+    pub fn active(value: u32) -> Vec<ActiveCode> {
+        (0..=31)
+            .filter(|i| value & (1 << i) > 0)
+            .map(|i| ActiveCode::new(i, Self::from_bit(i)))
+            .collect()
+    }
+
     fn from_bit(bit: usize) -> &'static str {
         match bit {
             0 => "W000: Battery communication failure",
@@ -2396,6 +2657,14 @@ impl FaultCodeString {
             .unwrap()
     }
 
+    // from_value only reports the lowest set bit; this returns all of them.
+    pub fn active(value: u32) -> Vec<ActiveCode> {
+        (0..=31)
+            .filter(|i| value & (1 << i) > 0)
+            .map(|i| ActiveCode::new(i, Self::from_bit(i)))
+            .collect()
+    }
+
     fn from_bit(bit: usize) -> &'static str {
         match bit {
             0 => "E000: Internal communication fault 1",
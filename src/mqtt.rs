@@ -1,6 +1,11 @@
 use crate::prelude::*;
 
-use rumqttc::{AsyncClient, Event, EventLoop, Incoming, LastWill, MqttOptions, Publish, QoS};
+use rumqttc::v5::{self, mqttbytes::v5::PublishProperties};
+use rumqttc::{AsyncClient, Event, Incoming, LastWill, MqttOptions, Publish, QoS, Transport};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use std::sync::Arc;
 
 // Message {{{
 #[derive(Eq, PartialEq, Debug, Clone)]
@@ -47,21 +52,19 @@ impl Message {
                 payload: serde_json::to_string(&scaled_value)?,
             });
 
-            if register == 21 {
-                let bits = lxp::packet::Register21Bits::new(value);
+            if let Some(parsed) = Self::hold_parsed(register, value) {
                 r.push(mqtt::Message {
-                    topic: format!("{}/hold/{}/bits", td.datalog, register),
+                    topic: format!("{}/hold/{}/parsed", td.datalog, register),
                     retain: true,
-                    payload: serde_json::to_string(&bits)?,
+                    payload: serde_json::to_string(parsed)?,
                 });
             }
 
-            if register == 110 {
-                let bits = lxp::packet::Register110Bits::new(value);
+            if let Some(bits) = Self::hold_bits(register, value)? {
                 r.push(mqtt::Message {
                     topic: format!("{}/hold/{}/bits", td.datalog, register),
                     retain: true,
-                    payload: serde_json::to_string(&bits)?,
+                    payload: bits,
                 });
             }
         }
@@ -69,8 +72,54 @@ impl Message {
         Ok(r)
     }
 
-    pub fn for_input_all(
-        inputs: &lxp::packet::ReadInputAll,
+    // holding registers which hold a single enumerated value
+    fn hold_parsed(register: u16, value: u16) -> Option<&'static str> {
+        use lxp::packet::{
+            DtcDeviceTypeString, GridTypeString, LanguageString, LineModeString,
+            OutputPrioConfigString, ReactivePowerCmdTypeString, SetSystemTypeString,
+        };
+
+        match register {
+            16 => Some(LanguageString::from_value(value)),
+            19 => Some(DtcDeviceTypeString::from_value(value)),
+            // 20 is not here; what its values mean depends on the inverter model,
+            // and we can't tell which one we're talking to.
+            59 => Some(ReactivePowerCmdTypeString::from_value(value)),
+            112 => Some(SetSystemTypeString::from_value(value)),
+            145 => Some(OutputPrioConfigString::from_value(value)),
+            146 => Some(LineModeString::from_value(value)),
+            205 => Some(GridTypeString::from_value(value)),
+            _ => None,
+        }
+    }
+
+    // holding registers which pack several settings into one value.
+    // returns the JSON payload for the /bits topic.
+    fn hold_bits(register: u16, value: u16) -> Result<Option<String>> {
+        use lxp::packet::{
+            Register110Bits, Register120Bits, Register179Bits, Register21Bits, Register224Bits,
+            Register230Bits, Register233Bits, Register235Bits,
+        };
+
+        let bits = match register {
+            21 => serde_json::to_string(&Register21Bits::new(value))?,
+            110 => serde_json::to_string(&Register110Bits::new(value))?,
+            120 => serde_json::to_string(&Register120Bits::new(value))?,
+            179 => serde_json::to_string(&Register179Bits::new(value))?,
+            224 => serde_json::to_string(&Register224Bits::new(value))?,
+            230 => serde_json::to_string(&Register230Bits::new(value))?,
+            233 => serde_json::to_string(&Register233Bits::new(value))?,
+            235 => serde_json::to_string(&Register235Bits::new(value))?,
+            _ => return Ok(None),
+        };
+
+        Ok(Some(bits))
+    }
+
+    // inputs is usually the combined ReadInputAll/ReadInputAll2 record from
+    // ReadInputs::combined, but anything that serializes to an object will do.
+    pub fn for_input_all<T: serde::Serialize>(
+        inputs: &T,
         datalog: lxp::inverter::Serial,
     ) -> Result<Message> {
         Ok(mqtt::Message {
@@ -234,6 +283,52 @@ impl Message {
         Ok(r)
     }
 
+    // Values we work out from the inputs (eg energy totals) are published
+    // alongside the individual inputs so they can be used the same way, when
+    // mqtt.publish_individual_input is on.
+    pub fn for_derived(
+        datalog: Serial,
+        data: &serde_json::Map<String, serde_json::Value>,
+    ) -> Vec<Message> {
+        data.iter()
+            .map(|(key, value)| mqtt::Message {
+                topic: format!("{}/input/{}/parsed", datalog, key),
+                retain: false,
+                payload: value.to_string(),
+            })
+            .collect()
+    }
+
+    // When holding registers are refreshed, only the registers which changed
+    // are published, followed by a setting_changed event for each one we knew
+    // the previous value of.
+    pub fn for_setting_changes(
+        td: lxp::packet::TranslatedData,
+        changes: &[coordinator::hold_cache::SettingChange],
+    ) -> Result<Vec<Message>> {
+        let mut r = Vec::new();
+
+        for (register, value) in td.pairs() {
+            if changes.iter().any(|change| change.register == register) {
+                r.append(&mut Self::for_hold(lxp::packet::TranslatedData {
+                    register,
+                    values: value.to_le_bytes().to_vec(),
+                    ..td.clone()
+                })?);
+            }
+        }
+
+        for change in changes.iter().filter(|change| change.old.is_some()) {
+            r.push(mqtt::Message {
+                topic: format!("{}/events/setting_changed", change.datalog),
+                retain: false,
+                payload: serde_json::to_string(change)?,
+            });
+        }
+
+        Ok(r)
+    }
+
     pub fn to_command(&self, inverter: config::Inverter) -> Result<Command> {
         use Command::*;
 
@@ -254,6 +349,8 @@ impl Message {
             ["read", "ac_first", num] => ReadAcFirstTime(inverter, num.parse()?),
             ["read", "charge_priority", num] => ReadChargePriorityTime(inverter, num.parse()?),
             ["read", "forced_discharge", num] => ReadForcedDischargeTime(inverter, num.parse()?),
+            ["read", "time"] => ReadTime(inverter),
+            ["read", "batch"] => ReadBatch(inverter, coordinator::batch::parse(&self.payload)?),
             ["set", "hold", register] => SetHold(inverter, register.parse()?, self.payload_float()?),
             ["set", "param", register] => {
                 WriteParam(inverter, register.parse()?, self.payload_int()?)
@@ -273,6 +370,13 @@ impl Message {
             ["set", "forced_discharge", num] => {
                 SetForcedDischargeTime(inverter, num.parse()?, self.payload_start_end_time()?)
             }
+            ["set", "bit", register, name] => SetBit(
+                inverter,
+                register.parse()?,
+                name.to_string(),
+                self.payload_bool(),
+            ),
+            ["set", "bits", register] => SetBits(inverter, register.parse()?, self.payload_bits()?),
             ["set", "charge_rate_pct"] => ChargeRate(inverter, self.payload_int()?),
             ["set", "discharge_rate_pct"] => DischargeRate(inverter, self.payload_int()?),
             ["set", "ac_charge_rate_pct"] => AcChargeRate(inverter, self.payload_int()?),
@@ -283,6 +387,13 @@ impl Message {
                 DischargeCutoffSocLimit(inverter, self.payload_int()?)
             }
 
+            ["set", "preset", name] => SetPreset(inverter, name.to_string()),
+            // the name can also be the payload, which is what a HA select sends
+            ["set", "preset"] => SetPreset(inverter, self.payload.clone()),
+
+            ["set", "schedule"] => SetSchedule(inverter, self.payload_schedule()?),
+            ["set", "time"] => SetTime(inverter, self.payload_datetime()?),
+
             [..] => bail!("unhandled: {:?}", self),
         };
 
@@ -313,6 +424,14 @@ impl Message {
         }
     }
 
+    // result/{datalog}/..., mirroring the cmd/{datalog or all}/... topic the
+    // message came in on
+    pub fn to_result_topic(&self, inverter: &config::Inverter) -> Result<String> {
+        let (_datalog, parts) = self.split_cmd_topic()?;
+
+        Ok(format!("result/{}/{}", inverter.datalog(), parts.join("/")))
+    }
+
     // not entirely happy with this return type but it avoids needing to expose a struct for now
     fn payload_start_end_time(&self) -> Result<[u8; 4]> {
         use serde::Deserialize;
@@ -338,6 +457,23 @@ impl Message {
         ])
     }
 
+    fn payload_schedule(&self) -> Result<coordinator::schedule::Schedule> {
+        serde_json::from_str(&self.payload).map_err(|err| anyhow!("payload_schedule: {}", err))
+    }
+
+    // "2024-03-31T01:30:00" (or with a space) -> Some, empty -> None
+    fn payload_datetime(&self) -> Result<Option<chrono::NaiveDateTime>> {
+        let payload = self.payload.trim();
+        if payload.is_empty() {
+            return Ok(None);
+        }
+
+        chrono::NaiveDateTime::parse_from_str(payload, "%Y-%m-%dT%H:%M:%S")
+            .or_else(|_| chrono::NaiveDateTime::parse_from_str(payload, "%Y-%m-%d %H:%M:%S"))
+            .map(Some)
+            .map_err(|err| anyhow!("payload_datetime: {}", err))
+    }
+
     fn payload_int_or_1(&self) -> Result<u16> {
         self.payload_int().or(Ok(1))
     }
@@ -355,21 +491,245 @@ impl Message {
     }
 
     fn payload_bool(&self) -> bool {
+        Self::is_truthy(&self.payload)
+    }
+
+    // {"eco_mode_enable": true, "green_mode_enable": "off"} -> [(name, bool), ..]
+    fn payload_bits(&self) -> Result<Vec<(String, bool)>> {
+        use serde_json::Value;
+
+        let bits = serde_json::from_str::<serde_json::Map<String, Value>>(&self.payload)
+            .map_err(|err| anyhow!("payload_bits: {}", err))?;
+
+        bits.into_iter()
+            .map(|(name, value)| match value {
+                Value::Bool(enable) => Ok((name, enable)),
+                Value::String(enable) => Ok((name, Self::is_truthy(&enable))),
+                _ => bail!("payload_bits: {} should be true or false", name),
+            })
+            .collect()
+    }
+
+    pub fn is_truthy(value: &str) -> bool {
         matches!(
-            self.payload.to_ascii_lowercase().as_str(),
+            value.to_ascii_lowercase().as_str(),
             "1" | "t" | "true" | "on" | "y" | "yes"
         )
     }
+
+    // If the payload is a command envelope, returns the envelope and a copy of
+    // the message with the payload replaced by the envelope's value, so
+    // to_command can treat it like any other.
+    //
+    // {"id":"abc","value":"1"} -> ({id: "abc"}, "1")
+    // {"id":"abc"} -> ({id: "abc"}, "") for commands which take no value,
+    // otherwise the envelope is marked missing_value so it can be refused
+    // Whether the command needs a payload. Reads (which default to one
+    // register) and set/preset/{name} don't, apart from read/batch.
+    fn takes_value(&self) -> bool {
+        match self.split_cmd_topic() {
+            Ok((_, parts)) => match parts[..] {
+                ["read", "batch"] => true,
+                ["read", ..] | ["set", "preset", _] => false,
+                _ => true,
+            },
+            // to_command will complain about the topic
+            Err(_) => false,
+        }
+    }
+
+    pub fn split_envelope(self) -> (Message, Option<CommandEnvelope>) {
+        use serde_json::Value;
+
+        // nothing but id and value, so payloads which are objects of their
+        // own (set/bits, set/schedule..) aren't mistaken for one
+        let mut envelope = match serde_json::from_str::<Value>(&self.payload) {
+            Ok(Value::Object(envelope))
+                if !envelope.is_empty()
+                    && envelope.keys().all(|key| key == "id" || key == "value") =>
+            {
+                envelope
+            }
+            _ => return (self, None),
+        };
+
+        // read commands and the like take no value, but anything else run
+        // with an empty one could do something the caller didn't ask for
+        let (payload, missing_value) = match envelope.remove("value") {
+            Some(Value::String(value)) => (value, false),
+            Some(value) => (value.to_string(), false),
+            None => (String::new(), self.takes_value()),
+        };
+
+        let envelope = CommandEnvelope {
+            id: envelope.remove("id"),
+            missing_value,
+        };
+
+        (Message { payload, ..self }, Some(envelope))
+    }
+} // }}}
+
+// CommandEnvelope {{{
+// Commands may wrap their payload in a JSON envelope, eg
+// {"id": "abc123", "value": "1"}, or just {"id": "abc123"} for commands which
+// take no value. Results for those are sent back as JSON which echoes the id,
+// so concurrent callers can tell which result is theirs.
+// Commands without an envelope still get a plain OK or FAIL.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct CommandEnvelope {
+    pub id: Option<serde_json::Value>,
+    // only an id was given, for a command which needs a value
+    pub missing_value: bool,
+}
+
+#[derive(PartialEq, Debug, Clone, serde::Serialize)]
+pub struct CommandResult {
+    pub id: Option<serde_json::Value>,
+    pub status: &'static str,
+    pub error: Option<String>,
+    pub value: Option<serde_json::Value>,
+    pub duration_ms: u64,
+    // what became of a write which was skipped or held back
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl CommandEnvelope {
+    pub fn result(
+        &self,
+        result: &Result<Option<serde_json::Value>>,
+        duration: std::time::Duration,
+    ) -> CommandResult {
+        CommandResult {
+            id: self.id.clone(),
+            status: if result.is_ok() { "OK" } else { "FAIL" },
+            error: result.as_ref().err().map(|err| err.to_string()),
+            value: result.as_ref().ok().cloned().flatten(),
+            duration_ms: duration.as_millis() as u64,
+            note: None,
+        }
+    }
 } // }}}
 
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub enum ChannelData {
     Message(Message),
+    // a message which is also answered directly: in from_mqtt, a v5 command
+    // with a response topic; in to_mqtt, the result of running it
+    WithReply(Message, Reply),
     Shutdown,
 }
 
 pub type Sender = broadcast::Sender<ChannelData>;
 
+// Reply {{{
+// where a v5 command asked for its result to be sent, besides the usual
+// result topic
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reply {
+    pub response_topic: String,
+    pub correlation_data: Option<bytes::Bytes>,
+} // }}}
+
+// Client {{{
+// the client for whichever version of the protocol mqtt.protocol asks for.
+// v5 properties are dropped when talking v4
+#[derive(Clone)]
+pub enum Client {
+    V4(AsyncClient),
+    V5(v5::AsyncClient),
+}
+
+impl Client {
+    async fn publish(
+        &self,
+        topic: &str,
+        retain: bool,
+        payload: String,
+        properties: Option<PublishProperties>,
+    ) -> Result<()> {
+        match (self, properties) {
+            (Self::V4(client), _) => {
+                client
+                    .publish(topic, QoS::AtLeastOnce, retain, payload)
+                    .await?
+            }
+            (Self::V5(client), Some(properties)) => {
+                client
+                    .publish_with_properties(
+                        topic,
+                        v5::mqttbytes::QoS::AtLeastOnce,
+                        retain,
+                        payload,
+                        properties,
+                    )
+                    .await?
+            }
+            (Self::V5(client), None) => {
+                client
+                    .publish(topic, v5::mqttbytes::QoS::AtLeastOnce, retain, payload)
+                    .await?
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn subscribe(&self, topic: String, qos: QoS) -> Result<()> {
+        match self {
+            Self::V4(client) => client.subscribe(topic, qos).await?,
+            Self::V5(client) => {
+                let qos = match qos {
+                    QoS::AtMostOnce => v5::mqttbytes::QoS::AtMostOnce,
+                    QoS::AtLeastOnce => v5::mqttbytes::QoS::AtLeastOnce,
+                    QoS::ExactlyOnce => v5::mqttbytes::QoS::ExactlyOnce,
+                };
+                client.subscribe(topic, qos).await?
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub enum EventLoop {
+    V4(Box<rumqttc::EventLoop>),
+    V5(Box<v5::EventLoop>),
+}
+
+impl EventLoop {
+    // the next message from the broker, or None for anything else (keepalives
+    // etc). a v5 message can ask for a reply
+    async fn poll(&mut self) -> Result<Option<(Publish, Option<Reply>)>> {
+        match self {
+            Self::V4(eventloop) => match eventloop.poll().await? {
+                Event::Incoming(Incoming::Publish(publish)) => Ok(Some((publish, None))),
+                _ => Ok(None),
+            },
+            Self::V5(eventloop) => match eventloop.poll().await? {
+                v5::Event::Incoming(v5::Incoming::Publish(publish)) => {
+                    let reply = publish.properties.and_then(|properties| {
+                        Some(Reply {
+                            response_topic: properties.response_topic?,
+                            correlation_data: properties.correlation_data,
+                        })
+                    });
+                    let mut v4 = Publish::new(
+                        String::from_utf8(publish.topic.to_vec())?,
+                        QoS::AtMostOnce,
+                        publish.payload,
+                    );
+                    v4.retain = publish.retain;
+
+                    Ok(Some((v4, reply)))
+                }
+                _ => Ok(None),
+            },
+        }
+    }
+} // }}}
+
 pub struct Mqtt {
     config: ConfigWrapper,
     shutdown: bool,
@@ -393,10 +753,45 @@ impl Mqtt {
             return Ok(());
         }
 
-        let mut options = MqttOptions::new("lxp-bridge", c.mqtt().host(), c.mqtt().port());
+        let v5 = c.mqtt().protocol() == config::MqttProtocol::V5;
+
+        info!(
+            "initializing mqtt{} at {}:{}{}",
+            if v5 { " v5" } else { "" },
+            c.mqtt().host(),
+            c.mqtt().port(),
+            if c.mqtt().tls().is_some() {
+                " (tls)"
+            } else {
+                ""
+            }
+        );
+
+        let (client, eventloop) = if v5 {
+            let (client, eventloop) = v5::AsyncClient::new(self.options_v5()?, 10);
+            (Client::V5(client), EventLoop::V5(Box::new(eventloop)))
+        } else {
+            let (client, eventloop) = AsyncClient::new(self.options()?, 10);
+            (Client::V4(client), EventLoop::V4(Box::new(eventloop)))
+        };
+
+        futures::try_join!(
+            self.setup(client.clone()),
+            self.receiver(eventloop),
+            self.sender(client)
+        )?;
+
+        Ok(())
+    }
+
+    pub fn options(&self) -> Result<MqttOptions> {
+        let c = self.config.mqtt();
+
+        let mut options = MqttOptions::new(c.session_client_id()?, c.host(), c.port());
+        options.set_clean_session(c.clean_session());
 
         let will = LastWill {
-            topic: self.lwt_topic(),
+            topic: c.lwt_topic(),
             message: bytes::Bytes::from("offline"),
             qos: QoS::AtLeastOnce,
             retain: true,
@@ -404,25 +799,95 @@ impl Mqtt {
         options.set_last_will(will);
 
         options.set_keep_alive(std::time::Duration::from_secs(60));
-        if let (Some(u), Some(p)) = (c.mqtt().username(), c.mqtt().password()) {
+        if let (Some(u), Some(p)) = (c.username(), c.password()) {
             options.set_credentials(u, p);
         }
 
-        info!(
-            "initializing mqtt at {}:{}",
-            c.mqtt().host(),
-            c.mqtt().port()
+        if let Some(transport) = self.transport()? {
+            options.set_transport(transport);
+        }
+
+        Ok(options)
+    }
+
+    pub fn options_v5(&self) -> Result<v5::MqttOptions> {
+        let c = self.config.mqtt();
+
+        let mut options = v5::MqttOptions::new(c.session_client_id()?, c.host(), c.port());
+        options.set_clean_start(c.clean_session());
+        if !c.clean_session() {
+            let mut properties = v5::mqttbytes::v5::ConnectProperties::new();
+            properties.session_expiry_interval = Some(c.session_expiry());
+            options.set_connect_properties(properties);
+        }
+
+        let will = v5::mqttbytes::v5::LastWill::new(
+            c.lwt_topic(),
+            "offline",
+            v5::mqttbytes::QoS::AtLeastOnce,
+            true,
+            None,
         );
+        options.set_last_will(will);
 
-        let (client, eventloop) = AsyncClient::new(options, 10);
+        options.set_keep_alive(std::time::Duration::from_secs(60));
+        if let (Some(u), Some(p)) = (c.username(), c.password()) {
+            options.set_credentials(u, p);
+        }
 
-        futures::try_join!(
-            self.setup(client.clone()),
-            self.receiver(eventloop),
-            self.sender(client)
-        )?;
+        if let Some(transport) = self.transport()? {
+            options.set_transport(transport);
+        }
 
-        Ok(())
+        Ok(options)
+    }
+
+    fn transport(&self) -> Result<Option<Transport>> {
+        match self.config.mqtt().tls() {
+            Some(tls) => {
+                let tls_config = tls_client_config(tls)?;
+                Ok(Some(Transport::tls_with_config(tls_config.into())))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // The v5 properties to publish message with: which datalog and inverter
+    // it's about, and an expiry on readings so offline subscribers don't get a
+    // backlog of stale ones. None for v4.
+    pub fn publish_properties(&self, message: &Message) -> Option<PublishProperties> {
+        let c = self.config.mqtt();
+
+        if c.protocol() != config::MqttProtocol::V5 {
+            return None;
+        }
+
+        let mut properties = PublishProperties::default();
+
+        // {datalog}/..., or result/{datalog}/... for command results
+        let parts: Vec<&str> = message.topic.split('/').collect();
+        let datalog = match parts.as_slice() {
+            ["result", datalog, ..] => *datalog,
+            [datalog, ..] => *datalog,
+            [] => "",
+        };
+        if let Some(inverter) = self
+            .config
+            .inverters()
+            .iter()
+            .find(|inverter| inverter.datalog().to_string() == datalog)
+        {
+            properties.user_properties = vec![
+                ("datalog".to_owned(), inverter.datalog().to_string()),
+                ("serial".to_owned(), inverter.serial().to_string()),
+            ];
+        }
+
+        if !message.retain && matches!(parts.as_slice(), [_, "inputs" | "input", ..]) {
+            properties.message_expiry_interval = c.message_expiry();
+        }
+
+        Some(properties)
     }
 
     pub fn stop(&mut self) {
@@ -431,16 +896,22 @@ impl Mqtt {
         let _ = self.channels.from_mqtt.send(ChannelData::Shutdown);
     }
 
-    async fn setup(&self, client: AsyncClient) -> Result<()> {
+    async fn setup(&self, client: Client) -> Result<()> {
+        let lwt_topic = self.config.mqtt().lwt_topic();
         client
-            .publish(self.lwt_topic(), QoS::AtLeastOnce, true, "online")
+            .publish(&lwt_topic, true, "online".to_owned(), None)
             .await?;
 
+        // with a persistent session the broker only queues commands for us
+        // while we're away if we subscribed at QoS 1
+        let qos = if self.config.mqtt().clean_session() {
+            QoS::AtMostOnce
+        } else {
+            QoS::AtLeastOnce
+        };
+
         client
-            .subscribe(
-                format!("{}/cmd/all/#", self.config.mqtt().namespace()),
-                QoS::AtMostOnce,
-            )
+            .subscribe(format!("{}/cmd/all/#", self.config.mqtt().namespace()), qos)
             .await?;
 
         for inverter in self.config.enabled_inverters() {
@@ -451,15 +922,16 @@ impl Mqtt {
                         self.config.mqtt().namespace(),
                         inverter.datalog()
                     ),
-                    QoS::AtMostOnce,
+                    qos,
                 )
                 .await?;
 
             if self.config.mqtt().homeassistant().enabled() {
-                let ha = home_assistant::Config::new(&inverter, &self.config.mqtt());
+                let ha = home_assistant::Config::new(&inverter, &self.config.mqtt())
+                    .with_presets(self.config.preset_names());
                 for msg in ha.all()?.into_iter() {
                     let _ = client
-                        .publish(&msg.topic, QoS::AtLeastOnce, msg.retain, msg.payload)
+                        .publish(&msg.topic, msg.retain, msg.payload, None)
                         .await;
                 }
             }
@@ -479,16 +951,16 @@ impl Mqtt {
                 tokio::time::timeout(std::time::Duration::from_secs(1), eventloop.poll()).await
             {
                 match event {
-                    Ok(Event::Incoming(Incoming::Publish(publish))) => {
-                        self.handle_message(publish)?;
+                    Ok(Some((publish, reply))) => {
+                        self.handle_message(publish, reply)?;
                     }
+                    Ok(None) => {} // keepalives etc
                     Err(e) => {
                         // should automatically reconnect on next poll()..
                         error!("{}", e);
                         info!("reconnecting in 5s");
                         tokio::time::sleep(std::time::Duration::from_secs(5)).await;
                     }
-                    _ => {} // keepalives etc
                 }
             }
         }
@@ -498,7 +970,7 @@ impl Mqtt {
         Ok(())
     }
 
-    fn handle_message(&self, publish: Publish) -> Result<()> {
+    fn handle_message(&self, publish: Publish, reply: Option<Reply>) -> Result<()> {
         // remove the namespace, including the first /
         // doing it this way means we don't break if namespace happens to contain a /
         let topic = publish.topic[self.config.mqtt().namespace().len() + 1..].to_owned();
@@ -509,12 +981,14 @@ impl Mqtt {
             payload: String::from_utf8(publish.payload.to_vec())?,
         };
         debug!("RX: {:?}", message);
-        if self
-            .channels
-            .from_mqtt
-            .send(ChannelData::Message(message))
-            .is_err()
-        {
+
+        // the reply goes with the command, so its result can't be mixed up
+        // with anyone else's
+        let data = match reply {
+            Some(reply) => ChannelData::WithReply(message, reply),
+            None => ChannelData::Message(message),
+        };
+        if self.channels.from_mqtt.send(data).is_err() {
             bail!("send(from_mqtt) failed - channel closed?");
         }
 
@@ -522,7 +996,7 @@ impl Mqtt {
     }
 
     // coordinator -> mqtt
-    async fn sender(&self, client: AsyncClient) -> Result<()> {
+    async fn sender(&self, client: Client) -> Result<()> {
         use ChannelData::*;
 
         let mut receiver = self.channels.to_mqtt.subscribe();
@@ -530,14 +1004,8 @@ impl Mqtt {
         loop {
             match receiver.recv().await? {
                 Shutdown => break,
-                Message(message) => {
-                    let topic = format!("{}/{}", self.config.mqtt().namespace(), message.topic);
-                    info!("publishing: {} = {}", topic, message.payload);
-                    let _ = client
-                        .publish(&topic, QoS::AtLeastOnce, message.retain, message.payload)
-                        .await
-                        .map_err(|err| error!("publish {} failed: {:?} .. skipping", topic, err));
-                }
+                Message(message) => self.publish(&client, message, None).await,
+                WithReply(message, reply) => self.publish(&client, message, Some(reply)).await,
             }
         }
 
@@ -546,7 +1014,160 @@ impl Mqtt {
         Ok(())
     }
 
-    fn lwt_topic(&self) -> String {
-        format!("{}/LWT", self.config.mqtt().namespace())
+    // to the namespaced topic, and first to reply's response topic if there is
+    // one. failures are logged and otherwise ignored
+    async fn publish(&self, client: &Client, message: Message, reply: Option<Reply>) {
+        let properties = self.publish_properties(&message);
+
+        if let Some(reply) = reply {
+            let mut properties = properties.clone().unwrap_or_default();
+            properties.correlation_data = reply.correlation_data;
+            info!("replying: {} = {}", reply.response_topic, message.payload);
+            let _ = client
+                .publish(
+                    &reply.response_topic,
+                    false,
+                    message.payload.clone(),
+                    Some(properties),
+                )
+                .await
+                .map_err(|err| {
+                    error!(
+                        "reply to {} failed: {:?} .. skipping",
+                        reply.response_topic, err
+                    )
+                });
+        }
+
+        let topic = format!("{}/{}", self.config.mqtt().namespace(), message.topic);
+        info!("publishing: {} = {}", topic, message.payload);
+        let _ = client
+            .publish(&topic, message.retain, message.payload, properties)
+            .await
+            .map_err(|err| error!("publish {} failed: {:?} .. skipping", topic, err));
+    }
+}
+
+// Tls {{{
+// Builds the rustls config for mqtt.tls. Files are read now, so a missing or
+// bad one is reported at startup rather than on every reconnect.
+pub fn tls_client_config(tls: &config::MqttTls) -> Result<rustls::ClientConfig> {
+    let builder = rustls::ClientConfig::builder();
+
+    let builder = if tls.insecure_skip_verify() {
+        warn!("mqtt.tls.insecure_skip_verify is set, the broker's certificate won't be checked");
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoCertificateVerification))
+    } else {
+        builder.with_root_certificates(tls_root_store(tls.ca_file())?)
+    };
+
+    let mut config = match tls.client_auth()? {
+        Some((cert_file, key_file)) => builder
+            .with_client_auth_cert(tls_certs(cert_file)?, tls_key(key_file)?)
+            .map_err(|err| anyhow!("mqtt.tls: {}: {}", cert_file, err))?,
+        None => builder.with_no_client_auth(),
+    };
+
+    config.alpn_protocols = tls.alpn().iter().map(|p| p.as_bytes().to_vec()).collect();
+
+    Ok(config)
+}
+
+fn tls_root_store(ca_file: Option<&str>) -> Result<rustls::RootCertStore> {
+    let mut roots = rustls::RootCertStore::empty();
+
+    match ca_file {
+        Some(ca_file) => {
+            for cert in tls_certs(ca_file)? {
+                roots
+                    .add(cert)
+                    .map_err(|err| anyhow!("mqtt.tls: {}: bad CA certificate: {}", ca_file, err))?;
+            }
+        }
+        None => {
+            // system stores can hold certificates rustls can't use; skip those
+            let certs = rustls_native_certs::load_native_certs()
+                .map_err(|err| anyhow!("mqtt.tls: loading system certificates: {}", err))?;
+            roots.add_parsable_certificates(certs);
+        }
+    }
+
+    Ok(roots)
+}
+
+fn tls_reader(file: &str) -> Result<std::io::BufReader<std::fs::File>> {
+    let file = std::fs::File::open(file).map_err(|err| anyhow!("mqtt.tls: {}: {}", file, err))?;
+
+    Ok(std::io::BufReader::new(file))
+}
+
+fn tls_certs(file: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut tls_reader(file)?)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|err| anyhow!("mqtt.tls: {}: {}", file, err))?;
+    if certs.is_empty() {
+        bail!("mqtt.tls: {}: no certificates found", file);
     }
+
+    Ok(certs)
 }
+
+// the first private key in file, which may be PKCS#8, RSA or EC
+fn tls_key(file: &str) -> Result<PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut tls_reader(file)?)
+        .map_err(|err| anyhow!("mqtt.tls: {}: {}", file, err))?
+        .ok_or_else(|| anyhow!("mqtt.tls: {}: no private key found", file))
+}
+
+#[derive(Debug)]
+struct NoCertificateVerification;
+
+impl ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    // the handshake is still checked, only who the certificate belongs to isn't
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        rustls::crypto::ring::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+} // }}}
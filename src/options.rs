@@ -1,11 +1,44 @@
 use clap::Parser;
 
+use crate::lxp::inverter::Serial;
+
 #[derive(Debug, Parser)]
 #[clap(author, version)]
 pub struct Options {
     /// Config file to read
     #[clap(short = 'c', long = "config", default_value = "config.yaml")]
     pub config_file: String,
+
+    #[clap(subcommand)]
+    pub subcommand: Option<Subcommand>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum Subcommand {
+    /// Save an inverter's holding registers and dongle parameters to a file
+    Backup {
+        /// Datalog serial of the inverter to back up
+        #[clap(long = "inverter")]
+        datalog: Serial,
+
+        /// File to write
+        #[clap(short = 'o', long = "output", default_value = "settings.yaml")]
+        output: String,
+    },
+
+    /// Compare a backup with the live inverter and write back any differences
+    Restore {
+        /// File written by backup
+        file: String,
+
+        /// Restore to this inverter instead of the one the backup came from
+        #[clap(long = "inverter")]
+        datalog: Option<Serial>,
+
+        /// Show the differences but don't write anything
+        #[clap(long)]
+        dry_run: bool,
+    },
 }
 
 impl Options {
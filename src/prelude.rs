@@ -13,11 +13,14 @@ pub use {
 };
 
 pub use crate::{
+    automation::{self, Automation},
     channels::Channels,
     command::Command,
     config::{self, Config, ConfigWrapper},
     coordinator::{self, Coordinator},
     database::{self, Database},
+    export_limit::{self, ExportLimit},
+    forecast::{self, Forecast},
     home_assistant,
     influx::{self, Influx},
     lxp::{
@@ -28,6 +31,7 @@ pub use crate::{
     mqtt::{self, Mqtt},
     options::Options,
     scheduler::Scheduler,
+    tariff::{self, Tariff},
     unixtime::UnixTime,
     utils::Utils,
 };
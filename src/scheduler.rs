@@ -1,8 +1,9 @@
 use crate::prelude::*;
 
 use cron_parser::parse;
+use futures::future::{FutureExt, LocalBoxFuture};
 
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, TimeZone, Utc};
 
 pub struct Scheduler {
     config: ConfigWrapper,
@@ -29,34 +30,227 @@ impl Scheduler {
 
         info!("scheduler starting");
 
-        if let Some(timesync_cron) = scheduler.timesync_cron() {
-            // sticking to Utc here avoids some "invalid date" panics around DST changes
-            while let Ok(next) = parse(timesync_cron, &Utils::utc()) {
-                let sleep = next - Utils::utc();
+        // give MQTT a chance to start listening for the next run times
+        tokio::task::yield_now().await;
 
-                // localtime is only used for display
-                let local_next: DateTime<Local> = DateTime::from(next);
-                info!("next timesync at {}, sleeping for {}", local_next, sleep);
+        // timesync and each job wait for their own next run, so none of them
+        // hold up the others
+        let mut tasks: Vec<LocalBoxFuture<'_, Result<()>>> = Vec::new();
 
-                tokio::time::sleep(sleep.to_std()?).await;
-                self.timesync().await?;
-            }
+        if let Some(timesync_cron) = scheduler.timesync_cron() {
+            let task = self.every("timesync".to_owned(), timesync_cron.to_owned(), || {
+                self.timesync()
+            });
+            tasks.push(task.boxed_local());
         } else {
             info!("timesync_cron config not found, skipping");
         }
 
+        for (n, job) in scheduler.jobs().iter().enumerate() {
+            for (name, timezone, messages) in self.job_runs(job, n + 1) {
+                let task = self.every_in(name, job.cron.to_owned(), timezone, move || {
+                    futures::future::ready(self.run_job(&messages))
+                });
+                tasks.push(task.boxed_local());
+            }
+        }
+
+        futures::future::try_join_all(tasks).await?;
+
         info!("scheduler exiting");
 
         Ok(())
     }
 
+    // Runs f each time cron (in UTC) comes round, until it has no next run.
+    // When that is due is published to scheduler/{name}/next_run. A run which
+    // fails is logged and the next one goes ahead as usual.
+    pub(crate) async fn every<F, Fut>(&self, name: String, cron: String, f: F) -> Result<()>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = Result<()>>,
+    {
+        self.every_in(name, cron, Some(chrono_tz::Tz::UTC), f).await
+    }
+
+    // As every, but with cron in timezone (the host's if None).
+    pub(crate) async fn every_in<F, Fut>(
+        &self,
+        name: String,
+        cron: String,
+        timezone: Option<chrono_tz::Tz>,
+        f: F,
+    ) -> Result<()>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = Result<()>>,
+    {
+        loop {
+            let next = match timezone {
+                Some(tz) => Self::next_run(&cron, &tz, Utils::utc()),
+                None => Self::next_run(&cron, &Local, Utils::utc()),
+            };
+            let next = match next {
+                Ok(next) => next,
+                Err(err) => {
+                    warn!("{}: no next run for {:?}: {}", name, cron, err);
+                    break;
+                }
+            };
+            let sleep = next - Utils::utc();
+
+            // localtime is only used for display
+            let local_next: DateTime<Local> = DateTime::from(next);
+            info!("next {} at {}, sleeping for {}", name, local_next, sleep);
+            self.publish_next_run(&name, &local_next);
+
+            tokio::time::sleep(sleep.to_std()?).await;
+            if let Err(err) = f().await {
+                warn!("{} failed: {}", name, err);
+            }
+        }
+
+        Ok(())
+    }
+
+    // The first time after now that cron comes round on timezone's wall
+    // clock. cron is worked out on the wall clock time as if it were UTC,
+    // which avoids some "invalid date" panics around DST changes. A time the
+    // clocks skip over runs an hour later, and one they pass
+    // twice runs the first time.
+    pub fn next_run<T: TimeZone>(
+        cron: &str,
+        timezone: &T,
+        now: DateTime<Utc>,
+    ) -> Result<DateTime<Utc>> {
+        let wall_clock = Utc.from_utc_datetime(&now.with_timezone(timezone).naive_local());
+        let next = parse(cron, &wall_clock)
+            .map_err(|err| anyhow!("{:?}", err))?
+            .naive_utc();
+
+        timezone
+            .from_local_datetime(&next)
+            .earliest()
+            .or_else(|| {
+                timezone
+                    .from_local_datetime(&(next + chrono::Duration::hours(1)))
+                    .earliest()
+            })
+            .map(|next| next.with_timezone(&Utc))
+            .ok_or_else(|| anyhow!("{} is skipped by a DST change", next))
+    }
+
+    fn publish_next_run(&self, name: &str, next: &DateTime<Local>) {
+        if !self.config.mqtt().enabled() {
+            return;
+        }
+
+        let message = mqtt::Message {
+            topic: format!("scheduler/{}/next_run", name),
+            retain: true,
+            payload: next.to_rfc3339(),
+        };
+        // not worth stopping the scheduler over if MQTT isn't listening
+        if self
+            .channels
+            .to_mqtt
+            .send(mqtt::ChannelData::Message(message))
+            .is_err()
+        {
+            debug!("could not publish next {} run, MQTT not connected?", name);
+        }
+    }
+
+    // A job's cron is in the timezone of the inverters it's for. When they
+    // don't all agree, each inverter gets its own run, named {name}/{datalog}.
+    fn job_runs(
+        &self,
+        job: &config::Job,
+        n: usize,
+    ) -> Vec<(String, Option<chrono_tz::Tz>, Vec<mqtt::Message>)> {
+        let inverters: Vec<config::Inverter> = self
+            .config
+            .enabled_inverters()
+            .into_iter()
+            .filter(|inverter| {
+                job.datalogs.is_empty() || job.datalogs.contains(&inverter.datalog())
+            })
+            .collect();
+
+        let timezone = inverters.first().and_then(|inverter| inverter.timezone());
+        if inverters
+            .iter()
+            .all(|inverter| inverter.timezone() == timezone)
+        {
+            return vec![(job.name(n), timezone, job.messages())];
+        }
+
+        inverters
+            .iter()
+            .map(|inverter| {
+                (
+                    format!("{}/{}", job.name(n), inverter.datalog()),
+                    inverter.timezone(),
+                    vec![job.message_for(inverter.datalog())],
+                )
+            })
+            .collect()
+    }
+
+    // Jobs are handed to the coordinator just like commands received over
+    // MQTT, so they go through the same checks and get the same replies.
+    fn run_job(&self, messages: &[mqtt::Message]) -> Result<()> {
+        for message in messages.iter().cloned() {
+            info!("running job: {} {:?}", message.topic, message.payload);
+
+            if self
+                .channels
+                .from_mqtt
+                .send(mqtt::ChannelData::Message(message))
+                .is_err()
+            {
+                bail!("send(from_mqtt) failed - channel closed?");
+            }
+        }
+
+        Ok(())
+    }
+
     async fn timesync(&self) -> Result<()> {
         info!("timesync starting");
 
         for inverter in self.config.enabled_inverters() {
-            coordinator::commands::timesync::TimeSync::new(self.channels.clone(), inverter)
-                .run()
-                .await?;
+            let datalog = inverter.datalog();
+            let timesync = coordinator::commands::timesync::TimeSync::new(
+                self.channels.clone(),
+                inverter.clone(),
+            );
+
+            // read-only inverters still get their drift reported
+            let clock = if self.config.read_only() || inverter.read_only() {
+                info!("{} is read-only, only checking its clock", datalog);
+                timesync.read().await
+            } else {
+                timesync.run().await
+            };
+            // one inverter being offline is no reason to skip the rest
+            let clock = match clock {
+                Ok(clock) => clock,
+                Err(err) => {
+                    warn!("timesync {}: {}", datalog, err);
+                    continue;
+                }
+            };
+
+            if self.config.mqtt().enabled() {
+                let message = mqtt::ChannelData::Message(clock.message(datalog)?);
+                if self.channels.to_mqtt.send(message).is_err() {
+                    debug!(
+                        "could not publish clock for {}, MQTT not connected?",
+                        datalog
+                    );
+                }
+            }
         }
 
         info!("timesync complete");
@@ -0,0 +1,439 @@
+use crate::prelude::*;
+
+use chrono::{DateTime, Duration, TimeZone, Timelike, Utc};
+use coordinator::schedule::{Mode, Schedule, Slot, Time, SLOTS};
+use serde::{Deserialize, Serialize};
+use std::{cell::Cell, rc::Rc};
+
+// the time registers only hold a time of day, so there's no point planning
+// further ahead than this
+const HORIZON_HOURS: i64 = 24;
+
+// Price {{{
+// One slot of a tariff. The field names used by Octopus' API are accepted too.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Price {
+    #[serde(
+        alias = "valid_from",
+        deserialize_with = "Utils::de_rfc3339",
+        serialize_with = "Utils::se_rfc3339"
+    )]
+    pub start: DateTime<Utc>,
+    #[serde(
+        alias = "valid_to",
+        deserialize_with = "Utils::de_rfc3339",
+        serialize_with = "Utils::se_rfc3339"
+    )]
+    pub end: DateTime<Utc>,
+    #[serde(alias = "value_inc_vat")]
+    pub price: f64,
+}
+
+impl Price {
+    fn hours(&self) -> f64 {
+        (self.end - self.start).num_seconds() as f64 / 3600.0
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Prices {
+    List(Vec<Price>),
+    Prices { prices: Vec<Price> },
+    Results { results: Vec<Price> },
+}
+
+// a bare list of prices, or one under "prices" or "results"
+pub fn parse_prices(json: &str) -> Result<Vec<Price>> {
+    match serde_json::from_str(json)? {
+        Prices::List(prices) | Prices::Prices { prices } | Prices::Results { results: prices } => {
+            Ok(prices)
+        }
+    }
+} // }}}
+
+// Plan {{{
+// What the optimiser decided and why, as published to {datalog}/tariff/plan.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Plan {
+    pub soc: u16,
+    pub target_soc: u16,
+    pub capacity_kwh: f64,
+    // energy needed to get from soc to target_soc
+    pub charge_kwh: f64,
+    pub charge: Vec<Price>,
+    pub discharge: Vec<Price>,
+    pub schedule: Schedule,
+}
+
+// a time of day in minutes, end exclusive; an end of 1440 is midnight
+type Range = (u32, u32);
+
+impl Plan {
+    // Charges in the cheapest slots of the next day until there's enough
+    // energy to reach target_soc. If discharge_above is set, the most
+    // expensive slots over that price (and dearer than any we charge in) are
+    // used to discharge down to discharge_soc. The slots chosen are then
+    // turned into the inverter's time-of-day windows in now's timezone.
+    pub fn new<Tz: TimeZone>(
+        config: &config::Tariff,
+        prices: &[Price],
+        now: DateTime<Tz>,
+        soc: u16,
+        capacity_kwh: f64,
+    ) -> Self {
+        let tz = now.timezone();
+        let now = now.with_timezone(&Utc);
+        let horizon = now + Duration::hours(HORIZON_HOURS);
+
+        let mut upcoming: Vec<&Price> = prices
+            .iter()
+            .filter(|p| p.end > now && p.start < horizon)
+            .collect();
+        upcoming.sort_by(|a, b| a.price.total_cmp(&b.price));
+
+        let target_soc = config.target_soc();
+        let charge_kwh = Utils::round(
+            target_soc.saturating_sub(soc) as f64 / 100.0 * capacity_kwh,
+            2,
+        );
+
+        let mut charge = Vec::new();
+        let mut planned = 0.0;
+        for price in &upcoming {
+            if planned >= charge_kwh {
+                break;
+            }
+            planned += config.charge_power() as f64 / 1000.0 * price.hours();
+            charge.push((*price).clone());
+        }
+
+        let mut discharge = Vec::new();
+        if let Some(threshold) = config.discharge_above() {
+            let dearest_charge = charge
+                .iter()
+                .map(|p| p.price)
+                .fold(f64::NEG_INFINITY, f64::max);
+            let available = soc.max(target_soc).saturating_sub(config.discharge_soc()) as f64
+                / 100.0
+                * capacity_kwh;
+
+            let mut planned = 0.0;
+            for price in upcoming.iter().rev() {
+                if planned >= available || price.price < threshold || price.price <= dearest_charge
+                {
+                    break;
+                }
+                planned += config.discharge_power() as f64 / 1000.0 * price.hours();
+                discharge.push((*price).clone());
+            }
+        }
+
+        charge.sort_by_key(|p| p.start);
+        discharge.sort_by_key(|p| p.start);
+
+        // discharge windows may not be widened over a charging slot, and
+        // charge windows may not be widened over the final discharge windows
+        let charge_ranges = Self::ranges(&charge, &tz);
+        let discharge_ranges = Self::limited(Self::ranges(&discharge, &tz), &charge_ranges);
+        let charge_ranges = Self::limited(charge_ranges, &discharge_ranges);
+
+        let mut schedule = Schedule {
+            ac_charge: Some(Mode {
+                enabled: Some(!charge.is_empty()),
+                slots: Some(charge_ranges.into_iter().map(Self::slot).collect()),
+                rate_pct: None,
+                soc_limit_pct: Some(target_soc),
+            }),
+            ..Default::default()
+        };
+        if config.discharge_above().is_some() {
+            schedule.forced_discharge = Some(Mode {
+                enabled: Some(!discharge.is_empty()),
+                slots: Some(discharge_ranges.into_iter().map(Self::slot).collect()),
+                rate_pct: None,
+                soc_limit_pct: Some(config.discharge_soc()),
+            });
+        }
+
+        Self {
+            soc,
+            target_soc,
+            capacity_kwh: Utils::round(capacity_kwh, 2),
+            charge_kwh,
+            charge,
+            discharge,
+            schedule,
+        }
+    }
+
+    // the times of day covered by slots, split at midnight and merged where
+    // they touch
+    fn ranges<Tz: TimeZone>(slots: &[Price], tz: &Tz) -> Vec<Range> {
+        let minutes = |time: &DateTime<Utc>| {
+            let time = time.with_timezone(tz);
+            time.hour() * 60 + time.minute()
+        };
+
+        let mut ranges = Vec::new();
+        for slot in slots {
+            let (start, end) = (minutes(&slot.start), minutes(&slot.end));
+            if end > start {
+                ranges.push((start, end));
+            } else {
+                ranges.push((start, 1440));
+                if end > 0 {
+                    ranges.push((0, end));
+                }
+            }
+        }
+        ranges.sort_unstable();
+
+        let mut merged: Vec<Range> = Vec::new();
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+
+        merged
+    }
+
+    // Cuts ranges down to the number of slots the inverter has, by closing
+    // the smallest gap which doesn't cover any of blocked, or failing that by
+    // dropping the shortest range.
+    fn limited(mut ranges: Vec<Range>, blocked: &[Range]) -> Vec<Range> {
+        while ranges.len() > SLOTS as usize {
+            let gap = (0..ranges.len() - 1)
+                .filter(|&i| {
+                    let (start, end) = (ranges[i].1, ranges[i + 1].0);
+                    !blocked.iter().any(|b| b.0 < end && start < b.1)
+                })
+                .min_by_key(|&i| ranges[i + 1].0 - ranges[i].1);
+
+            match gap {
+                Some(i) => {
+                    ranges[i].1 = ranges[i + 1].1;
+                    ranges.remove(i + 1);
+                }
+                None => {
+                    let shortest = (0..ranges.len())
+                        .min_by_key(|&i| ranges[i].1 - ranges[i].0)
+                        .unwrap();
+                    ranges.remove(shortest);
+                }
+            }
+        }
+
+        ranges
+    }
+
+    fn slot((start, end): Range) -> Slot {
+        let time = |minutes: u32| Time {
+            hour: (minutes / 60) as u8,
+            minute: (minutes % 60) as u8,
+        };
+
+        Slot {
+            start: time(start),
+            // the registers can't say midnight at the end of the day
+            end: time(end.min(1439)),
+        }
+    }
+} // }}}
+
+// Tariff {{{
+// A target_soc to plan for in place of the configured one; forecast sets this
+// when it and tariff are both enabled for the same inverter.
+pub type TargetSoc = Rc<Cell<Option<u16>>>;
+
+pub struct Tariff {
+    config: ConfigWrapper,
+    channels: Channels,
+    target_soc: TargetSoc,
+}
+
+impl Tariff {
+    pub fn new(config: ConfigWrapper, channels: Channels) -> Self {
+        Self {
+            config,
+            channels,
+            target_soc: TargetSoc::default(),
+        }
+    }
+
+    pub fn target_soc(&self) -> TargetSoc {
+        self.target_soc.clone()
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let tariff = self.config.tariff().clone();
+        if tariff.is_none() {
+            info!("tariff config not found, skipping");
+            return Ok(());
+        }
+
+        let tariff = tariff.unwrap();
+        if !tariff.enabled() {
+            info!("tariff disabled, skipping");
+            return Ok(());
+        }
+
+        let inverter = match self.config.enabled_inverter_with_datalog(tariff.datalog()) {
+            Some(inverter) => inverter,
+            None => bail!(
+                "tariff: no enabled inverter with datalog {}",
+                tariff.datalog()
+            ),
+        };
+
+        info!("tariff optimiser starting");
+
+        // the first plan goes out straight away, so let the coordinator
+        // subscribe to from_mqtt before it does
+        tokio::task::yield_now().await;
+
+        loop {
+            // failures are retried at the next interval with fresh prices
+            if let Err(err) = self.run(&tariff, &inverter).await {
+                warn!("tariff: {}", err);
+            }
+
+            tokio::time::sleep(std::time::Duration::from_secs(tariff.interval())).await;
+        }
+    }
+
+    // Plans from the latest prices and the battery's current state, then
+    // hands the schedule to the coordinator as a set/schedule command.
+    pub async fn run(&self, tariff: &config::Tariff, inverter: &config::Inverter) -> Result<Plan> {
+        let prices = parse_prices(&Utils::fetch(tariff.source()).await?)?;
+        let (soc, capacity_kwh) = self.battery(tariff, inverter).await?;
+
+        let mut tariff = tariff.clone();
+        if let Some(target_soc) = self.target_soc.get() {
+            tariff.target_soc = Some(target_soc);
+        }
+        let tariff = &tariff;
+
+        // windows are in the inverter's time of day, which may not be ours
+        let now = Utils::utc();
+        let plan = match inverter.timezone() {
+            Some(tz) => Plan::new(tariff, &prices, now.with_timezone(&tz), soc, capacity_kwh),
+            None => Plan::new(
+                tariff,
+                &prices,
+                now.with_timezone(&chrono::Local),
+                soc,
+                capacity_kwh,
+            ),
+        };
+        info!(
+            "tariff plan: soc {}%, {} charge slots, {} discharge slots",
+            plan.soc,
+            plan.charge.len(),
+            plan.discharge.len()
+        );
+
+        publish(
+            &self.config,
+            &self.channels,
+            format!("{}/tariff/plan", inverter.datalog()),
+            &plan,
+        )?;
+
+        let message = mqtt::Message {
+            topic: format!("cmd/{}/set/schedule", inverter.datalog()),
+            retain: false,
+            payload: serde_json::to_string(&plan.schedule)?,
+        };
+        if self
+            .channels
+            .from_mqtt
+            .send(mqtt::ChannelData::Message(message))
+            .is_err()
+        {
+            bail!("send(from_mqtt) failed - channel closed?");
+        }
+
+        Ok(plan)
+    }
+
+    // SOC and usable capacity in kWh
+    async fn battery(
+        &self,
+        tariff: &config::Tariff,
+        inverter: &config::Inverter,
+    ) -> Result<(u16, f64)> {
+        let soc = match read_inputs(&self.channels, inverter, 0).await? {
+            lxp::packet::ReadInput::ReadInput1(r) => r.soc.max(0) as u16,
+            _ => bail!("unexpected reply reading soc"),
+        };
+        let capacity_kwh =
+            battery_capacity(&self.channels, inverter, tariff.battery_voltage()).await?;
+
+        Ok((soc, capacity_kwh))
+    }
+} // }}}
+
+// Helpers {{{
+// usable battery capacity in kWh, from bat_capacity (Ah) at battery_voltage
+pub(crate) async fn battery_capacity(
+    channels: &Channels,
+    inverter: &config::Inverter,
+    battery_voltage: f64,
+) -> Result<f64> {
+    let capacity = match read_inputs(channels, inverter, 80).await? {
+        lxp::packet::ReadInput::ReadInput3(r) => r.bat_capacity,
+        _ => bail!("unexpected reply reading bat_capacity"),
+    };
+
+    Ok(capacity as f64 * battery_voltage / 1000.0)
+}
+
+async fn read_inputs(
+    channels: &Channels,
+    inverter: &config::Inverter,
+    register: u16,
+) -> Result<lxp::packet::ReadInput> {
+    let packet = coordinator::commands::read_inputs::ReadInputs::new(
+        channels.clone(),
+        inverter.clone(),
+        register,
+        40,
+    )
+    .run()
+    .await?;
+
+    match packet {
+        Packet::TranslatedData(td) => td.read_input(),
+        _ => bail!("didn't get expected reply from inverter"),
+    }
+}
+
+// value as retained JSON on topic, if MQTT is enabled
+pub(crate) fn publish<T: Serialize>(
+    config: &ConfigWrapper,
+    channels: &Channels,
+    topic: String,
+    value: &T,
+) -> Result<()> {
+    if !config.mqtt().enabled() {
+        return Ok(());
+    }
+
+    let message = mqtt::Message {
+        topic,
+        retain: true,
+        payload: serde_json::to_string(value)?,
+    };
+    if channels
+        .to_mqtt
+        .send(mqtt::ChannelData::Message(message))
+        .is_err()
+    {
+        bail!("send(to_mqtt) failed - channel closed?");
+    }
+
+    Ok(())
+} // }}}
@@ -49,6 +49,46 @@ impl Utils {
         Ok((input, UnixTime::now()))
     }
 
+    // the body of an http(s) URL, or the contents of a local file. A server
+    // which never answers gives up after 30 seconds.
+    pub async fn fetch(source: &str) -> Result<String> {
+        if source.starts_with("http://") || source.starts_with("https://") {
+            let client = reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(30))
+                .build()?;
+            let response = client.get(source).send().await?.error_for_status()?;
+            Ok(response.text().await?)
+        } else {
+            std::fs::read_to_string(source)
+                .map_err(|err| anyhow!("error reading {}: {}", source, err))
+        }
+    }
+
+    // serde helpers for RFC3339 timestamps, as used by price and forecast feeds
+    pub fn de_rfc3339<'de, D>(
+        deserializer: D,
+    ) -> std::result::Result<chrono::DateTime<chrono::Utc>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::Deserialize;
+
+        let s = String::deserialize(deserializer)?;
+        chrono::DateTime::parse_from_rfc3339(&s)
+            .map(|t| t.with_timezone(&chrono::Utc))
+            .map_err(serde::de::Error::custom)
+    }
+
+    pub fn se_rfc3339<S>(
+        time: &chrono::DateTime<chrono::Utc>,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&time.to_rfc3339())
+    }
+
     #[cfg(not(feature = "mocks"))]
     pub fn utc() -> chrono::DateTime<chrono::Utc> {
         chrono::Utc::now()
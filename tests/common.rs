@@ -24,6 +24,15 @@ impl Factory {
             heartbeats: None,
             publish_holdings_on_connect: None,
             read_timeout: None,
+            max_power: None,
+            hold_cache_max_age: None,
+            refresh_holdings_interval: None,
+            timezone: None,
+            time_tolerance: None,
+            write_interval: None,
+            read_only: None,
+            writable_registers: None,
+            protected_registers: None,
         }
     }
 
@@ -248,6 +257,67 @@ impl Factory {
             datalog: Serial::from_str("1234567890").unwrap(),
         }
     }
+
+    pub fn read_input_all2() -> lxp::packet::ReadInputAll2 {
+        lxp::packet::ReadInputAll2 {
+            v_eps_l1: 120.1,
+            v_eps_l2: 119.8,
+            p_eps_l1: 230,
+            p_eps_l2: 210,
+            s_eps_l1: 240,
+            s_eps_l2: 220,
+            e_eps_l1_day: 1.2,
+            e_eps_l2_day: 1.1,
+            e_eps_l1_all: 345.6,
+            e_eps_l2_all: 321.0,
+            afci_ch1_current: 0,
+            afci_ch2_current: 0,
+            afci_ch3_current: 0,
+            afci_ch4_current: 0,
+            register_144: 0,
+            afci_arc_ch1: 0,
+            afci_arc_ch2: 0,
+            afci_arc_ch3: 0,
+            afci_arc_ch4: 0,
+            afci_max_arc_ch1: 0,
+            afci_max_arc_ch2: 0,
+            afci_max_arc_ch3: 0,
+            afci_max_arc_ch4: 0,
+            p_ac_couple: 0,
+            p_load: 450,
+            e_load_day: 6.3,
+            e_load_all: 1234.5,
+            eps_overload_ctrl_time: 0,
+            p_inv_s: 0,
+            p_inv_t: 0,
+            p_rec_s: 0,
+            p_rec_t: 0,
+            p_to_grid_s: 0,
+            p_to_grid_t: 0,
+            p_to_user_s: 0,
+            p_to_user_t: 0,
+            p_gen_s: 0,
+            p_gen_t: 0,
+            inv_rms_curr_s: 0.0,
+            inv_rms_curr_t: 0.0,
+            pf_s: 0.0,
+            v_grid_l1: 121.3,
+            v_grid_l2: 120.9,
+            v_gen_l1: 0.0,
+            v_gen_l2: 0.0,
+            p_inv_l1: -20,
+            p_inv_l2: 0,
+            p_rec_l1: 0,
+            p_rec_l2: 0,
+            p_to_grid_l1: 0,
+            p_to_grid_l2: 0,
+            p_to_user_l1: 100,
+            p_to_user_l2: 90,
+            pf_t: 0.0,
+            time: UnixTime::now(),
+            datalog: Serial::from_str("1234567890").unwrap(),
+        }
+    }
 }
 
 pub fn common_setup() {
@@ -0,0 +1,193 @@
+mod common;
+use common::*;
+
+use chrono::NaiveTime;
+use lxp_bridge::automation::{parse_conditions, Condition, Engine, Inputs, Op, Rule, RuleState};
+
+fn rule(when: &str, hysteresis: Option<f64>, cooldown: Option<u64>) -> Rule {
+    Rule::new(&config::Rule {
+        name: "test".to_owned(),
+        when: when.to_owned(),
+        command: "set/forced_discharge".to_owned(),
+        payload: "off".to_owned(),
+        datalogs: vec![],
+        hysteresis,
+        cooldown,
+    })
+    .unwrap()
+}
+
+fn inputs(json: &str) -> Inputs {
+    serde_json::from_str(json).unwrap()
+}
+
+fn time(hour: u32, minute: u32) -> NaiveTime {
+    NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+}
+
+#[test]
+fn parses_conditions() {
+    common_setup();
+
+    assert_eq!(
+        parse_conditions("soc < 20 and time between 16:00 and 19:00").unwrap(),
+        vec![
+            Condition::Compare {
+                key: "soc".to_owned(),
+                op: Op::Lt,
+                value: 20.0
+            },
+            Condition::TimeBetween {
+                start: time(16, 0),
+                end: time(19, 0)
+            }
+        ]
+    );
+
+    // spaces around comparisons are optional
+    assert_eq!(
+        parse_conditions("t_bat>=45.5").unwrap(),
+        vec![Condition::Compare {
+            key: "t_bat".to_owned(),
+            op: Op::Ge,
+            value: 45.5
+        }]
+    );
+
+    assert!(parse_conditions("").is_err());
+    assert!(parse_conditions("soc <").is_err());
+    assert!(parse_conditions("soc < low").is_err());
+    assert!(parse_conditions("soc => 20").is_err());
+    assert!(parse_conditions("soc < 20 or soc > 90").is_err());
+    assert!(parse_conditions("time between 16:00 and 7pm").is_err());
+}
+
+#[test]
+fn bad_rule_names_itself() {
+    common_setup();
+
+    let err = Rule::new(&config::Rule {
+        name: "broken".to_owned(),
+        when: "soc <".to_owned(),
+        command: "set/forced_discharge".to_owned(),
+        payload: String::new(),
+        datalogs: vec![],
+        hysteresis: None,
+        cooldown: None,
+    })
+    .unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        "rule broken: expected a number to compare 'soc' with"
+    );
+}
+
+#[test]
+fn fires_once_when_conditions_become_true() {
+    common_setup();
+
+    let rule = rule("soc < 20 and time between 16:00 and 19:00", None, None);
+    let mut state = RuleState::default();
+
+    // outside the window
+    assert!(!rule.check(&mut state, &inputs(r#"{"soc":15}"#), time(15, 0), 0));
+    assert!(rule.check(&mut state, &inputs(r#"{"soc":15}"#), time(16, 0), 1));
+    // still true, so not again
+    assert!(!rule.check(&mut state, &inputs(r#"{"soc":10}"#), time(17, 0), 2));
+    // re-arms when false, fires when true again
+    assert!(!rule.check(&mut state, &inputs(r#"{"soc":20}"#), time(17, 0), 3));
+    assert!(rule.check(&mut state, &inputs(r#"{"soc":19}"#), time(17, 0), 4));
+}
+
+#[test]
+fn time_window_past_midnight() {
+    common_setup();
+
+    let rule = rule("time between 23:00 and 02:00", None, None);
+
+    for (t, fires) in [
+        (time(22, 59), false),
+        (time(23, 0), true),
+        (time(1, 59), true),
+        (time(2, 0), false),
+    ] {
+        let mut state = RuleState::default();
+        assert_eq!(rule.check(&mut state, &inputs("{}"), t, 0), fires);
+    }
+}
+
+#[test]
+fn hysteresis_delays_rearming() {
+    common_setup();
+
+    let rule = rule("t_bat > 45", Some(5.0), None);
+    let mut state = RuleState::default();
+    let at = |t_bat| inputs(&format!(r#"{{"t_bat":{}}}"#, t_bat));
+
+    assert!(rule.check(&mut state, &at(46), time(12, 0), 0));
+    // not far enough back below 45 to re-arm
+    assert!(!rule.check(&mut state, &at(41), time(12, 0), 1));
+    assert!(!rule.check(&mut state, &at(46), time(12, 0), 2));
+    // now it is
+    assert!(!rule.check(&mut state, &at(40), time(12, 0), 3));
+    assert!(rule.check(&mut state, &at(46), time(12, 0), 4));
+}
+
+#[test]
+fn cooldown_between_firings() {
+    common_setup();
+
+    let rule = rule("t_bat > 45", None, Some(600));
+    let mut state = RuleState::default();
+    let at = |t_bat| inputs(&format!(r#"{{"t_bat":{}}}"#, t_bat));
+
+    assert!(rule.check(&mut state, &at(46), time(12, 0), 1000));
+    assert!(!rule.check(&mut state, &at(40), time(12, 0), 1100));
+    // re-armed but still cooling down
+    assert!(!rule.check(&mut state, &at(46), time(12, 0), 1200));
+    assert!(rule.check(&mut state, &at(46), time(12, 0), 1600));
+}
+
+#[test]
+fn missing_inputs_neither_fire_nor_rearm() {
+    common_setup();
+
+    let rule = rule("soc < 20", None, None);
+    let mut state = RuleState::default();
+
+    assert!(!rule.check(&mut state, &inputs("{}"), time(12, 0), 0));
+    assert!(rule.check(&mut state, &inputs(r#"{"soc":10}"#), time(12, 0), 1));
+    assert!(!rule.check(&mut state, &inputs("{}"), time(12, 0), 2));
+    assert!(!rule.check(&mut state, &inputs(r#"{"soc":10}"#), time(12, 0), 3));
+}
+
+#[test]
+fn engine_ignores_stale_inputs() {
+    common_setup();
+
+    let inverter = Factory::inverter();
+    // soc 1, among others
+    let td = lxp::packet::TranslatedData {
+        datalog: inverter.datalog(),
+        device_function: lxp::packet::DeviceFunction::ReadInput,
+        inverter: inverter.serial(),
+        register: 0,
+        values: vec![1; 254],
+    };
+
+    let mut engine = Engine::new(vec![rule("soc < 20", None, None)], 60);
+    assert_eq!(engine.update_inputs(&td, 1000), Some(inverter.datalog()));
+
+    // three intervals on, soc is too old to act on
+    assert_eq!(
+        engine.evaluate(inverter.datalog(), time(12, 0), 1181),
+        vec![]
+    );
+
+    // and fires once it's read again
+    engine.update_inputs(&td, 1200);
+    let actions = engine.evaluate(inverter.datalog(), time(12, 0), 1200);
+    assert_eq!(actions.len(), 1);
+    assert_eq!(actions[0].values, inputs(r#"{"soc":1}"#));
+}
@@ -0,0 +1,129 @@
+mod common;
+use common::*;
+
+use lxp_bridge::backup::{Difference, Kind, Setting, Settings};
+
+fn settings(holdings: Vec<Setting>, params: Vec<Setting>) -> Settings {
+    Settings {
+        datalog: Factory::inverter().datalog(),
+        holdings,
+        params,
+    }
+}
+
+#[test]
+fn setting_hold() {
+    common_setup();
+
+    assert_eq!(
+        Setting::hold(177, 125),
+        Setting {
+            register: 177,
+            name: Some("GenRatePower".to_owned()),
+            raw: 125,
+            value: Some(12.5),
+            unit: Some("kW".to_owned()),
+        }
+    );
+    assert_eq!(Setting::hold(64, 50).name.unwrap(), "ChargePowerPercentCmd");
+
+    // nothing known about this one
+    assert_eq!(
+        Setting::hold(1, 3),
+        Setting {
+            register: 1,
+            name: None,
+            raw: 3,
+            value: None,
+            unit: None,
+        }
+    );
+}
+
+#[test]
+fn differences() {
+    common_setup();
+
+    let wanted = settings(
+        vec![
+            Setting::hold(12, 1),
+            Setting::hold(64, 100),
+            Setting::hold(65, 50),
+            Setting::hold(66, 100),
+        ],
+        vec![Setting::param(7, 1)],
+    );
+    let live = settings(
+        vec![
+            Setting::hold(12, 2),
+            Setting::hold(64, 50),
+            Setting::hold(65, 50),
+        ],
+        vec![Setting::param(7, 0)],
+    );
+
+    let differences = wanted.differences(&live);
+
+    // the clock in register 12 is never restored, and 65 already matches
+    assert_eq!(
+        differences,
+        vec![
+            Difference {
+                kind: Kind::Hold,
+                setting: Setting::hold(64, 100),
+                live: Some(50),
+            },
+            Difference {
+                kind: Kind::Hold,
+                setting: Setting::hold(66, 100),
+                live: None,
+            },
+            Difference {
+                kind: Kind::Param,
+                setting: Setting::param(7, 1),
+                live: Some(0),
+            },
+        ]
+    );
+
+    assert_eq!(
+        differences[0].to_string(),
+        "hold 64 (ChargePowerPercentCmd): 50 -> 100"
+    );
+    assert_eq!(
+        differences[1].to_string(),
+        "hold 66 (AcChargePowerCmd): ? -> 100"
+    );
+    assert_eq!(differences[2].to_string(), "param 7: 0 -> 1");
+
+    // scaled, as in the backup file
+    let difference = Difference {
+        kind: Kind::Hold,
+        setting: Setting::hold(177, 125),
+        live: Some(100),
+    };
+    assert_eq!(
+        difference.to_string(),
+        "hold 177 (GenRatePower): 10 kW -> 12.5 kW"
+    );
+}
+
+#[test]
+fn save_and_load() -> Result<()> {
+    common_setup();
+
+    let file = std::env::temp_dir().join("lxp-bridge-test-backup.yaml");
+    let file = file.to_str().unwrap();
+
+    let backup = settings(
+        vec![Setting::hold(177, 125), Setting::hold(1, 3)],
+        vec![Setting::param(7, 1)],
+    );
+    backup.save(file)?;
+
+    assert_eq!(Settings::load(file)?, backup);
+
+    std::fs::remove_file(file)?;
+
+    Ok(())
+}
@@ -19,6 +19,23 @@ fn config_returns_ok() {
     assert!(config.is_ok());
 }
 
+#[test]
+fn config_checks_presets() {
+    let mut config: serde_yaml::Value =
+        serde_yaml::from_str(&std::fs::read_to_string("config.yaml.example").unwrap()).unwrap();
+    config["presets"] = serde_yaml::from_str("storm:\n  AcChargeSocLimt: 100\n").unwrap();
+
+    let file = std::env::temp_dir().join("lxp-bridge-test-presets.yaml");
+    std::fs::write(&file, serde_yaml::to_string(&config).unwrap()).unwrap();
+
+    assert_eq!(
+        Config::new(file.to_str().unwrap().to_owned())
+            .unwrap_err()
+            .to_string(),
+        "preset storm: AcChargeSocLimt: unknown register or bit"
+    );
+}
+
 #[test]
 fn inverter_defaults() {
     let input =
@@ -29,6 +46,23 @@ fn inverter_defaults() {
     assert_eq!(inverter.publish_holdings_on_connect(), false);
 }
 
+#[test]
+fn inverter_timezone() {
+    let input =
+        json!({ "host": "host", "port": 8000, "serial": "TESTSERIAL", "datalog": "TESTDATALO" });
+    let inverter: config::Inverter = serde_json::from_value(input).unwrap();
+    assert_eq!(inverter.timezone(), None);
+    assert_eq!(inverter.time_tolerance(), 120);
+
+    let input = json!({ "host": "host", "port": 8000, "serial": "TESTSERIAL", "datalog": "TESTDATALO", "timezone": "Europe/London", "time_tolerance": 30 });
+    let inverter: config::Inverter = serde_json::from_value(input).unwrap();
+    assert_eq!(inverter.timezone(), Some(chrono_tz::Europe::London));
+    assert_eq!(inverter.time_tolerance(), 30);
+
+    let input = json!({ "host": "host", "port": 8000, "serial": "TESTSERIAL", "datalog": "TESTDATALO", "timezone": "Europe/Nowhere" });
+    assert!(serde_json::from_value::<config::Inverter>(input).is_err());
+}
+
 #[test]
 fn inverter_heartbeats() {
     let input = json!({ "host": "host", "port": 8000, "serial": "TESTSERIAL", "datalog": "TESTDATALO", "heartbeats": false });
@@ -49,6 +83,33 @@ fn inverter_publish_holdings_on_connect() {
     assert_eq!(inverter.publish_holdings_on_connect(), true);
 }
 
+#[test]
+fn inverter_register_writable() {
+    let input = json!({ "host": "host", "port": 8000, "serial": "TESTSERIAL", "datalog": "TESTDATALO" });
+    let inverter: config::Inverter = serde_json::from_value(input).unwrap();
+    assert!(!inverter.read_only());
+    // reboot and LCD password are protected unless told otherwise
+    assert!(!inverter.register_writable(11));
+    assert!(!inverter.register_writable(225));
+    assert!(inverter.register_writable(64));
+
+    let input = json!({ "host": "host", "port": 8000, "serial": "TESTSERIAL", "datalog": "TESTDATALO", "protected_registers": [11] });
+    let inverter: config::Inverter = serde_json::from_value(input).unwrap();
+    assert!(!inverter.register_writable(11));
+    assert!(inverter.register_writable(225));
+    assert!(inverter.register_writable(64));
+
+    let input = json!({ "host": "host", "port": 8000, "serial": "TESTSERIAL", "datalog": "TESTDATALO", "protected_registers": [] });
+    let inverter: config::Inverter = serde_json::from_value(input).unwrap();
+    assert!(inverter.register_writable(11));
+
+    let input = json!({ "host": "host", "port": 8000, "serial": "TESTSERIAL", "datalog": "TESTDATALO", "writable_registers": [64, 65], "protected_registers": [65] });
+    let inverter: config::Inverter = serde_json::from_value(input).unwrap();
+    assert!(inverter.register_writable(64));
+    assert!(!inverter.register_writable(65));
+    assert!(!inverter.register_writable(66));
+}
+
 #[test]
 fn database_defaults() {
     let input = json!({ "url": "url" });
@@ -63,6 +124,48 @@ fn mqtt_defaults() {
     assert!(mqtt.enabled());
     assert_eq!(mqtt.port(), 1883);
     assert_eq!(mqtt.namespace(), "lxp");
+    assert_eq!(mqtt.protocol(), config::MqttProtocol::V4);
+    assert_eq!(mqtt.message_expiry(), Some(300));
+
+    let input = json!({ "host": "host", "protocol": "v5", "message_expiry": 0 });
+    let mqtt: config::Mqtt = serde_json::from_value(input).unwrap();
+    assert_eq!(mqtt.protocol(), config::MqttProtocol::V5);
+    assert_eq!(mqtt.message_expiry(), None);
+}
+
+#[test]
+fn mqtt_instances() {
+    let input = json!({ "host": "host", "namespace": "site1" });
+    let mqtt: config::Mqtt = serde_json::from_value(input).unwrap();
+    assert!(mqtt.client_id().starts_with("lxp-bridge-"));
+    assert!(mqtt.client_id().len() > "lxp-bridge-".len());
+    assert_eq!(mqtt.lwt_topic(), format!("site1/{}/LWT", mqtt.client_id()));
+    assert!(mqtt.clean_session());
+    assert_eq!(mqtt.session_expiry(), 3600);
+
+    let input = json!({
+        "host": "host",
+        "client_id": "garage",
+        "lwt_topic": "lxp/garage/LWT",
+        "clean_session": false,
+        "session_expiry": 60
+    });
+    let mqtt: config::Mqtt = serde_json::from_value(input).unwrap();
+    assert_eq!(mqtt.client_id(), "garage");
+    assert_eq!(mqtt.lwt_topic(), "lxp/garage/LWT");
+    assert!(!mqtt.clean_session());
+    assert_eq!(mqtt.session_expiry(), 60);
+}
+
+#[test]
+fn mqtt_lwt_topic_per_client_id() {
+    let garage: config::Mqtt =
+        serde_json::from_value(json!({ "host": "host", "client_id": "garage" })).unwrap();
+    let shed: config::Mqtt =
+        serde_json::from_value(json!({ "host": "host", "client_id": "shed" })).unwrap();
+
+    assert_eq!(garage.lwt_topic(), "lxp/garage/LWT");
+    assert_eq!(shed.lwt_topic(), "lxp/shed/LWT");
 }
 
 #[test]
@@ -73,6 +176,57 @@ fn homeassistant_defaults() {
     assert_eq!(ha.prefix(), "homeassistant");
 }
 
+#[test]
+fn scheduler_jobs() {
+    let config = Config::new("config.yaml.example".to_owned()).unwrap();
+    let jobs = config.scheduler.unwrap().jobs;
+
+    assert_eq!(jobs.len(), 3);
+    assert_eq!(jobs[0].name(1), "night_charge_on");
+    assert_eq!(jobs[0].payload, "on");
+    assert_eq!(
+        jobs[0].messages(),
+        vec![mqtt::Message {
+            topic: "cmd/all/set/ac_charge".to_owned(),
+            retain: false,
+            payload: "on".to_owned(),
+        }]
+    );
+
+    assert_eq!(jobs[2].name(3), "job3");
+    assert_eq!(
+        jobs[2].messages(),
+        vec![mqtt::Message {
+            topic: "cmd/2222222222/set/hold/67".to_owned(),
+            retain: false,
+            payload: "90".to_owned(),
+        }]
+    );
+}
+
+#[test]
+fn job_payloads() {
+    let input = json!({ "cron": "0 2 * * *", "command": "set/schedule", "payload": { "ac_charge": { "enabled": true } } });
+    let job: config::Job = serde_json::from_value(input).unwrap();
+    assert_eq!(job.payload, r#"{"ac_charge":{"enabled":true}}"#);
+
+    let input = json!({ "cron": "0 2 * * *", "command": "read/inputs/1" });
+    let job: config::Job = serde_json::from_value(input).unwrap();
+    assert_eq!(job.payload, "");
+
+    let input = json!({ "cron": "0 2 * * *", "command": "set/ac_charge", "payload": true, "datalogs": ["2222222222", "3333333333"] });
+    let job: config::Job = serde_json::from_value(input).unwrap();
+    let topics: Vec<String> = job.messages().into_iter().map(|m| m.topic).collect();
+    assert_eq!(
+        topics,
+        vec![
+            "cmd/2222222222/set/ac_charge",
+            "cmd/3333333333/set/ac_charge"
+        ]
+    );
+    assert_eq!(job.payload, "true");
+}
+
 #[test]
 fn enabled_inverters() {
     let config = Factory::example_config_wrapped();
@@ -87,6 +241,15 @@ fn enabled_inverters() {
             heartbeats: None,
             publish_holdings_on_connect: None,
             read_timeout: None,
+            max_power: None,
+            hold_cache_max_age: None,
+            refresh_holdings_interval: None,
+            timezone: None,
+            time_tolerance: None,
+            write_interval: None,
+            read_only: None,
+            writable_registers: None,
+            protected_registers: None,
         },
         config::Inverter {
             enabled: true,
@@ -97,6 +260,15 @@ fn enabled_inverters() {
             heartbeats: None,
             publish_holdings_on_connect: None,
             read_timeout: None,
+            max_power: None,
+            hold_cache_max_age: None,
+            refresh_holdings_interval: None,
+            timezone: None,
+            time_tolerance: None,
+            write_interval: None,
+            read_only: None,
+            writable_registers: None,
+            protected_registers: None,
         },
     ]);
 
@@ -117,6 +289,15 @@ fn inverters_for_message() {
             heartbeats: None,
             publish_holdings_on_connect: None,
             read_timeout: None,
+            max_power: None,
+            hold_cache_max_age: None,
+            refresh_holdings_interval: None,
+            timezone: None,
+            time_tolerance: None,
+            write_interval: None,
+            read_only: None,
+            writable_registers: None,
+            protected_registers: None,
         },
         config::Inverter {
             enabled: false,
@@ -127,6 +308,15 @@ fn inverters_for_message() {
             heartbeats: None,
             publish_holdings_on_connect: None,
             read_timeout: None,
+            max_power: None,
+            hold_cache_max_age: None,
+            refresh_holdings_interval: None,
+            timezone: None,
+            time_tolerance: None,
+            write_interval: None,
+            read_only: None,
+            writable_registers: None,
+            protected_registers: None,
         },
     ]);
 
@@ -157,6 +157,9 @@ async fn handles_read_input_all() {
         let d = unwrap_influx_channeldata_input_data(to_influx.recv().await?);
         assert_eq!(d["soc"], 1);
         assert_eq!(d["v_pv_1"], 25.7);
+        assert_eq!(d["e_inv_total"], 1684300.9);
+        assert_eq!(d["e_inv_interval"], 0.0);
+        assert_eq!(d["p_consumption"], 0);
         let d = unwrap_database_channeldata_read_input_all(to_database.recv().await?);
         assert_eq!(d.soc, 1);
         assert_eq!(d.v_pv_1, 25.7);
@@ -169,6 +172,238 @@ async fn handles_read_input_all() {
     futures::try_join!(coordinator.start(), tf).unwrap();
 }
 
+#[tokio::test]
+#[cfg_attr(not(feature = "mocks"), ignore)]
+async fn handles_read_input_all2() {
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+    config.influx_mut().enabled = true;
+    config.databases_mut()[0].enabled = true;
+    let inverter = config.inverters()[0].clone();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let tf = async {
+        let mut to_influx = channels.to_influx.subscribe();
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+        let mut to_database = channels.to_database.subscribe();
+
+        // ReadInputAll first, which waits for the ReadInputAll2 that
+        // completes inputs/all
+        for register in [0, 127] {
+            let packet = Packet::TranslatedData(lxp::packet::TranslatedData {
+                datalog: inverter.datalog(),
+                device_function: lxp::packet::DeviceFunction::ReadInput,
+                inverter: inverter.serial(),
+                register,
+                values: vec![1; 254],
+            });
+            channels
+                .from_inverter
+                .send(lxp::inverter::ChannelData::Packet(packet))?;
+        }
+
+        // published once, complete
+        let topic = format!("{}/inputs/all", inverter.datalog());
+        let d = loop {
+            if let mqtt::ChannelData::Message(message) = to_mqtt.recv().await? {
+                if message.topic == topic {
+                    break serde_json::from_str::<serde_json::Value>(&message.payload)?;
+                }
+            }
+        };
+        assert_eq!(d["soc"], 1);
+        assert_eq!(d["v_eps_l1"], 25.7);
+        assert_eq!(d["e_inv_total"], 1684300.9);
+        assert_eq!(d["datalog"], "2222222222");
+
+        // ReadInputAll2 gets its own influx measurement and database table
+        let d = loop {
+            if let influx::ChannelData::Inputs2Data(d) = to_influx.recv().await? {
+                break d;
+            }
+        };
+        assert_eq!(d["v_eps_l1"], 25.7);
+        let d = loop {
+            if let database::ChannelData::ReadInputAll2(d) = to_database.recv().await? {
+                break d;
+            }
+        };
+        assert_eq!(d.v_eps_l1, 25.7);
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn publishes_inputs_all_without_read_input_all2() {
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+    config.influx_mut().enabled = false;
+    config.databases_mut()[0].enabled = false;
+    let inverter = config.inverters()[0].clone();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let tf = async {
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        let packet = Packet::TranslatedData(lxp::packet::TranslatedData {
+            datalog: inverter.datalog(),
+            device_function: lxp::packet::DeviceFunction::ReadInput,
+            inverter: inverter.serial(),
+            register: 0,
+            values: vec![1; 254],
+        });
+        let started = tokio::time::Instant::now();
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet))?;
+
+        // after a short wait for a ReadInputAll2 which never comes
+        let topic = format!("{}/inputs/all", inverter.datalog());
+        let d = loop {
+            if let mqtt::ChannelData::Message(message) = to_mqtt.recv().await? {
+                if message.topic == topic {
+                    break serde_json::from_str::<serde_json::Value>(&message.payload)?;
+                }
+            }
+        };
+        assert!(started.elapsed() >= std::time::Duration::from_secs(5));
+        assert_eq!(d["soc"], 1);
+        assert_eq!(d.get("v_eps_l1"), None);
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn saves_energy_counters_on_a_timer_and_at_shutdown() {
+    common_setup();
+
+    let file = std::env::temp_dir().join("lxp-bridge-test-coordinator-energy.json");
+    let file = file.to_str().unwrap().to_owned();
+    let _ = std::fs::remove_file(&file);
+
+    let config = Factory::example_config_wrapped();
+    config.influx_mut().enabled = false;
+    config.databases_mut()[0].enabled = false;
+    config.set_energy_state_file(Some(file.clone()));
+    let inverter = config.inverters()[0].clone();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let tf = async {
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        let packet = Packet::TranslatedData(lxp::packet::TranslatedData {
+            datalog: inverter.datalog(),
+            device_function: lxp::packet::DeviceFunction::ReadInput,
+            inverter: inverter.serial(),
+            register: 0,
+            values: vec![1; 254],
+        });
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet))?;
+
+        let topic = format!("{}/inputs/all", inverter.datalog());
+        loop {
+            if let mqtt::ChannelData::Message(message) = to_mqtt.recv().await? {
+                if message.topic == topic {
+                    break;
+                }
+            }
+        }
+        // not written for every reading
+        assert!(!std::path::Path::new(&file).exists());
+
+        tokio::time::sleep(std::time::Duration::from_secs(300)).await;
+        assert!(std::path::Path::new(&file).exists());
+        std::fs::remove_file(&file)?;
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+
+    let saved = std::fs::read_to_string(&file).unwrap();
+    std::fs::remove_file(&file).unwrap();
+    assert!(saved.contains(&inverter.datalog().to_string()));
+}
+
+#[tokio::test(start_paused = true)]
+async fn derived_inputs_follow_publish_individual_input() {
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+    config.influx_mut().enabled = false;
+    config.databases_mut()[0].enabled = false;
+    config.mqtt_mut().publish_individual_input = Some(false);
+    let inverter = config.inverters()[0].clone();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let tf = async {
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        let packet = Packet::TranslatedData(lxp::packet::TranslatedData {
+            datalog: inverter.datalog(),
+            device_function: lxp::packet::DeviceFunction::ReadInput,
+            inverter: inverter.serial(),
+            register: 0,
+            values: vec![1; 254],
+        });
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet))?;
+
+        // inputs/all comes last, after waiting for a ReadInputAll2
+        let inputs_all = format!("{}/inputs/all", inverter.datalog());
+        let mut topics = Vec::new();
+        loop {
+            if let mqtt::ChannelData::Message(message) = to_mqtt.recv().await? {
+                if message.topic == inputs_all {
+                    break;
+                }
+                topics.push(message.topic);
+            }
+        }
+
+        // derived values are still in inputs/all, but not published singly
+        for key in ["e_pv_total", "e_pv_interval", "p_consumption"] {
+            let topic = format!("{}/input/{}/parsed", inverter.datalog(), key);
+            assert!(!topics.contains(&topic), "{} was published", topic);
+        }
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
 #[tokio::test]
 async fn complete_path_read_hold_command() {
     common_setup();
@@ -248,3 +483,1222 @@ async fn complete_path_read_hold_command() {
 
     futures::try_join!(coordinator.start(), tf).unwrap();
 }
+
+#[tokio::test]
+async fn complete_path_read_hold_command_with_envelope() {
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+
+    let inverter = config.inverters()[0].clone();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        let message = mqtt::Message {
+            topic: "cmd/all/read/hold/12".to_owned(),
+            retain: false,
+            payload: r#"{"id":"abc123","value":"1"}"#.to_owned(),
+        };
+        channels
+            .from_mqtt
+            .send(mqtt::ChannelData::Message(message))
+            .unwrap();
+
+        to_inverter.recv().await?;
+
+        let reply = Packet::TranslatedData(lxp::packet::TranslatedData {
+            datalog: inverter.datalog(),
+            device_function: lxp::packet::DeviceFunction::ReadHold,
+            inverter: inverter.serial(),
+            register: 12,
+            values: vec![22, 6],
+        });
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(reply))
+            .unwrap();
+
+        let topic = "result/2222222222/read/hold/12";
+        let payload = loop {
+            if let mqtt::ChannelData::Message(message) = to_mqtt.recv().await? {
+                if message.topic == topic {
+                    break message.payload;
+                }
+            }
+        };
+        let result: serde_json::Value = serde_json::from_str(&payload)?;
+        assert_eq!(result["id"], "abc123");
+        assert_eq!(result["status"], "OK");
+        assert_eq!(result["error"], serde_json::Value::Null);
+        assert_eq!(result["value"], 1558);
+        assert!(result["duration_ms"].is_u64());
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn replies_to_bad_command_with_envelope() {
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let tf = async {
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        let message = mqtt::Message {
+            topic: "cmd/2222222222/set/hold/nope".to_owned(),
+            retain: false,
+            payload: r#"{"id":"abc123","value":"1"}"#.to_owned(),
+        };
+        channels
+            .from_mqtt
+            .send(mqtt::ChannelData::Message(message))
+            .unwrap();
+
+        let message = match to_mqtt.recv().await? {
+            mqtt::ChannelData::Message(message) => message,
+            other => bail!("unexpected {:?}", other),
+        };
+        assert_eq!(message.topic, "result/2222222222/set/hold/nope");
+        let result: serde_json::Value = serde_json::from_str(&message.payload)?;
+        assert_eq!(result["id"], "abc123");
+        assert_eq!(result["status"], "FAIL");
+        assert_eq!(result["error"], "invalid digit found in string");
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn refuses_envelope_without_value() {
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        // would otherwise turn AC charge off
+        let message = mqtt::Message {
+            topic: "cmd/2222222222/set/ac_charge".to_owned(),
+            retain: false,
+            payload: r#"{"id":"abc123"}"#.to_owned(),
+        };
+        channels
+            .from_mqtt
+            .send(mqtt::ChannelData::Message(message))
+            .unwrap();
+
+        let message = match to_mqtt.recv().await? {
+            mqtt::ChannelData::Message(message) => message,
+            other => bail!("unexpected {:?}", other),
+        };
+        assert_eq!(message.topic, "result/2222222222/set/ac_charge");
+        let result: serde_json::Value = serde_json::from_str(&message.payload)?;
+        assert_eq!(result["id"], "abc123");
+        assert_eq!(result["status"], "FAIL");
+        assert_eq!(result["error"], "missing value");
+        assert_eq!(to_inverter.try_recv(), Err(TryRecvError::Empty));
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn rejects_protected_register_write() {
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+    let mut inverters = config.inverters().clone();
+    inverters[0].protected_registers = Some(vec![11]);
+    config.set_inverters(inverters);
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        let message = mqtt::Message {
+            topic: "cmd/2222222222/set/hold/11".to_owned(),
+            retain: false,
+            payload: "128".to_owned(),
+        };
+        channels
+            .from_mqtt
+            .send(mqtt::ChannelData::Message(message))
+            .unwrap();
+
+        assert_eq!(
+            to_mqtt.recv().await?,
+            mqtt::ChannelData::Message(mqtt::Message {
+                topic: "result/2222222222/set/hold/11".to_owned(),
+                retain: false,
+                payload: "FAIL: write rejected: register 11 is not writable on 2222222222"
+                    .to_owned()
+            })
+        );
+        // nothing was sent to the inverter
+        assert_eq!(to_inverter.try_recv(), Err(TryRecvError::Empty));
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn refresh_publishes_changed_holdings() {
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+    config.influx_mut().enabled = false;
+    config.databases_mut()[0].enabled = false;
+    let mut inverters = config.inverters().clone();
+    inverters[0].refresh_holdings_interval = Some(1);
+    config.set_inverters(inverters);
+
+    let inverter = &config.inverters()[0].clone();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        let reply = |values: Vec<u8>| {
+            lxp::inverter::ChannelData::Packet(Packet::TranslatedData(
+                lxp::packet::TranslatedData {
+                    datalog: inverter.datalog(),
+                    device_function: lxp::packet::DeviceFunction::ReadHold,
+                    inverter: inverter.serial(),
+                    register: 0,
+                    values,
+                },
+            ))
+        };
+
+        // somebody else reads registers 0 and 1, which are both published
+        channels.from_inverter.send(reply(vec![1, 0, 2, 0]))?;
+        to_mqtt.recv().await?;
+        to_mqtt.recv().await?;
+
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Connected(inverter.datalog()))?;
+
+        // a second later, the first block is refreshed
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            Packet::TranslatedData(lxp::packet::TranslatedData {
+                datalog: inverter.datalog(),
+                device_function: lxp::packet::DeviceFunction::ReadHold,
+                inverter: inverter.serial(),
+                register: 0,
+                values: vec![40, 0],
+            })
+        );
+        channels.from_inverter.send(reply(vec![1, 0, 5, 0]))?;
+
+        // only register 1 changed
+        assert_eq!(
+            to_mqtt.recv().await?,
+            mqtt::ChannelData::Message(mqtt::Message {
+                topic: "2222222222/hold/1".to_owned(),
+                retain: true,
+                payload: "5.0".to_owned()
+            })
+        );
+
+        let event = match to_mqtt.recv().await? {
+            mqtt::ChannelData::Message(message) => {
+                assert_eq!(message.topic, "2222222222/events/setting_changed");
+                serde_json::from_str::<serde_json::Value>(&message.payload)?
+            }
+            _ => panic!("expected a message"),
+        };
+        assert_eq!(event["register"], 1);
+        assert_eq!(event["old"], 2.0);
+        assert_eq!(event["new"], 5.0);
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn applies_preset() {
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+    config.influx_mut().enabled = false;
+    config.databases_mut()[0].enabled = false;
+    config.set_presets(serde_yaml::from_str("storm:\n  AcChargeSocLimit: 100\n").unwrap());
+
+    let inverter = &config.inverters()[0].clone();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        let message = mqtt::Message {
+            topic: "cmd/2222222222/set/preset/storm".to_owned(),
+            retain: false,
+            payload: "".to_owned(),
+        };
+        channels
+            .from_mqtt
+            .send(mqtt::ChannelData::Message(message))?;
+
+        // the original value is read first, in case it needs putting back
+        let read = Packet::TranslatedData(lxp::packet::TranslatedData {
+            datalog: inverter.datalog(),
+            device_function: lxp::packet::DeviceFunction::ReadHold,
+            inverter: inverter.serial(),
+            register: 67,
+            values: vec![1, 0],
+        });
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            read
+        );
+        let reply = Packet::TranslatedData(lxp::packet::TranslatedData {
+            datalog: inverter.datalog(),
+            device_function: lxp::packet::DeviceFunction::ReadHold,
+            inverter: inverter.serial(),
+            register: 67,
+            values: vec![50, 0],
+        });
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(reply))?;
+
+        let write = Packet::TranslatedData(lxp::packet::TranslatedData {
+            datalog: inverter.datalog(),
+            device_function: lxp::packet::DeviceFunction::WriteSingle,
+            inverter: inverter.serial(),
+            register: 67,
+            values: vec![100, 0],
+        });
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            write
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(write))?;
+
+        // the order these arrive in depends on which task gets there first
+        let mut messages = Vec::new();
+        for _ in 0..4 {
+            if let mqtt::ChannelData::Message(message) = to_mqtt.recv().await? {
+                messages.push(message);
+            }
+        }
+
+        assert!(messages.contains(&mqtt::Message {
+            topic: "result/2222222222/set/preset/storm".to_owned(),
+            retain: false,
+            payload: "OK".to_owned()
+        }));
+        assert!(messages.contains(&mqtt::Message {
+            topic: "2222222222/preset".to_owned(),
+            retain: true,
+            payload: r#"{"matches":true,"name":"storm"}"#.to_owned()
+        }));
+        assert!(messages.contains(&mqtt::Message {
+            topic: "2222222222/hold/67".to_owned(),
+            retain: true,
+            payload: "50.0".to_owned()
+        }));
+        assert!(messages.contains(&mqtt::Message {
+            topic: "2222222222/hold/67".to_owned(),
+            retain: true,
+            payload: "100.0".to_owned()
+        }));
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn preset_reads_originals_despite_cache() {
+    use lxp::packet::DeviceFunction::{self, *};
+
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+    config.influx_mut().enabled = false;
+    config.databases_mut()[0].enabled = false;
+    config.set_presets(serde_yaml::from_str("storm:\n  AcChargeSocLimit: 100\n").unwrap());
+
+    let inverter = &config.inverters()[0].clone();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let packet = |device_function: DeviceFunction, value: u16| {
+        Packet::TranslatedData(lxp::packet::TranslatedData {
+            datalog: inverter.datalog(),
+            device_function,
+            inverter: inverter.serial(),
+            register: 67,
+            values: vec![value as u8, 0],
+        })
+    };
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        // cached just now, but may have been changed on the inverter since
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet(ReadHold, 90)))?;
+        assert_eq!(
+            result_payload(&mut to_mqtt, "2222222222/hold/67").await?,
+            "90.0"
+        );
+
+        let message = mqtt::Message {
+            topic: "cmd/2222222222/set/preset/storm".to_owned(),
+            retain: false,
+            payload: "".to_owned(),
+        };
+        channels
+            .from_mqtt
+            .send(mqtt::ChannelData::Message(message))?;
+
+        for (sent, reply) in [
+            (packet(ReadHold, 1), packet(ReadHold, 50)),
+            (packet(WriteSingle, 100), packet(WriteSingle, 100)),
+        ] {
+            assert_eq!(
+                unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+                sent
+            );
+            channels
+                .from_inverter
+                .send(lxp::inverter::ChannelData::Packet(reply))?;
+        }
+
+        assert_eq!(
+            result_payload(&mut to_mqtt, "result/2222222222/set/preset/storm").await?,
+            "OK"
+        );
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn failed_preset_is_not_active() {
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+    config.influx_mut().enabled = false;
+    config.databases_mut()[0].enabled = false;
+    config.set_presets(serde_yaml::from_str("storm:\n  AcChargeSocLimit: 100\n").unwrap());
+
+    let inverter = &config.inverters()[0].clone();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let packet = |device_function: lxp::packet::DeviceFunction, value: u16| {
+        Packet::TranslatedData(lxp::packet::TranslatedData {
+            datalog: inverter.datalog(),
+            device_function,
+            inverter: inverter.serial(),
+            register: 67,
+            values: vec![value, 0],
+        })
+    };
+
+    let tf = async {
+        use lxp::packet::DeviceFunction::*;
+
+        let mut to_inverter = channels.to_inverter.subscribe();
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        let message = mqtt::Message {
+            topic: "cmd/2222222222/set/preset/storm".to_owned(),
+            retain: false,
+            payload: "".to_owned(),
+        };
+        channels
+            .from_mqtt
+            .send(mqtt::ChannelData::Message(message))?;
+
+        // read 50, write 100 but 60 comes back, so 50 is put back
+        for (sent, reply) in [
+            (packet(ReadHold, 1), packet(ReadHold, 50)),
+            (packet(WriteSingle, 100), packet(WriteSingle, 60)),
+            (packet(WriteSingle, 50), packet(WriteSingle, 50)),
+        ] {
+            assert_eq!(
+                unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+                sent
+            );
+            channels
+                .from_inverter
+                .send(lxp::inverter::ChannelData::Packet(reply))?;
+        }
+
+        loop {
+            if let mqtt::ChannelData::Message(message) = to_mqtt.recv().await? {
+                assert_ne!(message.topic, "2222222222/preset");
+                if message.topic == "result/2222222222/set/preset/storm" {
+                    assert_eq!(message.payload, "FAIL");
+                    break;
+                }
+            }
+        }
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn schedule_reads_registers_afresh() {
+    use lxp::packet::DeviceFunction::{self, *};
+
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+    config.influx_mut().enabled = false;
+    config.databases_mut()[0].enabled = false;
+
+    let inverter = &config.inverters()[0].clone();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let packet = |device_function: DeviceFunction, register: u16, values: Vec<u8>| {
+        Packet::TranslatedData(lxp::packet::TranslatedData {
+            datalog: inverter.datalog(),
+            device_function,
+            inverter: inverter.serial(),
+            register,
+            values,
+        })
+    };
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        // AC charge was on when cached, but has been switched off since
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet(
+                ReadHold,
+                21,
+                vec![128, 0],
+            )))?;
+        assert_eq!(
+            result_payload(&mut to_mqtt, "2222222222/hold/21").await?,
+            "128.0"
+        );
+
+        let message = mqtt::Message {
+            topic: "cmd/2222222222/set/schedule".to_owned(),
+            retain: false,
+            payload: r#"{"ac_charge":{"enabled":true}}"#.to_owned(),
+        };
+        channels
+            .from_mqtt
+            .send(mqtt::ChannelData::Message(message))?;
+
+        for (register, count) in [(21, 1), (66, 24), (152, 6)] {
+            assert_eq!(
+                unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+                packet(ReadHold, register, vec![count, 0])
+            );
+            channels
+                .from_inverter
+                .send(lxp::inverter::ChannelData::Packet(packet(
+                    ReadHold,
+                    register,
+                    vec![0; count as usize * 2],
+                )))?;
+        }
+
+        // so the bit is written, after the transaction reads its original
+        for (sent, reply) in [
+            (
+                packet(ReadHold, 21, vec![1, 0]),
+                packet(ReadHold, 21, vec![0, 0]),
+            ),
+            (
+                packet(WriteSingle, 21, vec![128, 0]),
+                packet(WriteSingle, 21, vec![128, 0]),
+            ),
+        ] {
+            assert_eq!(
+                unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+                sent
+            );
+            channels
+                .from_inverter
+                .send(lxp::inverter::ChannelData::Packet(reply))?;
+        }
+
+        assert_eq!(
+            result_payload(&mut to_mqtt, "result/2222222222/set/schedule").await?,
+            "OK"
+        );
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn replies_to_preset_on_command_topic() {
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let tf = async {
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        // the name in the payload, as a HA select sends it
+        let message = mqtt::Message {
+            topic: "cmd/2222222222/set/preset".to_owned(),
+            retain: false,
+            payload: "nonexistent".to_owned(),
+        };
+        channels
+            .from_mqtt
+            .send(mqtt::ChannelData::Message(message))?;
+
+        assert_eq!(
+            result_payload(&mut to_mqtt, "result/2222222222/set/preset").await?,
+            "FAIL"
+        );
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
+// skips anything else published on the way
+async fn result_payload(
+    to_mqtt: &mut broadcast::Receiver<mqtt::ChannelData>,
+    topic: &str,
+) -> Result<String> {
+    loop {
+        if let mqtt::ChannelData::Message(message) = to_mqtt.recv().await? {
+            if message.topic == topic {
+                return Ok(message.payload);
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn coalesces_and_skips_writes() {
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+    config.influx_mut().enabled = false;
+    config.databases_mut()[0].enabled = false;
+    let mut inverters = config.inverters().clone();
+    inverters[0].write_interval = Some(60);
+    config.set_inverters(inverters);
+
+    let inverter = &config.inverters()[0].clone();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        let send = |payload: &str| {
+            channels
+                .from_mqtt
+                .send(mqtt::ChannelData::Message(mqtt::Message {
+                    topic: "cmd/2222222222/set/charge_rate_pct".to_owned(),
+                    retain: false,
+                    payload: payload.to_owned(),
+                }))
+        };
+        let topic = "result/2222222222/set/charge_rate_pct";
+
+        send("50")?;
+        let write = Packet::TranslatedData(lxp::packet::TranslatedData {
+            datalog: inverter.datalog(),
+            device_function: lxp::packet::DeviceFunction::WriteSingle,
+            inverter: inverter.serial(),
+            register: 64,
+            values: vec![50, 0],
+        });
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            write
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(write))?;
+        assert_eq!(result_payload(&mut to_mqtt, topic).await?, "OK");
+
+        // too soon after the last write, so held back
+        send("60")?;
+        assert_eq!(
+            result_payload(&mut to_mqtt, topic).await?,
+            "OK: coalesced, register 64 will be set to 60 in 60s"
+        );
+
+        // back to what the register already holds, which also drops the
+        // pending write
+        send("50")?;
+        assert_eq!(
+            result_payload(&mut to_mqtt, topic).await?,
+            "OK: unchanged, register 64 already 50"
+        );
+
+        // nothing more was sent to the inverter
+        assert_eq!(to_inverter.try_recv(), Err(TryRecvError::Empty));
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
+// time is paused, so the held back write is due as soon as nothing else is
+// going on
+#[tokio::test(start_paused = true)]
+async fn replies_when_coalesced_write_is_made() {
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+    config.influx_mut().enabled = false;
+    config.databases_mut()[0].enabled = false;
+    let mut inverters = config.inverters().clone();
+    inverters[0].write_interval = Some(60);
+    config.set_inverters(inverters);
+
+    let inverter = &config.inverters()[0].clone();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        let send = |payload: &str| {
+            channels
+                .from_mqtt
+                .send(mqtt::ChannelData::Message(mqtt::Message {
+                    topic: "cmd/2222222222/set/charge_rate_pct".to_owned(),
+                    retain: false,
+                    payload: payload.to_owned(),
+                }))
+        };
+        let topic = "result/2222222222/set/charge_rate_pct";
+        let write = |value: u8| {
+            Packet::TranslatedData(lxp::packet::TranslatedData {
+                datalog: inverter.datalog(),
+                device_function: lxp::packet::DeviceFunction::WriteSingle,
+                inverter: inverter.serial(),
+                register: 64,
+                values: vec![value, 0],
+            })
+        };
+
+        send("50")?;
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            write(50)
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(write(50)))?;
+        assert_eq!(result_payload(&mut to_mqtt, topic).await?, "OK");
+
+        send(r#"{"id":"abc123","value":"60"}"#)?;
+        let result: serde_json::Value =
+            serde_json::from_str(&result_payload(&mut to_mqtt, topic).await?)?;
+        assert_eq!(result["id"], "abc123");
+        assert_eq!(
+            result["note"],
+            "coalesced, register 64 will be set to 60 in 60s"
+        );
+
+        send("70")?;
+        assert_eq!(
+            result_payload(&mut to_mqtt, topic).await?,
+            "OK: coalesced, register 64 will be set to 70 in 60s instead of 60"
+        );
+
+        // only the last value is written, and both callers hear about it
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            write(70)
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(write(70)))?;
+
+        let result: serde_json::Value =
+            serde_json::from_str(&result_payload(&mut to_mqtt, topic).await?)?;
+        assert_eq!(result["id"], "abc123");
+        assert_eq!(result["status"], "OK");
+        assert_eq!(result["value"], 70);
+        assert_eq!(result["note"], "coalesced, register 64 set to 70");
+        assert_eq!(
+            result_payload(&mut to_mqtt, topic).await?,
+            "OK: coalesced, register 64 set to 70"
+        );
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
+// as scheduler jobs and automation rules do, which work without MQTT
+#[tokio::test(start_paused = true)]
+async fn makes_coalesced_write_without_mqtt() {
+    use lxp::packet::DeviceFunction::{self, *};
+
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+    config.influx_mut().enabled = false;
+    config.databases_mut()[0].enabled = false;
+    config.mqtt_mut().enabled = false;
+    let mut inverters = config.inverters().clone();
+    inverters[0].write_interval = Some(60);
+    config.set_inverters(inverters);
+
+    let inverter = &config.inverters()[0].clone();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+
+        let send = |topic: &str, payload: &str| {
+            channels
+                .from_mqtt
+                .send(mqtt::ChannelData::Message(mqtt::Message {
+                    topic: format!("cmd/2222222222/{}", topic),
+                    retain: false,
+                    payload: payload.to_owned(),
+                }))
+        };
+        let packet = |device_function: DeviceFunction, register: u16, value: u16| {
+            Packet::TranslatedData(lxp::packet::TranslatedData {
+                datalog: inverter.datalog(),
+                device_function,
+                inverter: inverter.serial(),
+                register,
+                values: vec![value as u8, (value >> 8) as u8],
+            })
+        };
+
+        send("set/charge_rate_pct", "50")?;
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            packet(WriteSingle, 64, 50)
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet(
+                WriteSingle,
+                64,
+                50,
+            )))?;
+
+        // held back, then written with nobody to tell
+        send("set/charge_rate_pct", "60")?;
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            packet(WriteSingle, 64, 60)
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet(
+                WriteSingle,
+                64,
+                60,
+            )))?;
+
+        // and the coordinator is still going
+        send("read/hold/21", "")?;
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            packet(ReadHold, 21, 1)
+        );
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn limits_bit_and_transaction_writes() {
+    use lxp::packet::{DeviceFunction, TranslatedData};
+
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+    config.influx_mut().enabled = false;
+    config.databases_mut()[0].enabled = false;
+    let mut inverters = config.inverters().clone();
+    inverters[0].write_interval = Some(60);
+    config.set_inverters(inverters);
+
+    let inverter = &config.inverters()[0].clone();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        let send = |topic: &str, payload: &str| {
+            channels
+                .from_mqtt
+                .send(mqtt::ChannelData::Message(mqtt::Message {
+                    topic: format!("cmd/2222222222/{}", topic),
+                    retain: false,
+                    payload: payload.to_owned(),
+                }))
+        };
+        let packet = |device_function, register, values| {
+            Packet::TranslatedData(TranslatedData {
+                datalog: inverter.datalog(),
+                device_function,
+                inverter: inverter.serial(),
+                register,
+                values,
+            })
+        };
+        let topic = "result/2222222222/set/ac_charge";
+
+        // register 21 is read, then written with the bit set
+        send("set/ac_charge", "true")?;
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            packet(DeviceFunction::ReadHold, 21, vec![1, 0])
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet(
+                DeviceFunction::ReadHold,
+                21,
+                vec![0, 0],
+            )))?;
+        let write = packet(DeviceFunction::WriteSingle, 21, vec![128, 0]);
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            write
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(write))?;
+        assert_eq!(result_payload(&mut to_mqtt, topic).await?, "OK");
+
+        // bits are held back like any other write, though the register is
+        // still read first in case it has been changed elsewhere
+        send("set/ac_charge", "false")?;
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            packet(DeviceFunction::ReadHold, 21, vec![1, 0])
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet(
+                DeviceFunction::ReadHold,
+                21,
+                vec![128, 0],
+            )))?;
+        assert_eq!(
+            result_payload(&mut to_mqtt, topic).await?,
+            "OK: coalesced, register 21 will be set to 0 in 60s"
+        );
+
+        // and putting the bit back drops the pending write, which the first
+        // caller is told about too
+        send("set/ac_charge", "true")?;
+        for _ in 0..2 {
+            assert_eq!(
+                result_payload(&mut to_mqtt, topic).await?,
+                "OK: unchanged, register 21 already 128"
+            );
+        }
+
+        // a transaction can't be held back in part, so it's refused
+        send("set/hold/68", "1")?;
+        let write = packet(DeviceFunction::WriteSingle, 68, vec![1, 0]);
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            write
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(write))?;
+        assert_eq!(
+            result_payload(&mut to_mqtt, "result/2222222222/set/hold/68").await?,
+            "OK"
+        );
+
+        send("set/ac_charge/1", r#"{"start":"01:00","end":"02:00"}"#)?;
+        assert_eq!(
+            result_payload(&mut to_mqtt, "result/2222222222/set/ac_charge/1").await?,
+            "FAIL: write rejected: register 68 was written less than 60s ago, try again in 60s"
+        );
+
+        // nothing more was sent to the inverter
+        assert_eq!(to_inverter.try_recv(), Err(TryRecvError::Empty));
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn answers_response_topic_once() {
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+    config.influx_mut().enabled = false;
+    config.databases_mut()[0].enabled = false;
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let tf = async {
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        let message = |topic: &str| mqtt::Message {
+            topic: topic.to_owned(),
+            retain: false,
+            payload: "1".to_owned(),
+        };
+        let reply = mqtt::Reply {
+            response_topic: "replies/me".to_owned(),
+            correlation_data: Some(bytes::Bytes::from_static(b"42")),
+        };
+
+        // the same command, once without a response topic and once with
+        channels.from_mqtt.send(mqtt::ChannelData::Message(message(
+            "cmd/2222222222/set/hold/11",
+        )))?;
+        channels.from_mqtt.send(mqtt::ChannelData::WithReply(
+            message("cmd/2222222222/set/hold/11"),
+            reply.clone(),
+        ))?;
+        // and one which doesn't parse
+        channels.from_mqtt.send(mqtt::ChannelData::WithReply(
+            message("cmd/2222222222/set/hold/nope"),
+            reply.clone(),
+        ))?;
+
+        let failed = "FAIL: write rejected: register 11 is not writable on 2222222222";
+        assert_eq!(
+            to_mqtt.recv().await?,
+            mqtt::ChannelData::Message(mqtt::Message {
+                topic: "result/2222222222/set/hold/11".to_owned(),
+                retain: false,
+                payload: failed.to_owned(),
+            })
+        );
+        assert_eq!(
+            to_mqtt.recv().await?,
+            mqtt::ChannelData::WithReply(
+                mqtt::Message {
+                    topic: "result/2222222222/set/hold/11".to_owned(),
+                    retain: false,
+                    payload: failed.to_owned(),
+                },
+                reply.clone()
+            )
+        );
+        assert_eq!(
+            to_mqtt.recv().await?,
+            mqtt::ChannelData::WithReply(
+                mqtt::Message {
+                    topic: "result/2222222222/set/hold/nope".to_owned(),
+                    retain: false,
+                    payload: "FAIL".to_owned(),
+                },
+                reply
+            )
+        );
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn reads_batch() {
+    use lxp::packet::{DeviceFunction, TranslatedData};
+
+    common_setup();
+
+    let config = Factory::example_config_wrapped();
+    config.influx_mut().enabled = false;
+    config.databases_mut()[0].enabled = false;
+
+    let inverter = &config.inverters()[0].clone();
+
+    let channels = Channels::new();
+
+    let coordinator = Coordinator::new(config, channels.clone());
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+        let mut to_mqtt = channels.to_mqtt.subscribe();
+
+        channels
+            .from_mqtt
+            .send(mqtt::ChannelData::Message(mqtt::Message {
+                topic: "cmd/2222222222/read/batch".to_owned(),
+                retain: false,
+                payload:
+                    r#"[{"space":"hold","register":0,"count":42},{"space":"input","register":5}]"#
+                        .to_owned(),
+            }))?;
+
+        // each register holds its own number
+        for (device_function, register, count) in [
+            (DeviceFunction::ReadHold, 0, 40),
+            (DeviceFunction::ReadHold, 40, 2),
+            (DeviceFunction::ReadInput, 5, 1),
+        ] {
+            let packet = unwrap_inverter_channeldata_packet(to_inverter.recv().await?);
+            assert_eq!(
+                packet,
+                Packet::TranslatedData(TranslatedData {
+                    datalog: inverter.datalog(),
+                    device_function,
+                    inverter: inverter.serial(),
+                    register,
+                    values: vec![count, 0],
+                })
+            );
+
+            let reply = Packet::TranslatedData(TranslatedData {
+                datalog: inverter.datalog(),
+                device_function,
+                inverter: inverter.serial(),
+                register,
+                values: (register..register + count as u16)
+                    .flat_map(|r| r.to_le_bytes())
+                    .collect(),
+            });
+            channels
+                .from_inverter
+                .send(lxp::inverter::ChannelData::Packet(reply))?;
+        }
+
+        let payload = result_payload(&mut to_mqtt, "result/2222222222/read/batch").await?;
+        let hold: serde_json::Map<String, serde_json::Value> =
+            (0..42).map(|r: u16| (r.to_string(), r.into())).collect();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&payload)?,
+            json!({"hold": hold, "input": {"5": 5}})
+        );
+
+        coordinator.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(coordinator.start(), tf).unwrap();
+}
@@ -0,0 +1,91 @@
+mod common;
+use common::*;
+
+use lxp_bridge::coordinator::alarms::{AlarmEvent, AlarmKind, AlarmState, AlarmStore};
+
+fn event(kind: AlarmKind, state: AlarmState, bit: u8, code: &'static str) -> AlarmEvent {
+    let description = match code {
+        "E000" => "Internal communication fault 1",
+        "E002" => "BatOnMosFail",
+        "E003" => "CT Fail",
+        "W016" => "Grid power outage",
+        _ => unreachable!(),
+    };
+
+    AlarmEvent {
+        datalog: Serial::from_str("2222222222").unwrap(),
+        kind,
+        state,
+        bit,
+        code,
+        description,
+        time: UnixTime::now(),
+    }
+}
+
+#[test]
+fn fault_code_active_returns_all_bits() {
+    common_setup();
+
+    let active = lxp::packet::FaultCodeString::active(0b1101);
+    let codes: Vec<&str> = active.iter().map(|c| c.code).collect();
+    assert_eq!(codes, vec!["E000", "E002", "E003"]);
+    assert_eq!(active[2].bit, 3);
+    assert_eq!(active[2].description, "CT Fail");
+
+    assert!(lxp::packet::WarningCodeString::active(0).is_empty());
+}
+
+#[test]
+#[cfg_attr(not(feature = "mocks"), ignore)]
+fn first_update_raises_active_codes() {
+    common_setup();
+
+    let datalog = Serial::from_str("2222222222").unwrap();
+    let mut store = AlarmStore::new();
+
+    assert_eq!(
+        store.update(datalog, 0b101, 1 << 16, &UnixTime::now()),
+        vec![
+            event(AlarmKind::Fault, AlarmState::Raised, 0, "E000"),
+            event(AlarmKind::Fault, AlarmState::Raised, 2, "E002"),
+            event(AlarmKind::Warning, AlarmState::Raised, 16, "W016"),
+        ]
+    );
+
+    // nothing changed, nothing to report
+    assert!(store
+        .update(datalog, 0b101, 1 << 16, &UnixTime::now())
+        .is_empty());
+}
+
+#[test]
+#[cfg_attr(not(feature = "mocks"), ignore)]
+fn update_raises_and_clears_changed_codes() {
+    common_setup();
+
+    let datalog = Serial::from_str("2222222222").unwrap();
+    let mut store = AlarmStore::new();
+
+    store.update(datalog, 0b101, 1 << 16, &UnixTime::now());
+
+    assert_eq!(
+        store.update(datalog, 0b1100, 0, &UnixTime::now()),
+        vec![
+            event(AlarmKind::Fault, AlarmState::Raised, 3, "E003"),
+            event(AlarmKind::Fault, AlarmState::Cleared, 0, "E000"),
+            event(AlarmKind::Warning, AlarmState::Cleared, 16, "W016"),
+        ]
+    );
+}
+
+#[test]
+#[cfg_attr(not(feature = "mocks"), ignore)]
+fn alarm_event_json() {
+    common_setup();
+
+    assert_eq!(
+        serde_json::to_string(&event(AlarmKind::Fault, AlarmState::Raised, 3, "E003")).unwrap(),
+        r#"{"datalog":"2222222222","kind":"fault","state":"raised","bit":3,"code":"E003","description":"CT Fail","time":1646370367}"#
+    );
+}
@@ -0,0 +1,51 @@
+mod common;
+use common::*;
+
+use lxp_bridge::coordinator::batch::{chunks, Range, Space};
+
+fn range(space: Space, register: u16, count: u16) -> Range {
+    Range {
+        space,
+        register,
+        count,
+    }
+}
+
+#[test]
+fn splits_into_chunks_of_40() {
+    common_setup();
+
+    assert_eq!(
+        chunks(&[range(Space::Hold, 0, 100), range(Space::Input, 120, 40)]),
+        vec![
+            range(Space::Hold, 0, 40),
+            range(Space::Hold, 40, 40),
+            range(Space::Hold, 80, 20),
+            range(Space::Input, 120, 40),
+        ]
+    );
+}
+
+#[test]
+fn params_read_one_at_a_time() {
+    common_setup();
+
+    assert_eq!(
+        chunks(&[range(Space::Param, 7, 3)]),
+        vec![
+            range(Space::Param, 7, 1),
+            range(Space::Param, 8, 1),
+            range(Space::Param, 9, 1),
+        ]
+    );
+}
+
+#[test]
+fn keeps_order_of_ranges() {
+    common_setup();
+
+    assert_eq!(
+        chunks(&[range(Space::Input, 0, 1), range(Space::Hold, 65535, 1)]),
+        vec![range(Space::Input, 0, 1), range(Space::Hold, 65535, 1)]
+    );
+}
@@ -118,3 +118,174 @@ async fn time_already_correct() {
 
     futures::try_join!(tf, sf).unwrap();
 }
+
+fn time_packet(
+    inverter: &config::Inverter,
+    device_function: lxp::packet::DeviceFunction,
+    values: Vec<u8>,
+) -> Packet {
+    Packet::TranslatedData(lxp::packet::TranslatedData {
+        datalog: inverter.datalog(),
+        device_function,
+        inverter: inverter.serial(),
+        register: 12,
+        values,
+    })
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "mocks"), ignore)]
+async fn corrects_for_timezone() {
+    use lxp::packet::DeviceFunction::*;
+
+    common_setup();
+
+    let inverter = config::Inverter {
+        timezone: Some(chrono_tz::Europe::Berlin),
+        ..Factory::inverter()
+    };
+    let channels = Channels::new();
+
+    let subject =
+        coordinator::commands::timesync::TimeSync::new(channels.clone(), inverter.clone());
+
+    let sf = async {
+        let clock = subject.run().await?;
+        assert_eq!(
+            clock,
+            coordinator::commands::timesync::Clock {
+                inverter_time: "2022-03-04T05:06:07".to_owned(),
+                expected_time: "2022-03-04T06:06:07".to_owned(),
+                timezone: "Europe/Berlin".to_owned(),
+                drift_seconds: -3600,
+                corrected: true,
+            }
+        );
+        Ok(())
+    };
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            time_packet(&inverter, ReadHold, vec![3, 0])
+        );
+        // an hour behind Berlin
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(time_packet(
+                &inverter,
+                ReadHold,
+                vec![22, 3, 4, 5, 6, 7],
+            )))?;
+
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            time_packet(&inverter, WriteMulti, vec![22, 3, 4, 6, 6, 7])
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(time_packet(
+                &inverter,
+                WriteMulti,
+                vec![3, 0],
+            )))?;
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(tf, sf).unwrap();
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "mocks"), ignore)]
+async fn drift_within_tolerance() {
+    use lxp::packet::DeviceFunction::*;
+
+    common_setup();
+
+    let inverter = config::Inverter {
+        time_tolerance: Some(300),
+        ..Factory::inverter()
+    };
+    let channels = Channels::new();
+
+    let subject =
+        coordinator::commands::timesync::TimeSync::new(channels.clone(), inverter.clone());
+
+    let sf = async {
+        let clock = subject.run().await?;
+        assert_eq!(clock.drift_seconds, 240);
+        assert!(!clock.corrected);
+        assert_eq!(clock.timezone, "local");
+        Ok(())
+    };
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            time_packet(&inverter, ReadHold, vec![3, 0])
+        );
+        // four minutes fast
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(time_packet(
+                &inverter,
+                ReadHold,
+                vec![22, 3, 4, 5, 10, 7],
+            )))?;
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(tf, sf).unwrap();
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "mocks"), ignore)]
+async fn set_time() {
+    use lxp::packet::DeviceFunction::*;
+
+    common_setup();
+
+    let inverter = Factory::inverter();
+    let channels = Channels::new();
+
+    let subject =
+        coordinator::commands::timesync::TimeSync::new(channels.clone(), inverter.clone());
+
+    let sf = async {
+        let time = chrono::NaiveDate::from_ymd_opt(2022, 3, 4)
+            .unwrap()
+            .and_hms_opt(5, 0, 0);
+        let clock = subject.set(time).await?;
+        assert_eq!(clock.inverter_time, "2022-03-04T05:00:00");
+        assert_eq!(clock.drift_seconds, -367);
+        assert!(clock.corrected);
+        Ok(())
+    };
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+
+        // no need to read it first
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            time_packet(&inverter, WriteMulti, vec![22, 3, 4, 5, 0, 0])
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(time_packet(
+                &inverter,
+                WriteMulti,
+                vec![3, 0],
+            )))?;
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(tf, sf).unwrap();
+}
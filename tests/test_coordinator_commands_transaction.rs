@@ -0,0 +1,310 @@
+mod common;
+use common::*;
+
+use lxp_bridge::coordinator::commands::transaction::{Transaction, TransactionFailed};
+
+fn packet(
+    inverter: &config::Inverter,
+    device_function: lxp::packet::DeviceFunction,
+    register: u16,
+    values: Vec<u8>,
+) -> Packet {
+    Packet::TranslatedData(lxp::packet::TranslatedData {
+        datalog: inverter.datalog(),
+        device_function,
+        inverter: inverter.serial(),
+        register,
+        values,
+    })
+}
+
+#[tokio::test]
+async fn writes_consecutive_registers_together() {
+    use lxp::packet::DeviceFunction::*;
+
+    common_setup();
+
+    let inverter = Factory::inverter();
+    let channels = Channels::new();
+
+    let subject = Transaction::new(channels.clone(), inverter.clone())
+        .set(69u16, 0x0403)
+        .set(68u16, 0x0201);
+
+    let sf = async {
+        let result = subject.run().await?;
+        assert_eq!(
+            result,
+            vec![packet(&inverter, ReadHold, 68, vec![1, 2, 3, 4])]
+        );
+
+        Ok(())
+    };
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+
+        // originals
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            packet(&inverter, ReadHold, 68, vec![2, 0])
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet(
+                &inverter,
+                ReadHold,
+                68,
+                vec![0, 0, 0, 0],
+            )))?;
+
+        // both registers in one write
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            packet(&inverter, WriteMulti, 68, vec![1, 2, 3, 4])
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet(
+                &inverter,
+                WriteMulti,
+                68,
+                vec![2, 0],
+            )))?;
+
+        // read back to check
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            packet(&inverter, ReadHold, 68, vec![2, 0])
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet(
+                &inverter,
+                ReadHold,
+                68,
+                vec![1, 2, 3, 4],
+            )))?;
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(tf, sf).unwrap();
+}
+
+#[tokio::test]
+async fn combines_bits_for_one_register() {
+    use lxp::packet::DeviceFunction::*;
+
+    common_setup();
+
+    let inverter = Factory::inverter();
+    let channels = Channels::new();
+
+    let subject = Transaction::new(channels.clone(), inverter.clone())
+        .update_bits(21u16, 1 << 7, 0)
+        .update_bits(21u16, 0, 1 << 1);
+
+    let sf = async {
+        let result = subject.run().await?;
+        assert_eq!(
+            result,
+            vec![packet(&inverter, WriteSingle, 21, vec![128, 0])]
+        );
+
+        Ok(())
+    };
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            packet(&inverter, ReadHold, 21, vec![1, 0])
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet(
+                &inverter,
+                ReadHold,
+                21,
+                vec![2, 0],
+            )))?;
+
+        // one write with both changes
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            packet(&inverter, WriteSingle, 21, vec![128, 0])
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet(
+                &inverter,
+                WriteSingle,
+                21,
+                vec![128, 0],
+            )))?;
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(tf, sf).unwrap();
+}
+
+#[tokio::test]
+async fn rolls_back_on_mismatch() {
+    use lxp::packet::DeviceFunction::*;
+
+    common_setup();
+
+    let inverter = Factory::inverter();
+    let channels = Channels::new();
+
+    let subject = Transaction::new(channels.clone(), inverter.clone())
+        .set(64u16, 50)
+        .set(66u16, 60);
+
+    let sf = async {
+        let err = subject.run().await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<TransactionFailed>(),
+            Some(&TransactionFailed {
+                error: "failed to update register 66, got back [20] (wanted [60])".to_owned(),
+                rollback: Ok(()),
+            })
+        );
+        assert_eq!(
+            err.to_string(),
+            "failed to update register 66, got back [20] (wanted [60]); rolled back"
+        );
+
+        Ok(())
+    };
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+
+        for (register, original) in [(64, 10), (66, 20)] {
+            assert_eq!(
+                unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+                packet(&inverter, ReadHold, register, vec![1, 0])
+            );
+            channels
+                .from_inverter
+                .send(lxp::inverter::ChannelData::Packet(packet(
+                    &inverter,
+                    ReadHold,
+                    register,
+                    vec![original, 0],
+                )))?;
+        }
+
+        // 64 takes, 66 doesn't
+        for (register, value, reply) in [(64, 50, 50), (66, 60, 20)] {
+            assert_eq!(
+                unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+                packet(&inverter, WriteSingle, register, vec![value, 0])
+            );
+            channels
+                .from_inverter
+                .send(lxp::inverter::ChannelData::Packet(packet(
+                    &inverter,
+                    WriteSingle,
+                    register,
+                    vec![reply, 0],
+                )))?;
+        }
+
+        // both put back, last first
+        for (register, original) in [(66, 20), (64, 10)] {
+            assert_eq!(
+                unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+                packet(&inverter, WriteSingle, register, vec![original, 0])
+            );
+            channels
+                .from_inverter
+                .send(lxp::inverter::ChannelData::Packet(packet(
+                    &inverter,
+                    WriteSingle,
+                    register,
+                    vec![original, 0],
+                )))?;
+        }
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(tf, sf).unwrap();
+}
+
+#[tokio::test]
+async fn reports_failed_rollback() {
+    use lxp::packet::DeviceFunction::*;
+
+    common_setup();
+
+    let inverter = Factory::inverter();
+    let channels = Channels::new();
+
+    let subject = Transaction::new(channels.clone(), inverter.clone()).set(64u16, 50);
+
+    let sf = async {
+        let err = subject.run().await.unwrap_err();
+        let failed = err.downcast_ref::<TransactionFailed>().unwrap();
+        assert!(failed.error.ends_with("- timeout"));
+        assert!(failed.rollback.is_err());
+        assert!(err.to_string().contains("; rollback failed: "));
+
+        Ok(())
+    };
+
+    let tf = async {
+        let mut to_inverter = channels.to_inverter.subscribe();
+
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            packet(&inverter, ReadHold, 64, vec![1, 0])
+        );
+        channels
+            .from_inverter
+            .send(lxp::inverter::ChannelData::Packet(packet(
+                &inverter,
+                ReadHold,
+                64,
+                vec![10, 0],
+            )))?;
+
+        // neither the write nor the rollback get a reply
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            packet(&inverter, WriteSingle, 64, vec![50, 0])
+        );
+        assert_eq!(
+            unwrap_inverter_channeldata_packet(to_inverter.recv().await?),
+            packet(&inverter, WriteSingle, 64, vec![10, 0])
+        );
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(tf, sf).unwrap();
+}
+
+#[tokio::test]
+async fn refuses_registers_past_the_end() {
+    common_setup();
+
+    let inverter = Factory::inverter();
+    let channels = Channels::new();
+
+    let subject = Transaction::new(channels.clone(), inverter.clone())
+        .set(65534u16, 1)
+        .set(65535u16, 2);
+
+    let err = subject.run().await.unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<coordinator::safeguards::WriteRejected>()
+            .map(ToString::to_string),
+        Some("write rejected: 2 registers from 65534 go past the last register".to_owned())
+    );
+}
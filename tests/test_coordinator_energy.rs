@@ -0,0 +1,178 @@
+mod common;
+use common::*;
+
+use lxp_bridge::coordinator::energy::EnergyStore;
+
+const MAX_POWER: u32 = 30000;
+
+// the same inputs, `seconds` later
+fn later(input: &lxp::packet::ReadInputAll, seconds: i64) -> lxp::packet::ReadInputAll {
+    let mut input = input.clone();
+    input.time = UnixTime(input.time.0 + chrono::Duration::seconds(seconds));
+    input
+}
+
+#[test]
+fn first_update_starts_from_lifetime_counters() {
+    common_setup();
+
+    let mut store = EnergyStore::new();
+    let totals = store.update(&Factory::read_input_all(), MAX_POWER);
+
+    assert_eq!(totals.e_pv_total, 4215.8);
+    assert_eq!(totals.e_pv_interval, 0.0);
+    assert_eq!(totals.e_to_user_total, 5889.8);
+    assert_eq!(totals.e_to_user_interval, 0.0);
+}
+
+#[test]
+fn counts_increase_in_lifetime_counters() {
+    common_setup();
+
+    let mut store = EnergyStore::new();
+    let input = Factory::read_input_all();
+    store.update(&input, MAX_POWER);
+
+    let mut input = later(&input, 300);
+    input.e_pv_all += 0.5;
+    input.e_pv_day += 0.5;
+    let totals = store.update(&input, MAX_POWER);
+
+    assert_eq!(totals.e_pv_total, 4216.3);
+    assert_eq!(totals.e_pv_interval, 0.5);
+    assert_eq!(totals.e_inv_interval, 0.0);
+}
+
+#[test]
+fn uses_day_counter_when_lifetime_counter_jumps() {
+    common_setup();
+
+    let mut store = EnergyStore::new();
+    let input = Factory::read_input_all();
+    store.update(&input, MAX_POWER);
+
+    let mut input = later(&input, 300);
+    input.e_inv_all += 1000.0;
+    input.e_inv_day += 0.3;
+    let totals = store.update(&input, MAX_POWER);
+
+    assert_eq!(totals.e_inv_total, 3249.4);
+    assert_eq!(totals.e_inv_interval, 0.3);
+}
+
+#[test]
+fn handles_day_counter_reset() {
+    common_setup();
+
+    let mut store = EnergyStore::new();
+    let input = Factory::read_input_all();
+    store.update(&input, MAX_POWER);
+
+    // midnight, and the lifetime counter has gone backwards too
+    let mut input = later(&input, 300);
+    input.e_dischg_all = 0.0;
+    input.e_dischg_day = 0.2;
+    let totals = store.update(&input, MAX_POWER);
+
+    assert_eq!(totals.e_dischg_total, 4092.9);
+    assert_eq!(totals.e_dischg_interval, 0.2);
+}
+
+#[test]
+fn ignores_implausible_readings_and_rebaselines() {
+    common_setup();
+
+    let mut store = EnergyStore::new();
+    let input = Factory::read_input_all();
+    store.update(&input, MAX_POWER);
+
+    let mut input = later(&input, 300);
+    input.e_chg_all += 500.0;
+    input.e_chg_day += 500.0;
+    let totals = store.update(&input, MAX_POWER);
+
+    assert_eq!(totals.e_chg_total, 4392.6);
+    assert_eq!(totals.e_chg_interval, 0.0);
+
+    // later readings count from the new baseline
+    let mut input = later(&input, 300);
+    input.e_chg_all += 0.4;
+    input.e_chg_day += 0.4;
+    let totals = store.update(&input, MAX_POWER);
+
+    assert_eq!(totals.e_chg_total, 4393.0);
+    assert_eq!(totals.e_chg_interval, 0.4);
+}
+
+#[test]
+fn tracks_inverters_separately() {
+    common_setup();
+
+    let mut store = EnergyStore::new();
+    let input = Factory::read_input_all();
+    store.update(&input, MAX_POWER);
+
+    let mut other = later(&input, 300);
+    other.datalog = Serial::from_str("2222222222").unwrap();
+    other.e_pv_all = 100.0;
+    let totals = store.update(&other, MAX_POWER);
+
+    assert_eq!(totals.e_pv_total, 100.0);
+    assert_eq!(totals.e_pv_interval, 0.0);
+}
+
+#[test]
+fn fields_excludes_datalog_and_time() {
+    common_setup();
+
+    let mut store = EnergyStore::new();
+    let fields = store
+        .update(&Factory::read_input_all(), MAX_POWER)
+        .fields()
+        .unwrap();
+
+    assert_eq!(fields.len(), 16);
+    assert_eq!(fields["e_rec_total"], 3919.5);
+    assert!(!fields.contains_key("datalog"));
+}
+
+#[test]
+fn carries_on_from_saved_counters_after_restart() {
+    common_setup();
+
+    let file = std::env::temp_dir().join("lxp-bridge-test-energy.json");
+    let file = file.to_str().unwrap();
+
+    let mut store = EnergyStore::new();
+    let input = Factory::read_input_all();
+    store.update(&input, MAX_POWER);
+
+    // leaves the total behind the lifetime counter
+    let mut input = later(&input, 300);
+    input.e_inv_all += 1000.0;
+    input.e_inv_day += 0.3;
+    store.update(&input, MAX_POWER);
+    store.save(file).unwrap();
+
+    let mut store = EnergyStore::load(file).unwrap();
+    std::fs::remove_file(file).unwrap();
+
+    let mut input = later(&input, 3600);
+    input.e_inv_all += 0.2;
+    input.e_inv_day += 0.2;
+    let totals = store.update(&input, MAX_POWER);
+
+    assert_eq!(totals.e_inv_total, 3249.6);
+    assert_eq!(totals.e_inv_interval, 0.2);
+}
+
+#[test]
+fn loads_nothing_without_a_saved_file() {
+    common_setup();
+
+    let file = std::env::temp_dir().join("lxp-bridge-test-energy-missing.json");
+    let mut store = EnergyStore::load(file.to_str().unwrap()).unwrap();
+
+    let totals = store.update(&Factory::read_input_all(), MAX_POWER);
+    assert_eq!(totals.e_pv_total, 4215.8);
+}
@@ -0,0 +1,112 @@
+mod common;
+use common::*;
+
+use lxp::packet::{DeviceFunction, TranslatedData};
+use lxp_bridge::coordinator::hold_cache::HoldCache;
+use std::time::Duration;
+
+const MAX_AGE: Duration = Duration::from_secs(300);
+
+fn reply(device_function: DeviceFunction, register: u16, values: Vec<u8>) -> TranslatedData {
+    let inverter = Factory::inverter();
+
+    TranslatedData {
+        datalog: inverter.datalog(),
+        device_function,
+        inverter: inverter.serial(),
+        register,
+        values,
+    }
+}
+
+#[test]
+fn read_hold_fills_cache() {
+    common_setup();
+
+    let datalog = Factory::inverter().datalog();
+    let mut cache = HoldCache::new();
+
+    assert_eq!(cache.get(datalog, 68, MAX_AGE), None);
+
+    cache.update(&reply(DeviceFunction::ReadHold, 68, vec![1, 2, 3, 4]));
+
+    assert_eq!(cache.get(datalog, 68, MAX_AGE), Some(513));
+    assert_eq!(
+        cache.get_range(datalog, 68, 2, MAX_AGE),
+        Some(vec![513, 1027])
+    );
+    // only part of the range is known
+    assert_eq!(cache.get_range(datalog, 68, 3, MAX_AGE), None);
+}
+
+#[test]
+fn write_single_updates_cache() {
+    common_setup();
+
+    let datalog = Factory::inverter().datalog();
+    let mut cache = HoldCache::new();
+
+    cache.update(&reply(DeviceFunction::ReadHold, 21, vec![2, 0]));
+    cache.update(&reply(DeviceFunction::WriteSingle, 21, vec![130, 0]));
+
+    assert_eq!(cache.get(datalog, 21, MAX_AGE), Some(130));
+}
+
+#[test]
+fn write_multi_invalidates_registers() {
+    common_setup();
+
+    let datalog = Factory::inverter().datalog();
+    let mut cache = HoldCache::new();
+
+    cache.update(&reply(
+        DeviceFunction::ReadHold,
+        12,
+        vec![22, 6, 7, 1, 0, 0],
+    ));
+    // reply says two registers were written, starting at 12
+    cache.update(&reply(DeviceFunction::WriteMulti, 12, vec![2, 0]));
+
+    assert_eq!(cache.get(datalog, 12, MAX_AGE), None);
+    assert_eq!(cache.get(datalog, 13, MAX_AGE), None);
+    assert_eq!(cache.get(datalog, 14, MAX_AGE), Some(0));
+}
+
+#[test]
+fn read_input_ignored() {
+    common_setup();
+
+    let datalog = Factory::inverter().datalog();
+    let mut cache = HoldCache::new();
+
+    cache.update(&reply(DeviceFunction::ReadInput, 0, vec![1, 0]));
+
+    assert_eq!(cache.get(datalog, 0, MAX_AGE), None);
+}
+
+#[test]
+fn stale_values_not_returned() {
+    common_setup();
+
+    let datalog = Factory::inverter().datalog();
+    let mut cache = HoldCache::new();
+
+    cache.update(&reply(DeviceFunction::ReadHold, 21, vec![2, 0]));
+    std::thread::sleep(Duration::from_millis(5));
+
+    assert_eq!(cache.get(datalog, 21, Duration::ZERO), None);
+    assert_eq!(cache.get(datalog, 21, MAX_AGE), Some(2));
+}
+
+#[test]
+fn clear_forgets_inverter() {
+    common_setup();
+
+    let datalog = Factory::inverter().datalog();
+    let mut cache = HoldCache::new();
+
+    cache.update(&reply(DeviceFunction::ReadHold, 21, vec![2, 0]));
+    cache.clear(datalog);
+
+    assert_eq!(cache.get(datalog, 21, MAX_AGE), None);
+}
@@ -0,0 +1,75 @@
+mod common;
+use common::*;
+
+use lxp_bridge::coordinator::metrics::HouseholdMetrics;
+
+#[test]
+fn discharging_in_the_evening() {
+    common_setup();
+
+    let metrics = HouseholdMetrics::new(&Factory::read_input_all());
+
+    assert_eq!(metrics.p_consumption, 722);
+    assert_eq!(metrics.p_pv_to_battery, 0);
+    assert_eq!(metrics.p_pv_to_load, 0);
+    assert_eq!(metrics.p_grid_to_battery, 0);
+    assert_eq!(metrics.e_consumption_day, 6.7);
+    assert_eq!(metrics.self_sufficiency_pct, Some(52.2));
+    // more discharged than charged today; clamped rather than over 100%
+    assert_eq!(metrics.battery_efficiency_pct, Some(100.0));
+}
+
+#[test]
+fn pv_charging_and_exporting() {
+    common_setup();
+
+    let mut input = Factory::read_input_all();
+    input.p_pv = 3000;
+    input.p_charge = 1000;
+    input.p_inv = 2000;
+    input.p_to_grid = 500;
+    input.e_pv_day = 10.0;
+    input.e_to_grid_day = 2.0;
+
+    let metrics = HouseholdMetrics::new(&input);
+
+    assert_eq!(metrics.p_consumption, 1500);
+    assert_eq!(metrics.p_pv_to_battery, 1000);
+    assert_eq!(metrics.p_pv_to_load, 1500);
+    assert_eq!(metrics.p_grid_to_battery, 0);
+    assert_eq!(metrics.self_consumption_pct, Some(80.0));
+}
+
+#[test]
+fn ac_charging_from_grid() {
+    common_setup();
+
+    let mut input = Factory::read_input_all();
+    input.p_charge = 2000;
+    input.p_rec = 2100;
+    input.p_inv = 0;
+    input.p_to_grid = 0;
+    input.p_to_user = 2500;
+
+    let metrics = HouseholdMetrics::new(&input);
+
+    assert_eq!(metrics.p_consumption, 400);
+    assert_eq!(metrics.p_pv_to_battery, 0);
+    assert_eq!(metrics.p_grid_to_battery, 2000);
+}
+
+#[test]
+fn percentages_omitted_without_data() {
+    common_setup();
+
+    let mut input = Factory::read_input_all();
+    input.e_chg_day = 0.0;
+
+    let metrics = HouseholdMetrics::new(&input);
+    assert_eq!(metrics.self_consumption_pct, None);
+    assert_eq!(metrics.battery_efficiency_pct, None);
+
+    let fields = metrics.fields().unwrap();
+    assert!(!fields.contains_key("self_consumption_pct"));
+    assert_eq!(fields["p_consumption"], 722);
+}
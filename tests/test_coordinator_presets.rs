@@ -0,0 +1,91 @@
+mod common;
+use common::*;
+
+use lxp_bridge::coordinator::presets::{Change, Preset};
+
+fn entries(yaml: &str) -> serde_yaml::Mapping {
+    serde_yaml::from_str(yaml).unwrap()
+}
+
+#[test]
+fn resolves_registers_and_bits() {
+    common_setup();
+
+    let preset = Preset::new(
+        "storm",
+        &entries("AcChargeSocLimit: 100\n66: 50\nac_charge: on\neco_mode_enable: false\n"),
+    )
+    .unwrap();
+
+    assert_eq!(preset.name, "storm");
+    assert_eq!(
+        preset.changes,
+        vec![
+            (
+                "AcChargeSocLimit".to_owned(),
+                Change::Hold {
+                    register: 67,
+                    value: 100
+                }
+            ),
+            (
+                "66".to_owned(),
+                Change::Hold {
+                    register: 66,
+                    value: 50
+                }
+            ),
+            (
+                "ac_charge".to_owned(),
+                Change::Bits {
+                    register: 21,
+                    set: 1 << 7,
+                    clear: 0
+                }
+            ),
+            (
+                "eco_mode_enable".to_owned(),
+                Change::Bits {
+                    register: 110,
+                    set: 0,
+                    clear: lxp::packet::find_register_bit(110, "eco_mode_enable").unwrap()
+                }
+            ),
+        ]
+    );
+}
+
+#[test]
+fn rejects_bad_entries() {
+    common_setup();
+
+    assert_eq!(
+        Preset::new("x", &entries("nonsense: 1"))
+            .unwrap_err()
+            .to_string(),
+        "preset x: nonsense: unknown register or bit"
+    );
+    assert!(Preset::new("x", &entries("ac_charge: [1]")).is_err());
+    assert!(Preset::new("x", &entries("AcChargeSocLimit: high")).is_err());
+}
+
+#[test]
+fn matches() {
+    common_setup();
+
+    let preset = Preset::new("storm", &entries("AcChargeSocLimit: 100\nac_charge: on")).unwrap();
+
+    let live = |soc_limit: u16, register21: u16| {
+        move |register| match register {
+            67 => Some(soc_limit),
+            21 => Some(register21),
+            _ => None,
+        }
+    };
+
+    assert!(preset.matches(live(100, 130)));
+    assert!(!preset.matches(live(90, 130)));
+    assert!(!preset.matches(live(100, 2)));
+    // unknown registers don't match
+    assert!(!preset.matches(|_| None));
+}
@@ -0,0 +1,123 @@
+mod common;
+use common::*;
+
+use lxp_bridge::coordinator::safeguards::{self, WriteRejected};
+
+fn rejection(result: Result<()>) -> WriteRejected {
+    result
+        .unwrap_err()
+        .downcast::<WriteRejected>()
+        .expect("should be a WriteRejected")
+}
+
+#[test]
+fn allows_writes_by_default() {
+    common_setup();
+
+    let inverter = Factory::inverter();
+    assert!(safeguards::check_hold_write(false, &inverter, 64, Some(50)).is_ok());
+    assert!(safeguards::check_hold_write(false, &inverter, 21, None).is_ok());
+    assert!(safeguards::check_param_write(false, &inverter).is_ok());
+}
+
+#[test]
+fn read_only() {
+    common_setup();
+
+    let mut inverter = Factory::inverter();
+    assert_eq!(
+        rejection(safeguards::check_hold_write(true, &inverter, 64, Some(50))),
+        WriteRejected("2222222222 is read-only".to_owned())
+    );
+
+    inverter.read_only = Some(true);
+    assert!(safeguards::check_hold_write(false, &inverter, 64, Some(50)).is_err());
+    assert!(safeguards::check_param_write(false, &inverter).is_err());
+}
+
+#[test]
+fn protected_registers() {
+    common_setup();
+
+    let mut inverter = Factory::inverter();
+    inverter.protected_registers = Some(vec![11, 225]);
+
+    assert_eq!(
+        rejection(safeguards::check_hold_write(false, &inverter, 225, Some(1))),
+        WriteRejected("register 225 is not writable on 2222222222".to_owned())
+    );
+    assert!(safeguards::check_hold_write(false, &inverter, 64, Some(1)).is_ok());
+}
+
+#[test]
+fn register_limits() {
+    common_setup();
+
+    let inverter = Factory::inverter();
+
+    assert_eq!(
+        rejection(safeguards::check_hold_write(false, &inverter, 67, Some(101))),
+        WriteRejected("value 101 out of range for register 67 (0-100)".to_owned())
+    );
+    assert!(safeguards::check_hold_write(false, &inverter, 67, Some(100)).is_ok());
+    assert!(safeguards::check_hold_write(false, &inverter, 140, Some(1000)).is_ok());
+    // no limits known for this one
+    assert!(safeguards::check_hold_write(false, &inverter, 5, Some(65535)).is_ok());
+
+    // grid protection points, in 0.1V and 0.01Hz
+    assert_eq!(
+        rejection(safeguards::check_hold_write(false, &inverter, 30, Some(9999))),
+        WriteRejected("value 9999 out of range for register 30 (800-3000)".to_owned())
+    );
+    assert!(safeguards::check_hold_write(false, &inverter, 30, Some(2640)).is_ok());
+    assert!(safeguards::check_hold_write(false, &inverter, 43, Some(0)).is_err());
+    assert!(safeguards::check_hold_write(false, &inverter, 43, Some(5150)).is_ok());
+}
+
+#[test]
+fn register_limits_step() {
+    common_setup();
+
+    let limits = lxp::packet::RegisterLimits {
+        min: 10,
+        max: 100,
+        step: 5,
+    };
+    assert!(limits.check(67, 15).is_ok());
+    assert_eq!(
+        limits.check(67, 16).unwrap_err().to_string(),
+        "value 16 for register 67 must be a multiple of 5"
+    );
+
+    // no step, rather than dividing by zero
+    let limits = lxp::packet::RegisterLimits { step: 0, ..limits };
+    assert!(limits.check(67, 16).is_ok());
+    assert!(limits.check(67, 101).is_err());
+}
+
+#[test]
+fn protects_reboot_and_lcd_password_by_default() {
+    common_setup();
+
+    let inverter = Factory::inverter();
+
+    assert_eq!(
+        rejection(safeguards::check_hold_write(false, &inverter, 11, Some(1))),
+        WriteRejected("register 11 is not writable on 2222222222".to_owned())
+    );
+    assert!(safeguards::check_hold_write(false, &inverter, 225, Some(1234)).is_err());
+}
+
+#[test]
+fn raw_hold_value() {
+    common_setup();
+
+    assert_eq!(safeguards::raw_hold_value(64, 50.0).unwrap(), 50);
+    // GenRatePower is in 0.1kW
+    assert_eq!(safeguards::raw_hold_value(177, 5.5).unwrap(), 55);
+
+    assert!(safeguards::raw_hold_value(64, 50.5).is_err());
+    assert!(safeguards::raw_hold_value(177, 5.55).is_err());
+    assert!(safeguards::raw_hold_value(64, -1.0).is_err());
+    assert!(safeguards::raw_hold_value(64, 70000.0).is_err());
+}
@@ -0,0 +1,234 @@
+mod common;
+use common::*;
+
+use lxp_bridge::coordinator::safeguards::WriteRejected;
+use lxp_bridge::coordinator::schedule::{Mode, Schedule, Slot};
+use std::collections::HashMap;
+
+fn time(hour: u8, minute: u8) -> u16 {
+    u16::from_le_bytes([hour, minute])
+}
+
+fn slot(start: &str, end: &str) -> Slot {
+    Slot {
+        start: start.parse().unwrap(),
+        end: end.parse().unwrap(),
+    }
+}
+
+// the schedule held in the registers, with anything not given as 0
+fn live(values: &[(u16, u16)]) -> Schedule {
+    let values: HashMap<u16, u16> = values.iter().copied().collect();
+    Schedule::from_registers(|register| Some(values.get(&register).copied().unwrap_or(0))).unwrap()
+}
+
+fn schedule(json: &str) -> Schedule {
+    serde_json::from_str(json).unwrap()
+}
+
+fn rejection(schedule: &Schedule, current: &Schedule) -> String {
+    let err = schedule.applied_to(current).unwrap_err();
+    assert!(err.downcast_ref::<WriteRejected>().is_some());
+    err.to_string()
+}
+
+#[test]
+fn reads_registers() {
+    common_setup();
+
+    let schedule = live(&[
+        (21, 1 << 7),
+        (66, 50),
+        (67, 90),
+        (68, time(23, 30)),
+        (69, time(23, 59)),
+        (152, time(7, 0)),
+        (153, time(9, 15)),
+    ]);
+
+    assert_eq!(
+        schedule.ac_charge,
+        Some(Mode {
+            enabled: Some(true),
+            slots: Some(vec![
+                slot("23:30", "23:59"),
+                Slot::default(),
+                Slot::default()
+            ]),
+            rate_pct: Some(50),
+            soc_limit_pct: Some(90),
+        })
+    );
+    assert_eq!(
+        serde_json::to_string(&schedule.ac_first).unwrap(),
+        r#"{"slots":[{"start":"07:00","end":"09:15"},{"start":"00:00","end":"00:00"},{"start":"00:00","end":"00:00"}]}"#
+    );
+    assert_eq!(schedule.forced_discharge.unwrap().enabled, Some(false));
+}
+
+#[test]
+fn missing_register() {
+    common_setup();
+
+    assert!(Schedule::from_registers(|register| (register != 75).then_some(0)).is_err());
+}
+
+#[test]
+fn applies_over_current() {
+    common_setup();
+
+    let current = live(&[
+        (21, 1 << 10),
+        (66, 50),
+        (67, 90),
+        (84, time(16, 0)),
+        (85, time(19, 0)),
+    ]);
+
+    let wanted = schedule(
+        r#"{"ac_charge":{"enabled":true,"slots":[{"start":"00:30","end":"04:30"}],"soc_limit_pct":100}}"#,
+    )
+    .applied_to(&current)
+    .unwrap();
+
+    let ac_charge = wanted.ac_charge.as_ref().unwrap();
+    assert_eq!(ac_charge.enabled, Some(true));
+    assert_eq!(ac_charge.rate_pct, Some(50)); // left as it was
+    assert_eq!(ac_charge.soc_limit_pct, Some(100));
+    assert_eq!(wanted.forced_discharge, current.forced_discharge);
+
+    let values = wanted.register_values(1 << 10);
+    assert_eq!(values[&21], (1 << 10) | (1 << 7));
+    assert_eq!(values[&67], 100);
+    assert_eq!(values[&68], time(0, 30));
+    assert_eq!(values[&69], time(4, 30));
+    // the slots not given are cleared
+    assert_eq!(values[&70], 0);
+    assert_eq!(values[&73], 0);
+    assert_eq!(values[&84], time(16, 0));
+}
+
+#[test]
+fn enable_bits_unchanged() {
+    common_setup();
+
+    let current = live(&[(21, 1 << 7)]);
+    let wanted = schedule(r#"{"ac_charge":{"enabled":true}}"#)
+        .applied_to(&current)
+        .unwrap();
+
+    assert!(!wanted.register_values(1 << 7).contains_key(&21));
+}
+
+#[test]
+fn rejects_bad_times() {
+    common_setup();
+
+    let current = live(&[]);
+
+    assert_eq!(
+        rejection(
+            &schedule(r#"{"ac_charge":{"slots":[{"start":"01:60","end":"02:00"}]}}"#),
+            &current
+        ),
+        "write rejected: ac_charge slot 01:60-02:00 is not a valid time range"
+    );
+    assert_eq!(
+        rejection(
+            &schedule(r#"{"ac_first":{"slots":[{"start":"24:00","end":"02:00"}]}}"#),
+            &current
+        ),
+        "write rejected: ac_first slot 24:00-02:00 is not a valid time range"
+    );
+    assert_eq!(
+        rejection(
+            &schedule(r#"{"charge_priority":{"slots":[{"start":"05:00","end":"02:00"}]}}"#),
+            &current
+        ),
+        "write rejected: charge_priority slot 05:00-02:00 ends before it starts"
+    );
+
+    assert!(serde_json::from_str::<Schedule>(
+        r#"{"ac_charge":{"slots":[{"start":"0100","end":"02:00"}]}}"#
+    )
+    .is_err());
+}
+
+#[test]
+fn rejects_overlaps() {
+    common_setup();
+
+    let current = live(&[(68, time(1, 0)), (69, time(4, 0))]);
+
+    assert_eq!(
+        rejection(
+            &schedule(
+                r#"{"forced_discharge":{"slots":[{"start":"16:00","end":"19:00"},{"start":"18:30","end":"20:00"}]}}"#
+            ),
+            &current
+        ),
+        "write rejected: forced_discharge slots 16:00-19:00 and 18:30-20:00 overlap"
+    );
+
+    // against the ac_charge slot already on the inverter
+    assert_eq!(
+        rejection(
+            &schedule(r#"{"forced_discharge":{"slots":[{"start":"03:00","end":"05:00"}]}}"#),
+            &current
+        ),
+        "write rejected: forced_discharge slot 03:00-05:00 overlaps ac_charge slot 01:00-04:00"
+    );
+
+    // slots which only touch are fine
+    assert!(
+        schedule(r#"{"forced_discharge":{"slots":[{"start":"04:00","end":"05:00"}]}}"#)
+            .applied_to(&current)
+            .is_ok()
+    );
+}
+
+#[test]
+fn ignores_existing_overlaps_not_being_changed() {
+    common_setup();
+
+    let current = live(&[
+        (68, time(1, 0)),
+        (69, time(4, 0)),
+        (84, time(3, 0)),
+        (85, time(5, 0)),
+    ]);
+
+    assert!(schedule(r#"{"ac_first":{"slots":[]}}"#)
+        .applied_to(&current)
+        .is_ok());
+}
+
+#[test]
+fn rejects_unsupported_settings() {
+    common_setup();
+
+    let current = live(&[]);
+
+    assert_eq!(
+        rejection(&schedule(r#"{"ac_first":{"rate_pct":50}}"#), &current),
+        "write rejected: ac_first has no rate"
+    );
+    assert_eq!(
+        rejection(
+            &schedule(r#"{"ac_charge":{"soc_limit_pct":101}}"#),
+            &current
+        ),
+        "write rejected: ac_charge.soc_limit_pct 101 is over 100"
+    );
+    assert_eq!(
+        rejection(
+            &schedule(
+                r#"{"ac_charge":{"slots":[{"start":"01:00","end":"02:00"},{"start":"03:00","end":"04:00"},{"start":"05:00","end":"06:00"},{"start":"07:00","end":"08:00"}]}}"#
+            ),
+            &current
+        ),
+        "write rejected: ac_charge has 4 slots, the most is 3"
+    );
+
+    assert!(serde_json::from_str::<Schedule>(r#"{"ac_charge":{"rate":50}}"#).is_err());
+}
@@ -0,0 +1,146 @@
+mod common;
+use common::*;
+
+use lxp_bridge::coordinator::write_limiter::{Reply, WriteLimiter, WriteSkipped};
+use tokio::time::{Duration, Instant};
+
+fn inverter(write_interval: Option<u64>) -> config::Inverter {
+    config::Inverter {
+        write_interval,
+        ..Factory::inverter()
+    }
+}
+
+#[test]
+fn no_limit_by_default() {
+    common_setup();
+
+    let inverter = inverter(None);
+    let mut limiter = WriteLimiter::new();
+    let now = Instant::now();
+
+    limiter.written(inverter.datalog(), 64, now);
+    assert_eq!(limiter.defer(&inverter, 64, 50, now), None);
+    assert_eq!(limiter.next_due(), None);
+}
+
+#[test]
+fn first_write_goes_now() {
+    common_setup();
+
+    let inverter = inverter(Some(60));
+    let mut limiter = WriteLimiter::new();
+
+    assert_eq!(limiter.defer(&inverter, 64, 50, Instant::now()), None);
+}
+
+#[test]
+fn coalesces_writes_within_interval() {
+    common_setup();
+
+    let inverter = inverter(Some(60));
+    let mut limiter = WriteLimiter::new();
+    let now = Instant::now();
+
+    limiter.written(inverter.datalog(), 64, now);
+
+    assert_eq!(
+        limiter.defer(&inverter, 64, 50, now + Duration::from_secs(10)),
+        Some(WriteSkipped::Coalesced {
+            register: 64,
+            value: 50,
+            delay_secs: 50,
+            replaced: None
+        })
+    );
+    // last value wins
+    let skipped = limiter
+        .defer(&inverter, 64, 40, now + Duration::from_secs(20))
+        .unwrap();
+    assert_eq!(
+        skipped.to_string(),
+        "coalesced, register 64 will be set to 40 in 40s instead of 50"
+    );
+    // other registers aren't held up
+    assert_eq!(
+        limiter.defer(&inverter, 65, 40, now + Duration::from_secs(20)),
+        None
+    );
+
+    let due = now + Duration::from_secs(60);
+    assert_eq!(limiter.next_due(), Some(due));
+    assert!(limiter.due(due - Duration::from_secs(1)).is_empty());
+
+    let pending = limiter.due(due);
+    assert_eq!(pending.len(), 1);
+    assert_eq!((pending[0].register, pending[0].value), (64, 40));
+    assert_eq!(limiter.next_due(), None);
+}
+
+#[test]
+fn write_after_interval_replaces_pending() {
+    common_setup();
+
+    let inverter = inverter(Some(60));
+    let mut limiter = WriteLimiter::new();
+    let now = Instant::now();
+
+    limiter.written(inverter.datalog(), 64, now);
+    assert!(limiter
+        .defer(&inverter, 64, 50, now + Duration::from_secs(10))
+        .is_some());
+
+    // goes straight out, so the pending one mustn't follow it
+    assert_eq!(
+        limiter.defer(&inverter, 64, 40, now + Duration::from_secs(60)),
+        None
+    );
+    assert_eq!(limiter.next_due(), None);
+}
+
+#[test]
+fn cancels_pending() {
+    common_setup();
+
+    let inverter = inverter(Some(60));
+    let mut limiter = WriteLimiter::new();
+    let now = Instant::now();
+
+    limiter.written(inverter.datalog(), 64, now);
+    limiter.defer(&inverter, 64, 50, now);
+
+    assert_eq!(limiter.cancel(inverter.datalog(), 64).unwrap().value, 50);
+    assert_eq!(limiter.next_due(), None);
+}
+
+#[test]
+fn replaced_writes_keep_their_replies() {
+    common_setup();
+
+    let inverter = inverter(Some(60));
+    let mut limiter = WriteLimiter::new();
+    let now = Instant::now();
+    let reply = |id: &str| Reply {
+        topic: "result/2222222222/set/charge_rate_pct".to_owned(),
+        envelope: Some(mqtt::CommandEnvelope {
+            id: Some(id.into()),
+            ..Default::default()
+        }),
+    };
+
+    limiter.written(inverter.datalog(), 64, now);
+    assert_eq!(
+        limiter.wait(&inverter, 64, now + Duration::from_secs(10)),
+        Some(Duration::from_secs(50))
+    );
+    assert_eq!(limiter.wait(&inverter, 65, now), None);
+
+    limiter.defer(&inverter, 64, 50, now);
+    limiter.reply_to(inverter.datalog(), 64, reply("a"));
+    limiter.defer(&inverter, 64, 40, now);
+    limiter.reply_to(inverter.datalog(), 64, reply("b"));
+    assert_eq!(limiter.pending_value(inverter.datalog(), 64), Some(40));
+
+    let pending = limiter.due(now + Duration::from_secs(60));
+    assert_eq!(pending[0].replies, vec![reply("a"), reply("b")]);
+}
@@ -84,3 +84,127 @@ async fn sqlite_insertion() {
 
     futures::try_join!(database.start(), tf).unwrap();
 }
+
+#[tokio::test]
+async fn sqlite_alarm_event_insertion() {
+    common_setup();
+
+    let config = config::Database {
+        enabled: true,
+        url: "sqlite::memory:".to_string(),
+    };
+    let channels = Channels::new();
+
+    let database = Database::new(config, channels.clone());
+
+    let tf = async {
+        let event = coordinator::alarms::AlarmEvent {
+            datalog: Serial::from_str("1234567890").unwrap(),
+            kind: coordinator::alarms::AlarmKind::Fault,
+            state: coordinator::alarms::AlarmState::Raised,
+            bit: 3,
+            code: "E003",
+            description: "CT Fail",
+            time: UnixTime::now(),
+        };
+        let channel_data = database::ChannelData::AlarmEvent(event);
+
+        let mut retries = 0;
+        // wait up to 5 seconds for database to be ready and accepting messages
+        while channels.to_database.send(channel_data.clone()).is_err() {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            retries = retries + 1;
+            if retries > 50 {
+                panic!("database not ready for messages");
+            }
+        }
+
+        database.stop();
+
+        let mut conn = database.connection().await?;
+
+        let mut retries = 0;
+        loop {
+            let mut rows = sqlx::query("SELECT * FROM alarm_events").fetch(&mut conn);
+            if let Some(row) = rows.try_next().await? {
+                assert_str_eq(row.get("kind"), "fault");
+                assert_str_eq(row.get("state"), "raised");
+                assert_i32_eq(row.get("bit"), 3);
+                assert_str_eq(row.get("code"), "E003");
+                assert_str_eq(row.get("description"), "CT Fail");
+                assert_str_eq(row.get("datalog"), "1234567890");
+                break;
+            }
+
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            retries = retries + 1;
+
+            if retries > 50 {
+                panic!("row not inserted");
+            }
+        }
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(database.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn sqlite_inputs2_insertion() {
+    common_setup();
+
+    let config = config::Database {
+        enabled: true,
+        url: "sqlite::memory:".to_string(),
+    };
+    let channels = Channels::new();
+
+    let database = Database::new(config, channels.clone());
+
+    let tf = async {
+        let channel_data =
+            database::ChannelData::ReadInputAll2(Box::new(Factory::read_input_all2()));
+
+        let mut retries = 0;
+        // wait up to 5 seconds for database to be ready and accepting messages
+        while channels.to_database.send(channel_data.clone()).is_err() {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            retries = retries + 1;
+            if retries > 50 {
+                panic!("database not ready for messages");
+            }
+        }
+
+        database.stop();
+
+        let mut conn = database.connection().await?;
+
+        let mut retries = 0;
+        loop {
+            let mut rows = sqlx::query("SELECT * FROM inputs2").fetch(&mut conn);
+            if let Some(row) = rows.try_next().await? {
+                let ria2 = Factory::read_input_all2();
+                assert_f64_eq(row.get("v_eps_l1"), ria2.v_eps_l1);
+                assert_u16_eq(row.get("p_eps_l2"), ria2.p_eps_l2);
+                assert_u16_eq(row.get("p_load"), ria2.p_load);
+                assert_f64_eq(row.get("e_load_all"), ria2.e_load_all);
+                assert_f64_eq(row.get("v_grid_l2"), ria2.v_grid_l2);
+                assert_i16_eq(row.get("p_inv_l1"), ria2.p_inv_l1);
+                assert_str_eq(row.get("datalog"), "1234567890");
+                break;
+            }
+
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            retries = retries + 1;
+
+            if retries > 50 {
+                panic!("row not inserted");
+            }
+        }
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(database.start(), tf).unwrap();
+}
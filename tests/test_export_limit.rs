@@ -0,0 +1,145 @@
+mod common;
+use common::*;
+
+use lxp_bridge::export_limit::{Action, Controller};
+
+fn config(gain: Option<f64>) -> config::ExportLimit {
+    config::ExportLimit {
+        enabled: true,
+        datalog: Serial::from_str("2222222222").unwrap(),
+        limit: 1000,
+        register: None,
+        gain,
+        deadband: None,
+        max_power: Some(4000),
+        min_pct: Some(10),
+        max_pct: None,
+        min_interval: None,
+    }
+}
+
+#[test]
+fn waits_for_register_value() {
+    common_setup();
+
+    let mut controller = Controller::new(config(None));
+
+    let state = controller.update(2000, 0);
+    assert_eq!(state.action, Action::Waiting);
+    assert_eq!(state.pct, None);
+    assert_eq!(state.register, 82);
+}
+
+#[test]
+fn holds_within_deadband() {
+    common_setup();
+
+    let mut controller = Controller::new(config(None));
+    controller.observe(50);
+
+    for p_to_grid in [800, 900, 1000] {
+        let state = controller.update(p_to_grid, 0);
+        assert_eq!(state.action, Action::InBand);
+        assert_eq!(state.pct, Some(50));
+        assert_eq!(state.wanted_pct, None);
+    }
+}
+
+#[test]
+fn corrects_towards_middle_of_band() {
+    common_setup();
+
+    let mut controller = Controller::new(config(None));
+    controller.observe(50);
+
+    // 1100W over 900W, halved, is 13.75% of 4000W
+    let state = controller.update(2000, 0);
+    assert_eq!(state.action, Action::Write);
+    assert_eq!(state.wanted_pct, Some(36));
+    // not until the inverter says so
+    assert_eq!(state.pct, Some(50));
+    controller.observe(36);
+
+    // 900W under
+    let state = controller.update(0, 100);
+    assert_eq!(state.action, Action::Write);
+    assert_eq!(state.pct, Some(36));
+    assert_eq!(state.wanted_pct, Some(48));
+}
+
+#[test]
+fn rate_limits_writes() {
+    common_setup();
+
+    let mut controller = Controller::new(config(None));
+    controller.observe(50);
+
+    assert_eq!(controller.update(2000, 0).action, Action::Write);
+    controller.observe(36);
+
+    let state = controller.update(2000, 29);
+    assert_eq!(state.action, Action::RateLimited);
+    assert_eq!(state.pct, Some(36));
+    assert_eq!(state.wanted_pct, Some(22));
+
+    let state = controller.update(2000, 30);
+    assert_eq!(state.action, Action::Write);
+    assert_eq!(state.wanted_pct, Some(22));
+}
+
+#[test]
+fn stays_within_min_and_max() {
+    common_setup();
+
+    let mut controller = Controller::new(config(None));
+    controller.observe(15);
+
+    let state = controller.update(4000, 0);
+    assert_eq!(state.wanted_pct, Some(10));
+    controller.observe(10);
+    assert_eq!(controller.update(4000, 100).action, Action::AtLimit);
+
+    controller.observe(100);
+    assert_eq!(controller.update(0, 200).action, Action::AtLimit);
+}
+
+#[test]
+fn moves_at_least_one_percent() {
+    common_setup();
+
+    let mut controller = Controller::new(config(Some(0.01)));
+    controller.observe(50);
+
+    assert_eq!(controller.update(1001, 0).wanted_pct, Some(49));
+}
+
+#[test]
+fn retries_unconfirmed_write() {
+    common_setup();
+
+    let mut controller = Controller::new(config(None));
+    controller.observe(50);
+
+    assert_eq!(controller.update(2000, 0).action, Action::Write);
+
+    // the write never showed up, so once min_interval is up it goes again
+    let state = controller.update(2000, 30);
+    assert_eq!(state.action, Action::Write);
+    assert_eq!(state.pct, Some(50));
+    assert_eq!(state.wanted_pct, Some(36));
+}
+
+#[test]
+fn waits_again_after_unknown_write() {
+    common_setup();
+
+    let mut controller = Controller::new(config(None));
+    controller.observe(50);
+
+    // eg a preset wrote the register, and we don't know what with
+    controller.forget();
+
+    let state = controller.update(2000, 0);
+    assert_eq!(state.action, Action::Waiting);
+    assert_eq!(state.pct, None);
+}
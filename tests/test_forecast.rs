@@ -0,0 +1,124 @@
+mod common;
+use common::*;
+
+use chrono::{Duration, NaiveDate, TimeZone, Utc};
+use lxp_bridge::forecast::{parse_forecast, pv_energy, ConsumptionHistory, Target};
+
+fn config() -> config::Forecast {
+    config::Forecast {
+        enabled: true,
+        datalog: Serial::from_str("2222222222").unwrap(),
+        source: "forecast.json".to_owned(),
+        cron: "0 23 * * *".to_owned(),
+        consumption: None,
+        history_days: None,
+        min_soc: None,
+        max_soc: None,
+        margin_pct: None,
+        battery_voltage: None,
+    }
+}
+
+fn date(day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2022, 3, day).unwrap()
+}
+
+#[test]
+fn parses_forecast() {
+    common_setup();
+
+    let periods = parse_forecast(
+        r#"{"forecasts":[{"pv_estimate":2.0,"pv_estimate10":1.0,"period_end":"2022-03-04T12:00:00Z","period":"PT30M"},{"pv_estimate":4.0,"period_end":"2022-03-04T13:00:00Z","period":"PT1H"}]}"#,
+    )
+    .unwrap();
+
+    assert_eq!(periods.len(), 2);
+    assert_eq!(
+        periods[0].period_end,
+        Utc.with_ymd_and_hms(2022, 3, 4, 12, 0, 0).unwrap()
+    );
+    assert_eq!(periods[0].period, Duration::minutes(30));
+    assert_eq!(periods[1].period, Duration::hours(1));
+
+    // a bare list, and period defaults to half an hour
+    let periods =
+        parse_forecast(r#"[{"pv_estimate":2.0,"period_end":"2022-03-04T12:00:00Z"}]"#).unwrap();
+    assert_eq!(periods[0].period, Duration::minutes(30));
+
+    assert!(parse_forecast(
+        r#"[{"pv_estimate":2.0,"period_end":"2022-03-04T12:00:00Z","period":"P1D"}]"#
+    )
+    .is_err());
+}
+
+#[test]
+fn totals_pv_energy() {
+    common_setup();
+
+    let periods = parse_forecast(
+        r#"[{"pv_estimate":2.0,"period_end":"2022-03-04T12:00:00Z"},{"pv_estimate":4.0,"period_end":"2022-03-04T13:00:00Z","period":"PT1H"},{"pv_estimate":3.0,"period_end":"2022-03-05T12:00:00Z","period":"PT1H"}]"#,
+    )
+    .unwrap();
+
+    let at = |hour, minute| Utc.with_ymd_and_hms(2022, 3, 4, hour, minute, 0).unwrap();
+
+    // 1kWh + 4kWh; the last period is outside
+    assert_eq!(pv_energy(&periods, at(11, 0), at(14, 0)), 5.0);
+    // half of the hour long period
+    assert_eq!(pv_energy(&periods, at(12, 30), at(14, 0)), 2.0);
+    assert_eq!(pv_energy(&periods, at(13, 0), at(14, 0)), 0.0);
+}
+
+#[test]
+fn averages_complete_days() {
+    common_setup();
+
+    let mut history = ConsumptionHistory::new();
+    assert_eq!(history.average(date(4), 7), None);
+
+    // today doesn't count until it's over
+    history.record(date(4), 3.0);
+    assert_eq!(history.average(date(4), 7), None);
+
+    history.record(date(1), 8.0);
+    history.record(date(2), 10.0);
+    history.record(date(3), 12.0);
+    assert_eq!(history.average(date(4), 7), Some((10.0, 3)));
+    assert_eq!(history.average(date(4), 2), Some((11.0, 2)));
+
+    // the last value seen for a day is its total
+    history.record(date(4), 14.0);
+    assert_eq!(history.average(date(5), 2), Some((13.0, 2)));
+}
+
+#[test]
+fn charges_for_shortfall() {
+    common_setup();
+
+    // 10kWh use, 4kWh PV, 10% margin: 6.6kWh of a 10kWh battery
+    let target = Target::new(&config(), 4.0, 10.0, 3, 10.0);
+    assert_eq!(target.shortfall_kwh, 6.6);
+    assert_eq!(target.soc, 76);
+    assert_eq!(target.consumption_days, 3);
+    assert_eq!(
+        target.reason,
+        "4kWh PV forecast leaves 6.6kWh of 10kWh expected use to cover (with 10% margin), charging to 76%"
+    );
+
+    // no more than max_soc
+    let target = Target::new(&config(), 0.0, 20.0, 0, 10.0);
+    assert_eq!(target.soc, 100);
+}
+
+#[test]
+fn sunny_day_needs_only_min_soc() {
+    common_setup();
+
+    let target = Target::new(&config(), 25.0, 10.0, 0, 10.0);
+    assert_eq!(target.shortfall_kwh, 0.0);
+    assert_eq!(target.soc, 10);
+    assert_eq!(
+        target.reason,
+        "25kWh PV forecast covers 10kWh expected use, charging to 10%"
+    );
+}
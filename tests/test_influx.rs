@@ -34,3 +34,37 @@ async fn sends_http_request() {
 
     mock.assert();
 }
+
+#[tokio::test]
+async fn sends_events_measurement() {
+    common_setup();
+
+    let mut server = mockito::Server::new();
+    let mock = server
+        .mock("POST", "/write")
+        .match_query(Matcher::UrlEncoded("db".to_owned(), "lxp".to_owned()))
+        .with_status(204)
+        .match_body(
+            r#"events,code=E003,datalog=BA12345678,kind=fault,state=raised bit=3i,description="CT Fail" 1000000000"#,
+        )
+        .create();
+
+    let config = Factory::example_config_wrapped();
+    config.influx_mut().url = server.url();
+    let channels = Channels::new();
+
+    let influx = Influx::new(config, channels.clone());
+
+    let tf = async {
+        let json = json!({ "time": 1, "datalog": "BA12345678", "kind": "fault", "state": "raised", "bit": 3, "code": "E003", "description": "CT Fail" });
+        channels
+            .to_influx
+            .send(influx::ChannelData::EventData(json))?;
+        channels.to_influx.send(influx::ChannelData::Shutdown)?;
+        Ok(())
+    };
+
+    futures::try_join!(influx.start(), tf).unwrap();
+
+    mock.assert();
+}
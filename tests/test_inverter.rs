@@ -22,6 +22,15 @@ async fn serials_fixed_in_outgoing_packets() {
         heartbeats: None,
         publish_holdings_on_connect: None,
         read_timeout: None,
+        max_power: None,
+        hold_cache_max_age: None,
+        refresh_holdings_interval: None,
+        timezone: None,
+        time_tolerance: None,
+        write_interval: None,
+        read_only: None,
+        writable_registers: None,
+        protected_registers: None,
     };
     let channels = Channels::new();
     let inverter = lxp::inverter::Inverter::new(config, &inverter, channels.clone());
@@ -112,6 +121,15 @@ async fn test_replies_to_heartbeats() {
         heartbeats: Some(true),
         publish_holdings_on_connect: None,
         read_timeout: None,
+        max_power: None,
+        hold_cache_max_age: None,
+        refresh_holdings_interval: None,
+        timezone: None,
+        time_tolerance: None,
+        write_interval: None,
+        read_only: None,
+        writable_registers: None,
+        protected_registers: None,
     };
     let channels = Channels::new();
     let inverter = lxp::inverter::Inverter::new(config, &inverter, channels.clone());
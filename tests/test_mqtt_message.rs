@@ -129,6 +129,93 @@ async fn for_hold_110() {
     );
 }
 
+#[tokio::test]
+async fn for_hold_145() {
+    common_setup();
+
+    let inverter = Factory::inverter();
+
+    let packet = lxp::packet::TranslatedData {
+        datalog: inverter.datalog(),
+        device_function: lxp::packet::DeviceFunction::ReadHold,
+        inverter: inverter.serial(),
+        register: 145,
+        values: vec![1, 0],
+    };
+
+    assert_eq!(
+        mqtt::Message::for_hold(packet).unwrap(),
+        vec![mqtt::Message { topic: "2222222222/hold/145".to_owned(), retain: true, payload: "1.0".to_owned() },
+             mqtt::Message { topic: "2222222222/hold/145/parsed".to_owned(), retain: true, payload: "\"PV first\"".to_owned() }
+        ]
+    );
+}
+
+#[tokio::test]
+async fn for_hold_20() {
+    common_setup();
+
+    let inverter = Factory::inverter();
+
+    // meaning depends on inverter model, so no parsed topic
+    let packet = lxp::packet::TranslatedData {
+        datalog: inverter.datalog(),
+        device_function: lxp::packet::DeviceFunction::ReadHold,
+        inverter: inverter.serial(),
+        register: 20,
+        values: vec![3, 0],
+    };
+
+    assert_eq!(
+        mqtt::Message::for_hold(packet).unwrap(),
+        vec![mqtt::Message { topic: "2222222222/hold/20".to_owned(), retain: true, payload: "3.0".to_owned() }]
+    );
+}
+
+#[tokio::test]
+async fn for_hold_233() {
+    common_setup();
+
+    let inverter = Factory::inverter();
+
+    let packet = lxp::packet::TranslatedData {
+        datalog: inverter.datalog(),
+        device_function: lxp::packet::DeviceFunction::ReadHold,
+        inverter: inverter.serial(),
+        register: 233,
+        values: vec![10, 0],
+    };
+
+    assert_eq!(
+        mqtt::Message::for_hold(packet).unwrap(),
+        vec![mqtt::Message { topic: "2222222222/hold/233".to_owned(), retain: true, payload: "10.0".to_owned() },
+             mqtt::Message { topic: "2222222222/hold/233/bits".to_owned(), retain: true, payload: "{\"ub_quick_charge_start_en\":\"OFF\",\"ub_batt_backup_en\":\"ON\",\"ub_maintenance_en\":\"OFF\",\"ub_working_mode\":\"Work mode 2\"}".to_owned() }
+        ]
+    );
+}
+
+#[tokio::test]
+async fn for_hold_235() {
+    common_setup();
+
+    let inverter = Factory::inverter();
+
+    let packet = lxp::packet::TranslatedData {
+        datalog: inverter.datalog(),
+        device_function: lxp::packet::DeviceFunction::ReadHold,
+        inverter: inverter.serial(),
+        register: 235,
+        values: vec![3, 7],
+    };
+
+    assert_eq!(
+        mqtt::Message::for_hold(packet).unwrap(),
+        vec![mqtt::Message { topic: "2222222222/hold/235".to_owned(), retain: true, payload: "1795.0".to_owned() },
+             mqtt::Message { topic: "2222222222/hold/235/bits".to_owned(), retain: true, payload: "{\"no_full_charge_days\":3,\"no_full_charge_days_num_set\":7}".to_owned() }
+        ]
+    );
+}
+
 #[tokio::test]
 async fn for_hold_multi() {
     common_setup();
@@ -407,3 +494,303 @@ async fn for_input_ignore_127_254() {
         mqtt::Message { topic: "2222222222/input/v_grid_l2/parsed".to_owned(), retain: false, payload: "0.0".to_owned() }
     ]);
 }
+
+#[tokio::test]
+async fn to_command_set_bit() {
+    common_setup();
+
+    let message = mqtt::Message {
+        topic: "cmd/2222222222/set/bit/110/eco_mode_enable".to_owned(),
+        retain: false,
+        payload: "on".to_owned(),
+    };
+    let command = message.to_command(Factory::inverter()).unwrap();
+    assert!(matches!(command, Command::SetBit(_, 110, ref name, true) if name == "eco_mode_enable"));
+    assert_eq!(
+        command.to_result_topic(),
+        "result/2222222222/set/bit/110/eco_mode_enable"
+    );
+
+    let message = mqtt::Message {
+        topic: "cmd/2222222222/set/bits/179".to_owned(),
+        retain: false,
+        payload: r#"{"pv_sell_first": true, "grid_peak_shaving": "off"}"#.to_owned(),
+    };
+    let command = message.to_command(Factory::inverter()).unwrap();
+    if let Command::SetBits(_, 179, bits) = command {
+        assert_eq!(
+            bits,
+            vec![
+                ("grid_peak_shaving".to_owned(), false),
+                ("pv_sell_first".to_owned(), true)
+            ]
+        );
+    } else {
+        panic!("unexpected command {:?}", command);
+    }
+
+    let message = mqtt::Message {
+        topic: "cmd/2222222222/set/bits/179".to_owned(),
+        retain: false,
+        payload: r#"{"pv_sell_first": 5}"#.to_owned(),
+    };
+    assert!(message.to_command(Factory::inverter()).is_err());
+}
+
+#[test]
+fn finds_register_bits_by_name() {
+    use lxp::packet::{find_register_bit, Register120Bit, Register179Bit, Register21Bit};
+
+    assert_eq!(
+        find_register_bit(21, "feed_in_grid_enable").unwrap(),
+        u16::from(Register21Bit::FeedInGridEnable)
+    );
+    assert_eq!(
+        find_register_bit(120, "GenChargeType").unwrap(),
+        u16::from(Register120Bit::GenChargeType)
+    );
+    assert_eq!(
+        find_register_bit(179, "on_grid_always_on").unwrap(),
+        u16::from(Register179Bit::OnGridAlwaysOn)
+    );
+    assert_eq!(
+        find_register_bit(21, "nope").unwrap_err().to_string(),
+        "unknown bit nope for register 21"
+    );
+    assert!(find_register_bit(113, "clear_detected_phases").is_err());
+}
+
+#[tokio::test]
+async fn to_command_set_schedule() {
+    common_setup();
+
+    let message = mqtt::Message {
+        topic: "cmd/2222222222/set/schedule".to_owned(),
+        retain: false,
+        payload: r#"{"ac_charge":{"enabled":true,"slots":[{"start":"00:30","end":"04:30"}]}}"#
+            .to_owned(),
+    };
+    let command = message.to_command(Factory::inverter()).unwrap();
+    assert_eq!(command.to_result_topic(), "result/2222222222/set/schedule");
+    if let Command::SetSchedule(_, schedule) = command {
+        let ac_charge = schedule.ac_charge.unwrap();
+        assert_eq!(ac_charge.enabled, Some(true));
+        assert_eq!(ac_charge.slots.unwrap().len(), 1);
+        assert_eq!(schedule.forced_discharge, None);
+    } else {
+        panic!("unexpected command {:?}", command);
+    }
+
+    let message = mqtt::Message {
+        topic: "cmd/2222222222/set/schedule".to_owned(),
+        retain: false,
+        payload: r#"{"ac_charge":{"slots":[{"start":"0030"}]}}"#.to_owned(),
+    };
+    assert!(message.to_command(Factory::inverter()).is_err());
+}
+
+#[tokio::test]
+async fn to_command_time() {
+    common_setup();
+
+    let message = mqtt::Message {
+        topic: "cmd/2222222222/read/time".to_owned(),
+        retain: false,
+        payload: "".to_owned(),
+    };
+    let command = message.to_command(Factory::inverter()).unwrap();
+    assert_eq!(command.to_result_topic(), "result/2222222222/read/time");
+    assert!(matches!(command, Command::ReadTime(_)));
+
+    let message = mqtt::Message {
+        topic: "cmd/2222222222/set/time".to_owned(),
+        retain: false,
+        payload: "".to_owned(),
+    };
+    let command = message.to_command(Factory::inverter()).unwrap();
+    assert_eq!(command.to_result_topic(), "result/2222222222/set/time");
+    assert!(matches!(command, Command::SetTime(_, None)));
+
+    for payload in ["2024-03-31T01:30:00", "2024-03-31 01:30:00"] {
+        let message = mqtt::Message {
+            topic: "cmd/2222222222/set/time".to_owned(),
+            retain: false,
+            payload: payload.to_owned(),
+        };
+        if let Command::SetTime(_, Some(time)) = message.to_command(Factory::inverter()).unwrap() {
+            assert_eq!(time.to_string(), "2024-03-31 01:30:00");
+        } else {
+            panic!("expected SetTime for {}", payload);
+        }
+    }
+
+    let message = mqtt::Message {
+        topic: "cmd/2222222222/set/time".to_owned(),
+        retain: false,
+        payload: "01:30".to_owned(),
+    };
+    assert!(message.to_command(Factory::inverter()).is_err());
+}
+
+#[tokio::test]
+async fn to_command_read_batch() {
+    use lxp_bridge::coordinator::batch::{Range, Space};
+
+    common_setup();
+
+    let message = mqtt::Message {
+        topic: "cmd/2222222222/read/batch".to_owned(),
+        retain: false,
+        payload: r#"[{"space":"hold","register":0,"count":80},{"type":"param","register":7}]"#
+            .to_owned(),
+    };
+    let command = message.to_command(Factory::inverter()).unwrap();
+    assert_eq!(command.to_result_topic(), "result/2222222222/read/batch");
+    if let Command::ReadBatch(_, ranges) = command {
+        assert_eq!(
+            ranges,
+            vec![
+                Range {
+                    space: Space::Hold,
+                    register: 0,
+                    count: 80
+                },
+                Range {
+                    space: Space::Param,
+                    register: 7,
+                    count: 1
+                }
+            ]
+        );
+    } else {
+        panic!("expected ReadBatch");
+    }
+
+    for payload in [
+        "",
+        "[]",
+        r#"{"space":"hold","register":0}"#,
+        r#"[{"space":"coil","register":0}]"#,
+        r#"[{"space":"hold","register":0,"count":0}]"#,
+        r#"[{"space":"hold","register":65535,"count":2}]"#,
+    ] {
+        let message = mqtt::Message {
+            topic: "cmd/2222222222/read/batch".to_owned(),
+            retain: false,
+            payload: payload.to_owned(),
+        };
+        assert!(
+            message.to_command(Factory::inverter()).is_err(),
+            "{}",
+            payload
+        );
+    }
+}
+
+#[tokio::test]
+async fn split_envelope() {
+    common_setup();
+
+    let message = mqtt::Message {
+        topic: "cmd/2222222222/set/ac_charge/1".to_owned(),
+        retain: false,
+        payload: r#"{"id": 42, "value": {"start": "20:00", "end": "21:00"}}"#.to_owned(),
+    };
+    let (message, envelope) = message.split_envelope();
+    assert_eq!(envelope.unwrap().id, Some(json!(42)));
+    let command = message.to_command(Factory::inverter()).unwrap();
+    assert!(matches!(
+        command,
+        Command::SetAcChargeTime(_, 1, [20, 0, 21, 0])
+    ));
+
+    let message = mqtt::Message {
+        topic: "cmd/2222222222/set/ac_charge".to_owned(),
+        retain: false,
+        payload: r#"{"value": "on"}"#.to_owned(),
+    };
+    let (message, envelope) = message.split_envelope();
+    assert_eq!(envelope, Some(mqtt::CommandEnvelope::default()));
+    assert_eq!(message.payload, "on");
+
+    // commands which take no value can still have an id
+    let message = mqtt::Message {
+        topic: "cmd/2222222222/read/time".to_owned(),
+        retain: false,
+        payload: r#"{"id": "x"}"#.to_owned(),
+    };
+    let (message, envelope) = message.split_envelope();
+    let envelope = envelope.unwrap();
+    assert_eq!(envelope.id, Some(json!("x")));
+    assert!(!envelope.missing_value);
+    assert_eq!(message.payload, "");
+
+    // but others need one, rather than running with an empty payload
+    for topic in [
+        "cmd/2222222222/set/ac_charge",
+        "cmd/2222222222/set/hold/66",
+        "cmd/2222222222/read/batch",
+    ] {
+        let message = mqtt::Message {
+            topic: topic.to_owned(),
+            retain: false,
+            payload: r#"{"id": "x"}"#.to_owned(),
+        };
+        let (_, envelope) = message.split_envelope();
+        assert!(envelope.unwrap().missing_value, "{}", topic);
+    }
+
+    // payloads which aren't envelopes are left alone
+    for payload in [
+        r#"{"pv_sell_first": true}"#,
+        r#"{"value": true, "pv_sell_first": true}"#,
+        "{}",
+    ] {
+        let message = mqtt::Message {
+            topic: "cmd/2222222222/set/bits/179".to_owned(),
+            retain: false,
+            payload: payload.to_owned(),
+        };
+        let (split, envelope) = message.clone().split_envelope();
+        assert_eq!(envelope, None);
+        assert_eq!(split, message);
+    }
+}
+
+#[tokio::test]
+async fn to_result_topic() {
+    common_setup();
+
+    let message = mqtt::Message {
+        topic: "cmd/all/set/preset/storm".to_owned(),
+        retain: false,
+        payload: "".to_owned(),
+    };
+    assert_eq!(
+        message.to_result_topic(&Factory::inverter()).unwrap(),
+        "result/2222222222/set/preset/storm"
+    );
+}
+
+#[tokio::test]
+async fn command_envelope_result() {
+    common_setup();
+
+    let envelope = mqtt::CommandEnvelope {
+        id: Some(json!("abc")),
+        ..Default::default()
+    };
+    let duration = std::time::Duration::from_millis(150);
+
+    let result = envelope.result(&Ok(Some(json!(1558))), duration);
+    assert_eq!(
+        serde_json::to_value(result).unwrap(),
+        json!({"id": "abc", "status": "OK", "error": null, "value": 1558, "duration_ms": 150})
+    );
+
+    let result = envelope.result(&Err(anyhow!("timeout")), duration);
+    assert_eq!(
+        serde_json::to_value(result).unwrap(),
+        json!({"id": "abc", "status": "FAIL", "error": "timeout", "value": null, "duration_ms": 150})
+    );
+}
@@ -0,0 +1,101 @@
+mod common;
+use common::*;
+
+fn config(protocol: config::MqttProtocol, clean_session: bool) -> ConfigWrapper {
+    let config = Factory::example_config_wrapped();
+    {
+        let mut mqtt = config.mqtt_mut();
+        mqtt.protocol = protocol;
+        mqtt.client_id = Some("garage".to_owned());
+        mqtt.lwt_topic = Some("lxp/garage/LWT".to_owned());
+        mqtt.clean_session = Some(clean_session);
+    }
+    config
+}
+
+#[test]
+fn options_v4() {
+    common_setup();
+
+    let config = config(config::MqttProtocol::V4, false);
+    let options = Mqtt::new(config, Channels::new()).options().unwrap();
+
+    assert_eq!(options.client_id(), "garage");
+    assert!(!options.clean_session());
+    assert_eq!(options.last_will().unwrap().topic, "lxp/garage/LWT");
+}
+
+#[test]
+fn options_v5() {
+    common_setup();
+
+    let config = config(config::MqttProtocol::V5, false);
+    config.mqtt_mut().session_expiry = Some(60);
+    let options = Mqtt::new(config.clone(), Channels::new())
+        .options_v5()
+        .unwrap();
+
+    assert_eq!(options.client_id(), "garage");
+    assert!(!options.clean_start());
+    assert_eq!(options.last_will().unwrap().topic, "lxp/garage/LWT");
+    assert_eq!(
+        options
+            .connect_properties()
+            .unwrap()
+            .session_expiry_interval,
+        Some(60)
+    );
+
+    // a clean session isn't kept at all
+    config.mqtt_mut().clean_session = None;
+    let options = Mqtt::new(config, Channels::new()).options_v5().unwrap();
+    assert!(options.clean_start());
+    assert!(options.connect_properties().is_none());
+}
+
+#[test]
+fn persistent_session_needs_client_id() {
+    common_setup();
+
+    let config = config(config::MqttProtocol::V4, false);
+    config.mqtt_mut().client_id = Some("".to_owned());
+
+    assert_eq!(
+        Mqtt::new(config, Channels::new())
+            .options()
+            .unwrap_err()
+            .to_string(),
+        "mqtt: clean_session: false needs a client_id"
+    );
+}
+
+#[test]
+fn persistent_session_needs_client_id_v5() {
+    common_setup();
+
+    let config = config(config::MqttProtocol::V5, false);
+    config.mqtt_mut().client_id = Some("".to_owned());
+
+    assert_eq!(
+        Mqtt::new(config, Channels::new())
+            .options_v5()
+            .unwrap_err()
+            .to_string(),
+        "mqtt: clean_session: false needs a client_id"
+    );
+}
+
+#[test]
+fn home_assistant_availability() {
+    common_setup();
+
+    let config = config(config::MqttProtocol::V4, true);
+    let inverter = config.inverters()[0].clone();
+    let ha = home_assistant::Config::new(&inverter, &config.mqtt());
+
+    for msg in ha.all().unwrap() {
+        assert!(msg
+            .payload
+            .contains(r#""availability":{"topic":"lxp/garage/LWT"}"#));
+    }
+}
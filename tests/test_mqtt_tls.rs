@@ -0,0 +1,165 @@
+mod common;
+use common::*;
+
+use rumqttc::{AsyncClient, Event, Incoming};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const FIXTURES: &str = "tests/fixtures/tls";
+
+fn fixture(name: &str) -> String {
+    format!("{}/{}", FIXTURES, name)
+}
+
+fn tls(ca_file: Option<&str>, client_auth: bool) -> config::MqttTls {
+    config::MqttTls {
+        enabled: true,
+        ca_file: ca_file.map(fixture),
+        cert_file: client_auth.then(|| fixture("client.pem")),
+        key_file: client_auth.then(|| fixture("client.key")),
+        alpn: vec![],
+        insecure_skip_verify: false,
+    }
+}
+
+// A broker which only speaks TLS, as far as accepting one connection and
+// saying yes to its CONNECT. Returns the port it listens on.
+async fn broker(require_client_cert: bool) -> u16 {
+    use rumqttc::tokio_rustls::{rustls, TlsAcceptor};
+
+    let reader = |name: &str| std::io::BufReader::new(std::fs::File::open(fixture(name)).unwrap());
+    let certs = |name: &str| {
+        rustls_pemfile::certs(&mut reader(name))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    };
+    let key = rustls_pemfile::private_key(&mut reader("server.key"))
+        .unwrap()
+        .unwrap();
+
+    let builder = rustls::ServerConfig::builder();
+    let builder = if require_client_cert {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(certs("ca.pem").remove(0)).unwrap();
+        let verifier = rustls::server::WebPkiClientVerifier::builder(Arc::new(roots))
+            .build()
+            .unwrap();
+        builder.with_client_cert_verifier(verifier)
+    } else {
+        builder.with_no_client_auth()
+    };
+    let config = builder.with_single_cert(certs("server.pem"), key).unwrap();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await?;
+        let mut stream = acceptor.accept(stream).await?;
+
+        let mut connect = [0; 1024];
+        let _ = stream.read(&mut connect).await?;
+        // CONNACK, session not present, accepted
+        stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await?;
+        stream.flush().await?;
+
+        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+
+        Ok::<(), anyhow::Error>(())
+    });
+
+    port
+}
+
+// what the bridge's MQTT client makes of connecting to the broker
+async fn connect(port: u16, tls: config::MqttTls) -> Result<Event> {
+    let config = Factory::example_config_wrapped();
+    {
+        let mut mqtt = config.mqtt_mut();
+        mqtt.host = "localhost".to_owned();
+        mqtt.port = port;
+        mqtt.tls = Some(tls);
+    }
+
+    let options = Mqtt::new(config, Channels::new()).options()?;
+    let (_client, mut eventloop) = AsyncClient::new(options, 10);
+
+    let event = tokio::time::timeout(std::time::Duration::from_secs(10), eventloop.poll()).await?;
+
+    Ok(event?)
+}
+
+#[test]
+fn builds_client_config() {
+    common_setup();
+
+    let mut tls = tls(Some("ca.pem"), true);
+    tls.alpn = vec!["mqtt".to_owned()];
+
+    let config = mqtt::tls_client_config(&tls).unwrap();
+    assert_eq!(config.alpn_protocols, vec![b"mqtt".to_vec()]);
+}
+
+#[test]
+fn bad_client_config() {
+    common_setup();
+
+    let mut missing = tls(Some("missing.pem"), false);
+    assert!(mqtt::tls_client_config(&missing).is_err());
+
+    // a key with no certificate
+    missing.ca_file = Some(fixture("ca.pem"));
+    missing.key_file = Some(fixture("client.key"));
+    assert_eq!(
+        mqtt::tls_client_config(&missing).unwrap_err().to_string(),
+        "mqtt.tls: cert_file and key_file must be given together"
+    );
+
+    // a certificate where the key should be
+    let mut swapped = tls(Some("ca.pem"), true);
+    swapped.key_file = Some(fixture("client.pem"));
+    assert!(mqtt::tls_client_config(&swapped).is_err());
+}
+
+#[tokio::test]
+async fn connects_with_client_certificate() {
+    common_setup();
+
+    let port = broker(true).await;
+
+    let event = connect(port, tls(Some("ca.pem"), true)).await.unwrap();
+    assert!(matches!(event, Event::Incoming(Incoming::ConnAck(_))));
+}
+
+#[tokio::test]
+async fn refused_without_client_certificate() {
+    common_setup();
+
+    let port = broker(true).await;
+
+    assert!(connect(port, tls(Some("ca.pem"), false)).await.is_err());
+}
+
+#[tokio::test]
+async fn rejects_untrusted_broker() {
+    common_setup();
+
+    // the client certificate isn't a CA, so can't vouch for the broker
+    let port = broker(false).await;
+
+    assert!(connect(port, tls(Some("client.pem"), false)).await.is_err());
+}
+
+#[tokio::test]
+async fn insecure_skip_verify_trusts_any_broker() {
+    common_setup();
+
+    let port = broker(false).await;
+
+    let mut tls = tls(None, false);
+    tls.insecure_skip_verify = true;
+
+    let event = connect(port, tls).await.unwrap();
+    assert!(matches!(event, Event::Incoming(Incoming::ConnAck(_))));
+}
@@ -0,0 +1,263 @@
+mod common;
+use common::*;
+
+use bytes::{Bytes, BytesMut};
+use rumqttc::v5::mqttbytes::v5::{
+    ConnAck, ConnectReturnCode, Packet, PubAck, Publish, PublishProperties,
+};
+use rumqttc::v5::mqttbytes::QoS;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+fn config(protocol: config::MqttProtocol) -> ConfigWrapper {
+    let config = Factory::example_config_wrapped();
+    config.mqtt_mut().protocol = protocol;
+    config
+}
+
+fn message(topic: &str, retain: bool) -> mqtt::Message {
+    mqtt::Message {
+        topic: topic.to_owned(),
+        retain,
+        payload: "1".to_owned(),
+    }
+}
+
+fn reply(correlation_data: &'static [u8]) -> mqtt::Reply {
+    mqtt::Reply {
+        response_topic: "replies/me".to_owned(),
+        correlation_data: Some(Bytes::from_static(correlation_data)),
+    }
+}
+
+#[test]
+fn no_properties_for_v4() {
+    common_setup();
+
+    let mqtt = Mqtt::new(config(config::MqttProtocol::V4), Channels::new());
+
+    assert_eq!(
+        mqtt.publish_properties(&message("2222222222/inputs/all", false)),
+        None
+    );
+}
+
+#[test]
+fn publish_properties() {
+    common_setup();
+
+    let config = config(config::MqttProtocol::V5);
+    let mqtt = Mqtt::new(config.clone(), Channels::new());
+
+    let inverter = vec![
+        ("datalog".to_owned(), "2222222222".to_owned()),
+        ("serial".to_owned(), "5555555555".to_owned()),
+    ];
+
+    // readings expire
+    let properties = mqtt
+        .publish_properties(&message("2222222222/inputs/all", false))
+        .unwrap();
+    assert_eq!(properties.user_properties, inverter);
+    assert_eq!(properties.message_expiry_interval, Some(300));
+
+    let properties = mqtt
+        .publish_properties(&message("2222222222/input/soc/parsed", false))
+        .unwrap();
+    assert_eq!(properties.message_expiry_interval, Some(300));
+
+    // retained settings don't, nor do results
+    let properties = mqtt
+        .publish_properties(&message("2222222222/hold/21", true))
+        .unwrap();
+    assert_eq!(properties.user_properties, inverter);
+    assert_eq!(properties.message_expiry_interval, None);
+
+    let properties = mqtt
+        .publish_properties(&message("result/2222222222/read/hold/21", false))
+        .unwrap();
+    assert_eq!(properties.user_properties, inverter);
+    assert_eq!(properties.message_expiry_interval, None);
+
+    // nothing to say about topics which aren't for an inverter
+    let properties = mqtt
+        .publish_properties(&message("scheduler/night_charge_on/next_run", true))
+        .unwrap();
+    assert_eq!(properties, PublishProperties::default());
+
+    config.mqtt_mut().message_expiry = Some(0);
+    let properties = mqtt
+        .publish_properties(&message("2222222222/inputs/all", false))
+        .unwrap();
+    assert_eq!(properties.message_expiry_interval, None);
+}
+
+async fn read_packet(stream: &mut tokio::net::TcpStream, buf: &mut BytesMut) -> Result<Packet> {
+    loop {
+        match Packet::read(buf, None) {
+            Ok(packet) => return Ok(packet),
+            Err(rumqttc::v5::mqttbytes::Error::InsufficientBytes(_)) => {
+                if stream.read_buf(buf).await? == 0 {
+                    bail!("connection closed");
+                }
+            }
+            Err(err) => bail!("{:?}", err),
+        }
+    }
+}
+
+async fn write_packet(stream: &mut tokio::net::TcpStream, packet: Packet) -> Result<()> {
+    let mut buf = BytesMut::new();
+    packet.write(&mut buf)?;
+    stream.write_all(&buf).await?;
+
+    Ok(())
+}
+
+// Accepts one v5 client and sends it a command with a response topic. Returns
+// the port it listens on, and everything the client publishes.
+async fn broker() -> Result<(u16, tokio::sync::mpsc::UnboundedReceiver<Publish>)> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+
+    tokio::spawn(serve(listener, tx));
+
+    Ok((port, rx))
+}
+
+async fn serve(
+    listener: tokio::net::TcpListener,
+    tx: tokio::sync::mpsc::UnboundedSender<Publish>,
+) -> Result<()> {
+    let (mut stream, _) = listener.accept().await?;
+    let mut buf = BytesMut::new();
+
+    match read_packet(&mut stream, &mut buf).await? {
+        Packet::Connect(..) => {}
+        packet => bail!("expected CONNECT, got {:?}", packet),
+    }
+    let connack = ConnAck {
+        session_present: false,
+        code: ConnectReturnCode::Success,
+        properties: None,
+    };
+    write_packet(&mut stream, Packet::ConnAck(connack)).await?;
+
+    let properties = PublishProperties {
+        response_topic: Some("replies/me".to_owned()),
+        correlation_data: Some(Bytes::from_static(b"42")),
+        ..Default::default()
+    };
+    let command = Publish::new(
+        "lxp/cmd/2222222222/read/hold/21",
+        QoS::AtMostOnce,
+        "",
+        Some(properties),
+    );
+    write_packet(&mut stream, Packet::Publish(command)).await?;
+
+    loop {
+        if let Packet::Publish(publish) = read_packet(&mut stream, &mut buf).await? {
+            if publish.qos == QoS::AtLeastOnce {
+                let puback = PubAck::new(publish.pkid, None);
+                write_packet(&mut stream, Packet::PubAck(puback)).await?;
+            }
+            tx.send(publish)?;
+        }
+    }
+}
+
+#[tokio::test]
+async fn replies_to_response_topic() {
+    common_setup();
+
+    let (port, mut published) = broker().await.unwrap();
+
+    let config = config(config::MqttProtocol::V5);
+    {
+        let mut mqtt = config.mqtt_mut();
+        mqtt.host = "127.0.0.1".to_owned();
+        mqtt.port = port;
+        mqtt.homeassistant.enabled = false;
+    }
+
+    let channels = Channels::new();
+    let mqtt = Mqtt::new(config, channels.clone());
+
+    let tf = async {
+        let mut from_mqtt = channels.from_mqtt.subscribe();
+
+        // the reply comes along with the command
+        assert_eq!(
+            from_mqtt.recv().await?,
+            mqtt::ChannelData::WithReply(
+                mqtt::Message {
+                    topic: "cmd/2222222222/read/hold/21".to_owned(),
+                    retain: false,
+                    payload: "".to_owned(),
+                },
+                reply(b"42")
+            )
+        );
+
+        // an unrelated result to the same topic isn't sent to it
+        channels
+            .to_mqtt
+            .send(mqtt::ChannelData::Message(mqtt::Message {
+                topic: "result/2222222222/read/hold/21".to_owned(),
+                retain: false,
+                payload: "FAIL".to_owned(),
+            }))?;
+
+        // as the coordinator would answer it
+        channels.to_mqtt.send(mqtt::ChannelData::WithReply(
+            mqtt::Message {
+                topic: "result/2222222222/read/hold/21".to_owned(),
+                retain: false,
+                payload: "OK".to_owned(),
+            },
+            reply(b"42"),
+        ))?;
+
+        let mut replies = Vec::new();
+        while replies.len() < 3 {
+            let publish = tokio::time::timeout(Duration::from_secs(5), published.recv())
+                .await?
+                .unwrap();
+            if !publish.topic.ends_with("/LWT") {
+                replies.push(publish);
+            }
+        }
+
+        let unrelated = &replies[0];
+        assert_eq!(unrelated.topic, "lxp/result/2222222222/read/hold/21");
+        assert_eq!(unrelated.payload, "FAIL");
+
+        let response = &replies[1];
+        assert_eq!(response.topic, "replies/me");
+        assert_eq!(response.payload, "OK");
+        let properties = response.properties.as_ref().unwrap();
+        assert_eq!(properties.correlation_data, Some(Bytes::from_static(b"42")));
+        assert_eq!(
+            properties.user_properties,
+            vec![
+                ("datalog".to_owned(), "2222222222".to_owned()),
+                ("serial".to_owned(), "5555555555".to_owned()),
+            ]
+        );
+
+        // and the usual result topic still gets it
+        let result = &replies[2];
+        assert_eq!(result.topic, "lxp/result/2222222222/read/hold/21");
+        assert_eq!(result.payload, "OK");
+        assert_eq!(result.properties.as_ref().unwrap().correlation_data, None);
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    tokio::select! {
+        r = mqtt.start() => panic!("mqtt exited: {:?}", r),
+        r = tf => r.unwrap(),
+    }
+}
@@ -18,3 +18,32 @@ async fn handles_missing_read_input() {
     read_inputs.set_read_input_3(Factory::read_input_3());
     assert_eq!(read_inputs.to_input_all(), None);
 }
+
+#[tokio::test]
+#[cfg_attr(not(feature = "mocks"), ignore)]
+async fn combines_read_input_all2() {
+    let mut read_inputs = lxp::packet::ReadInputs::default();
+    let input = Factory::read_input_all();
+
+    // without a ReadInputAll2 we just get ReadInputAll back
+    assert_eq!(
+        read_inputs.combined(&input).unwrap(),
+        serde_json::to_value(&input).unwrap()
+    );
+
+    read_inputs.set_read_input_all2(Box::new(Factory::read_input_all2()));
+    let combined = read_inputs.combined(&input).unwrap();
+    assert_eq!(combined["soc"], 55);
+    assert_eq!(combined["v_eps_l1"], 120.1);
+    assert_eq!(combined["p_load"], 450);
+    assert_eq!(combined["datalog"], "1234567890");
+    assert_eq!(combined["time"], 1646370367);
+
+    // a ReadInputAll2 left over from an earlier read isn't merged
+    let mut input2 = Factory::read_input_all2();
+    input2.time = UnixTime(input.time.0 - chrono::Duration::minutes(5));
+    read_inputs.set_read_input_all2(Box::new(input2));
+    let combined = read_inputs.combined(&input).unwrap();
+    assert_eq!(combined["soc"], 55);
+    assert_eq!(combined.get("v_eps_l1"), None);
+}
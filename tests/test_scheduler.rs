@@ -0,0 +1,49 @@
+mod common;
+use common::*;
+
+fn utc(s: &str) -> chrono::DateTime<chrono::Utc> {
+    chrono::DateTime::parse_from_rfc3339(s)
+        .unwrap()
+        .with_timezone(&chrono::Utc)
+}
+
+#[test]
+fn next_run_in_timezone() {
+    common_setup();
+
+    let now = utc("2024-06-01T12:00:00Z");
+
+    assert_eq!(
+        Scheduler::next_run("0 2 * * *", &chrono::Utc, now).unwrap(),
+        utc("2024-06-02T02:00:00Z")
+    );
+    // 02:00 BST
+    assert_eq!(
+        Scheduler::next_run("0 2 * * *", &chrono_tz::Europe::London, now).unwrap(),
+        utc("2024-06-02T01:00:00Z")
+    );
+    // 02:00 EDT, and already past 02:00 there today
+    assert_eq!(
+        Scheduler::next_run("0 2 * * *", &chrono_tz::America::New_York, now).unwrap(),
+        utc("2024-06-02T06:00:00Z")
+    );
+}
+
+#[test]
+fn next_run_across_dst_changes() {
+    common_setup();
+
+    let london = chrono_tz::Europe::London;
+
+    // 01:30 doesn't happen on the day the clocks go forward, so it runs an
+    // hour later, at 02:30 BST
+    assert_eq!(
+        Scheduler::next_run("30 1 * * *", &london, utc("2024-03-30T12:00:00Z")).unwrap(),
+        utc("2024-03-31T01:30:00Z")
+    );
+    // and happens twice when they go back; the first, in BST, counts
+    assert_eq!(
+        Scheduler::next_run("30 1 * * *", &london, utc("2024-10-26T12:00:00Z")).unwrap(),
+        utc("2024-10-27T00:30:00Z")
+    );
+}
@@ -0,0 +1,241 @@
+mod common;
+use common::*;
+
+use chrono::{DateTime, Duration, FixedOffset, TimeZone, Utc};
+use lxp_bridge::coordinator::schedule::Slot;
+use lxp_bridge::tariff::{parse_prices, Plan, Price};
+
+fn config(discharge_above: Option<f64>) -> config::Tariff {
+    config::Tariff {
+        enabled: true,
+        datalog: Serial::from_str("2222222222").unwrap(),
+        source: "prices.json".to_owned(),
+        target_soc: None,
+        charge_power: None,
+        discharge_above,
+        discharge_soc: None,
+        discharge_power: None,
+        battery_voltage: None,
+        interval: None,
+    }
+}
+
+fn midnight() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2022, 3, 4, 0, 0, 0).unwrap()
+}
+
+// half hour slots from midnight, in order
+fn prices(prices: &[f64]) -> Vec<Price> {
+    prices
+        .iter()
+        .enumerate()
+        .map(|(n, &price)| {
+            let start = midnight() + Duration::minutes(30 * n as i64);
+            Price {
+                start,
+                end: start + Duration::minutes(30),
+                price,
+            }
+        })
+        .collect()
+}
+
+fn slots(plan: &Plan, discharge: bool) -> Vec<String> {
+    let mode = if discharge {
+        plan.schedule.forced_discharge.as_ref()
+    } else {
+        plan.schedule.ac_charge.as_ref()
+    };
+    mode.unwrap()
+        .slots
+        .as_ref()
+        .unwrap()
+        .iter()
+        .map(Slot::to_string)
+        .collect()
+}
+
+#[test]
+fn parses_prices() {
+    common_setup();
+
+    let expected = vec![Price {
+        start: midnight(),
+        end: midnight() + Duration::minutes(30),
+        price: 12.5,
+    }];
+
+    assert_eq!(
+        parse_prices(
+            r#"[{"start":"2022-03-04T00:00:00Z","end":"2022-03-04T00:30:00Z","price":12.5}]"#
+        )
+        .unwrap(),
+        expected
+    );
+    assert_eq!(
+        parse_prices(
+            r#"{"prices":[{"start":"2022-03-04T01:00:00+01:00","end":"2022-03-04T01:30:00+01:00","price":12.5}]}"#
+        )
+        .unwrap(),
+        expected
+    );
+    // Octopus
+    assert_eq!(
+        parse_prices(
+            r#"{"count":1,"results":[{"value_exc_vat":11.9,"value_inc_vat":12.5,"valid_from":"2022-03-04T00:00:00Z","valid_to":"2022-03-04T00:30:00Z"}]}"#
+        )
+        .unwrap(),
+        expected
+    );
+
+    assert!(
+        parse_prices(r#"[{"start":"midnight","end":"2022-03-04T00:30:00Z","price":1}]"#).is_err()
+    );
+    assert!(parse_prices(r#"{"rates":[]}"#).is_err());
+}
+
+#[test]
+fn charges_in_cheapest_slots() {
+    common_setup();
+
+    let prices = prices(&[10.0, 5.0, 4.0, 12.0, 3.0, 20.0, 30.0, 25.0]);
+
+    // 5kWh needed, 1.5kWh per slot at 3kW
+    let plan = Plan::new(&config(None), &prices, midnight(), 50, 10.0);
+
+    assert_eq!(plan.charge_kwh, 5.0);
+    assert_eq!(
+        plan.charge.iter().map(|p| p.price).collect::<Vec<_>>(),
+        vec![10.0, 5.0, 4.0, 3.0]
+    );
+    assert_eq!(slots(&plan, false), vec!["00:00-01:30", "02:00-02:30"]);
+    assert_eq!(
+        plan.schedule.ac_charge.as_ref().unwrap().soc_limit_pct,
+        Some(100)
+    );
+    assert!(plan.discharge.is_empty());
+    assert_eq!(plan.schedule.forced_discharge, None);
+
+    // with discharge
+    let plan = Plan::new(&config(Some(20.0)), &prices, midnight(), 50, 10.0);
+
+    assert_eq!(
+        plan.discharge.iter().map(|p| p.price).collect::<Vec<_>>(),
+        vec![20.0, 30.0, 25.0]
+    );
+    assert_eq!(slots(&plan, true), vec!["02:30-04:00"]);
+    assert_eq!(
+        plan.schedule
+            .forced_discharge
+            .as_ref()
+            .unwrap()
+            .soc_limit_pct,
+        Some(20)
+    );
+}
+
+#[test]
+fn ignores_past_slots() {
+    common_setup();
+
+    let prices = prices(&[1.0, 10.0, 10.0]);
+    let plan = Plan::new(
+        &config(None),
+        &prices,
+        midnight() + Duration::minutes(30),
+        90,
+        10.0,
+    );
+
+    assert_eq!(plan.charge.len(), 1);
+    assert_eq!(slots(&plan, false), vec!["00:30-01:00"]);
+}
+
+#[test]
+fn nothing_to_charge() {
+    common_setup();
+
+    let plan = Plan::new(&config(None), &prices(&[1.0, 2.0]), midnight(), 100, 10.0);
+
+    assert_eq!(plan.charge_kwh, 0.0);
+    assert!(plan.charge.is_empty());
+    let ac_charge = plan.schedule.ac_charge.unwrap();
+    assert_eq!(ac_charge.enabled, Some(false));
+    assert_eq!(ac_charge.slots, Some(vec![]));
+}
+
+#[test]
+fn limits_to_three_windows() {
+    common_setup();
+
+    let prices = prices(&[1.0, 9.0, 1.0, 9.0, 1.0, 9.0, 9.0, 1.0]);
+
+    // the smallest gap is closed
+    let plan = Plan::new(&config(None), &prices, midnight(), 40, 10.0);
+    assert_eq!(
+        slots(&plan, false),
+        vec!["00:00-01:30", "02:00-02:30", "03:30-04:00"]
+    );
+
+    // unless that would charge while discharging, so a window is dropped
+    let plan = Plan::new(&config(Some(9.0)), &prices, midnight(), 40, 10.0);
+    assert_eq!(
+        slots(&plan, true),
+        vec!["00:30-01:00", "01:30-02:00", "02:30-03:30"]
+    );
+    assert_eq!(
+        slots(&plan, false),
+        vec!["01:00-01:30", "02:00-02:30", "03:30-04:00"]
+    );
+}
+
+#[test]
+fn uses_local_time_of_day() {
+    common_setup();
+
+    let tz = FixedOffset::east_opt(3600).unwrap();
+    let start = Utc.with_ymd_and_hms(2022, 3, 4, 22, 30, 0).unwrap();
+    let prices = vec![
+        Price {
+            start,
+            end: start + Duration::minutes(30),
+            price: 1.0,
+        },
+        Price {
+            start: start + Duration::minutes(30),
+            end: start + Duration::minutes(60),
+            price: 2.0,
+        },
+    ];
+
+    let plan = Plan::new(&config(None), &prices, start.with_timezone(&tz), 80, 10.0);
+
+    // 23:30-00:30 local, split at midnight
+    assert_eq!(slots(&plan, false), vec!["00:00-00:30", "23:30-23:59"]);
+}
+
+#[tokio::test]
+async fn fetches_prices() {
+    common_setup();
+
+    let mut server = mockito::Server::new();
+    let mock = server
+        .mock("GET", "/prices")
+        .with_status(200)
+        .with_body(r#"{"prices":[]}"#)
+        .create();
+
+    let body = Utils::fetch(&format!("{}/prices", server.url()))
+        .await
+        .unwrap();
+    assert_eq!(parse_prices(&body).unwrap(), vec![]);
+    mock.assert();
+
+    let mock = server.mock("GET", "/missing").with_status(404).create();
+    assert!(Utils::fetch(&format!("{}/missing", server.url()))
+        .await
+        .is_err());
+    mock.assert();
+
+    assert!(Utils::fetch("/nonexistent/prices.json").await.is_err());
+}